@@ -0,0 +1,759 @@
+// ウィンドウを開かずに記録・再生・ファイルの確認を行うコマンドラインツール。
+// ヘッドレスのキャプチャ用PCやCIでの利用を想定している。
+//
+// $ mru-cli record <file> [--bind 0.0.0.0] [--port 38013] [--per-device] [--compressed] [--meta session.json]
+//       [--split-minutes 10] [--split-mb 500] [--sync on_close|every_second|every_frame]
+//       [--vmc 127.0.0.1:39539] [--fingers] [--osc 127.0.0.1:9000] [--osc-prefix /mru] [--streams pose,...]
+//       [--ws 127.0.0.1:38014] [--relay 127.0.0.1:38013,192.168.0.10:38013]
+//       [--filter one_euro]
+// $ mru-cli play <file> [--target 127.0.0.1:38013] [--speed 1.0] [--loop] [--filter one_euro] [--resample 30]
+//       [--vmc 127.0.0.1:39539] [--fingers] [--osc 127.0.0.1:9000] [--osc-prefix /mru] [--streams pose,...]
+//       [--ws 127.0.0.1:38014] [--relay 127.0.0.1:38013,192.168.0.10:38013]
+// $ mru-cli info <file>
+// $ mru-cli validate <file>
+// $ mru-cli compress <file> <output>
+// $ mru-cli filter <file> <output> [--filter one_euro|ema|kalman|<json>] [--streams pose,face,...]
+// $ mru-cli resample <file> <output> [--fps 30]
+// $ mru-cli fill-gaps <file> <output> [--method interpolate|hold] [--max-gap-ms 300] [--min-visibility 0.5|none] [--streams pose,...]
+// $ mru-cli export-csv <file> <output.csv> [--long] [--streams pose,face,...]
+// $ mru-cli export-bvh <file> <output.bvh> [--fps 30] [--fingers] [--root-motion]
+// $ mru-cli export-gltf <file> <output.glb> [--fingers] [--root-motion]
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use tokio::sync::Mutex;
+
+use mru_core::compressed::CompressedWriter;
+use mru_core::devices::{DeviceRecordMode, DeviceTable};
+use mru_core::export::bvh::{export_bvh, BvhOptions};
+use mru_core::export::csv::{export_csv, CsvLayout, CsvOptions, CsvStream};
+use mru_core::export::gltf::{export_gltf, GltfOptions};
+use mru_core::filter::{write_filtered, FilterControl, FilterKind, FilterOptions};
+use mru_core::frames::{open_reader, LoadPolicy, LoadSummary};
+use mru_core::gapfill::{write_filled, GapFillMethod, GapFillOptions};
+use mru_core::holistic::LandmarkStream;
+use mru_core::metadata::SessionInfo;
+use mru_core::network::{socket_addr_string, UdpSink, DEFAULT_PORT};
+use mru_core::osc::{OscOptions, OscSink, OscStream};
+use mru_core::playback::{PlaybackControl, PlaybackOptions, Player};
+use mru_core::position::PositionSink;
+use mru_core::receiver::{bind_receiver, receive_frames};
+use mru_core::recorder::{RecordFormat, RecordOptions, Recorder, RotationOptions, SyncPolicy};
+use mru_core::relay::{RelayTarget, RelayTargets};
+use mru_core::resample::{resample, write_resampled, ResampleOptions};
+use mru_core::sink::FrameSink;
+use mru_core::store::{FrameSource, FrameStore};
+use mru_core::vmc::{VmcOptions, VmcSink, DEFAULT_VMC_PORT};
+use mru_core::websocket::{WsRelay, WsRelayOptions, DEFAULT_WS_PORT};
+
+const USAGE: &str = "usage:
+  mru-cli record <file> [--bind <addr>] [--port <port>] [--per-device] [--compressed] [--meta <session.json>] [--split-minutes <min>] [--split-mb <MB>] [--sync <on_close|every_second|every_frame>] [--vmc <host:port>] [--fingers] [--osc <host:port>] [--osc-prefix <prefix>] [--streams <...>] [--ws <addr:port>] [--relay <host:port,...>] [--filter <...>]
+  mru-cli play <file> [--target <host:port>] [--speed <0.1-10|max>] [--loop] [--filter <...>] [--resample <fps>] [--vmc <host:port>] [--fingers] [--osc <host:port>] [--osc-prefix <prefix>] [--streams <...>] [--ws <addr:port>] [--relay <host:port,...>]
+  mru-cli info <file>
+  mru-cli validate <file>
+  mru-cli compress <file> <output>
+  mru-cli filter <file> <output> [--filter <one_euro|ema|kalman|json>] [--streams <pose,pose_world,face,left_hand,right_hand>]
+  mru-cli resample <file> <output> [--fps <fps>]
+  mru-cli fill-gaps <file> <output> [--method <interpolate|hold>] [--max-gap-ms <msec>] [--min-visibility <0-1|none>] [--streams <pose,pose_world,face,left_hand,right_hand>]
+  mru-cli export-csv <file> <output.csv> [--long] [--streams <pose,pose_world,face,left_hand,right_hand,gravity,root>]
+  mru-cli export-bvh <file> <output.bvh> [--fps <fps>] [--fingers] [--root-motion]
+  mru-cli export-gltf <file> <output.glb> [--fingers] [--root-motion]";
+
+// 値をとるオプション
+const VALUE_OPTIONS: [&str; 20] = [
+    "--bind",
+    "--port",
+    "--target",
+    "--speed",
+    "--streams",
+    "--fps",
+    "--vmc",
+    "--osc",
+    "--osc-prefix",
+    "--ws",
+    "--relay",
+    "--meta",
+    "--split-minutes",
+    "--split-mb",
+    "--sync",
+    "--filter",
+    "--resample",
+    "--method",
+    "--max-gap-ms",
+    "--min-visibility",
+];
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: BTreeSet<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: HashMap::new(),
+            flags: BTreeSet::new(),
+        };
+        while let Some(arg) = args.next() {
+            if VALUE_OPTIONS.contains(&arg.as_str()) {
+                match args.next() {
+                    Some(value) => {
+                        parsed.options.insert(arg, value);
+                    }
+                    None => return Err(format!("{} requires a value", arg)),
+                }
+            } else if arg.starts_with("--") {
+                parsed.flags.insert(arg);
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn file(&self) -> Result<PathBuf, String> {
+        match self.positional.get(1) {
+            Some(file) => Ok(PathBuf::from(file)),
+            None => Err("file is not specified".to_string()),
+        }
+    }
+
+    fn output(&self) -> Result<PathBuf, String> {
+        match self.positional.get(2) {
+            Some(output) => Ok(PathBuf::from(output)),
+            None => Err("output file is not specified".to_string()),
+        }
+    }
+
+    // <host:port>の形式のオプションを読む。ポート番号を省略した場合はdefault_portとする。
+    fn target(&self, option: &str, default_port: u16) -> Result<Option<(String, u16)>, String> {
+        match self.options.get(option) {
+            Some(target) => parse_target(target, default_port).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // 正の数をとるオプションを読む。
+    fn positive_number(&self, option: &str) -> Result<Option<f64>, String> {
+        match self.options.get(option) {
+            Some(value) => match value.parse::<f64>() {
+                Ok(number) if number > 0.0 => Ok(Some(number)),
+                _ => Err(format!("{}: invalid value {}", option, value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // --filterでフィルタの種類を指定する。
+    // 名前だけの場合は既定のパラメータを使い、{"kind":"one_euro","beta":5.0}のようなjsonでパラメータも指定できる。
+    fn filter(&self) -> Result<FilterControl, String> {
+        let control = FilterControl::new();
+        if let Some(filter) = self.options.get("--filter") {
+            let json = if filter.trim_start().starts_with('{') {
+                filter.clone()
+            } else {
+                format!("{{\"kind\":{}}}", serde_json::to_string(filter).unwrap())
+            };
+            let kind: FilterKind =
+                serde_json::from_str(&json).map_err(|why| format!("--filter: {}", why))?;
+            control.set(FilterOptions {
+                filter: Some(kind),
+                ..Default::default()
+            });
+        }
+        Ok(control)
+    }
+
+    // --wsが指定されていればWebSocketでの配信を開始する。
+    async fn ws_relay(&self) -> Result<Option<WsRelay>, String> {
+        match self.target("--ws", DEFAULT_WS_PORT)? {
+            Some((bind_addr, port)) => {
+                let options = WsRelayOptions { bind_addr, port };
+                let relay = WsRelay::start(&options)
+                    .await
+                    .map_err(|why| why.to_string())?;
+                Ok(Some(relay))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // --vmc, --osc, --relayが指定されていれば、VMCプロトコル、OSC、UDPで送信するFrameSinkを作る。
+    fn output_sinks(&self) -> Result<Vec<Box<dyn FrameSink>>, String> {
+        let mut sinks: Vec<Box<dyn FrameSink>> = Vec::new();
+        if let Some((host, port)) = self.target("--vmc", DEFAULT_VMC_PORT)? {
+            let options = VmcOptions {
+                host,
+                port,
+                fingers: self.flags.contains("--fingers"),
+                ..Default::default()
+            };
+            let target = socket_addr_string(&options.host, options.port);
+            sinks.push(Box::new(
+                VmcSink::connect(options).map_err(|why| why.to_string())?,
+            ));
+            println!("vmc: sending to {}", target);
+        }
+        if let Some((host, port)) = self.target("--osc", OscOptions::default().port)? {
+            let mut options = OscOptions {
+                host,
+                port,
+                ..Default::default()
+            };
+            if let Some(prefix) = self.options.get("--osc-prefix") {
+                options.prefix = prefix.clone();
+            }
+            if let Some(streams) = self.options.get("--streams") {
+                options.streams = streams
+                    .split(',')
+                    .map(|name| {
+                        OscStream::from_name(name).ok_or(format!("unknown stream: {}", name))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            let target = socket_addr_string(&options.host, options.port);
+            sinks.push(Box::new(
+                OscSink::connect(options).map_err(|why| why.to_string())?,
+            ));
+            println!("osc: sending to {}", target);
+        }
+        if let Some(relay) = self.options.get("--relay") {
+            let targets = RelayTargets::new();
+            for target in relay.split(',').filter(|s| !s.is_empty()) {
+                let (host, port) = parse_target(target, DEFAULT_PORT)?;
+                targets
+                    .add(RelayTarget {
+                        host,
+                        port,
+                        streams: None,
+                    })
+                    .map_err(|why| why.to_string())?;
+            }
+            sinks.push(Box::new(targets.sink()));
+        }
+        Ok(sinks)
+    }
+}
+
+fn parse_target(target: &str, default_port: u16) -> Result<(String, u16), String> {
+    let (host, port) = match target.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => {
+            (host, port.parse::<u16>().map_err(|why| why.to_string())?)
+        }
+        _ => (target, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host.to_string(), port))
+}
+
+// UDPで受信したデータをファイルに保存する。Ctrl-Cで終了。
+async fn record(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let bind_addr = args
+        .options
+        .get("--bind")
+        .cloned()
+        .unwrap_or("0.0.0.0".to_string());
+    let port = match args.options.get("--port") {
+        Some(port) => port.parse::<u16>().map_err(|why| why.to_string())?,
+        None => DEFAULT_PORT,
+    };
+    let mode = if args.flags.contains("--per-device") {
+        DeviceRecordMode::PerDevice
+    } else {
+        DeviceRecordMode::Merged
+    };
+    let format = if args.flags.contains("--compressed") {
+        RecordFormat::Compressed
+    } else {
+        RecordFormat::Jsonl
+    };
+
+    let addr = socket_addr_string(&bind_addr, port);
+    let mut framed = bind_receiver(&addr).await.map_err(|why| why.to_string())?;
+    // --metaで指定したjsonファイルをセッションの情報としてサイドカーに保存する。
+    let info = match args.options.get("--meta") {
+        Some(meta) => {
+            let s = std::fs::read_to_string(meta).map_err(|why| format!("{}: {}", meta, why))?;
+            serde_json::from_str(&s).map_err(|why| format!("{}: {}", meta, why))?
+        }
+        None => SessionInfo::default(),
+    };
+    // --split-minutes, --split-mbのどちらかを指定した場合はファイルを分割し、
+    // <file>.session.jsonにファイルの一覧を保存する。
+    let rotation = RotationOptions {
+        max_minutes: args.positive_number("--split-minutes")?,
+        max_megabytes: args.positive_number("--split-mb")?,
+    };
+    let sync = match args.options.get("--sync").map(|s| s.as_str()) {
+        None | Some("on_close") => SyncPolicy::OnClose,
+        Some("every_second") => SyncPolicy::EverySecond,
+        Some("every_frame") => SyncPolicy::EveryFrame,
+        Some(sync) => return Err(format!("unknown sync policy: {}", sync)),
+    };
+    let options = RecordOptions {
+        mode,
+        format,
+        info,
+        rotation,
+        sync,
+    };
+    let mut outputs = args.output_sinks()?;
+    let relay = args.ws_relay().await?;
+    if let Some(relay) = &relay {
+        outputs.push(Box::new(relay.sink()));
+    }
+    // 送信先の準備に失敗した場合に空のファイルが残らないように、最後にファイルを作る。
+    let mut recorder = Recorder::create(&path, options).map_err(|why| why.to_string())?;
+    // --filterと腰の位置(root_translation)の推定は送信するフレームだけにかけ、記録するフレームにはかけない。
+    let filter = args.filter()?;
+    let devices = Mutex::new(DeviceTable::default());
+    println!("recorder: start on {}, writing to {:?}", addr, path);
+
+    let mut sinks: Vec<Box<dyn FrameSink + '_>> = vec![
+        Box::new(&mut recorder),
+        Box::new(filter.sink(PositionSink::new(outputs))),
+    ];
+    tokio::select! {
+        _ = receive_frames(&mut framed, &devices, &mut sinks) => {},
+        _ = tokio::signal::ctrl_c() => {
+            println!("recorder: stop");
+        },
+    }
+    drop(sinks);
+
+    recorder.flush().map_err(|why| why.to_string())?;
+    println!("recorder: {} frames written.", recorder.frames);
+    Ok(())
+}
+
+// ファイルを読み込んで、記録時と同じ間隔でUDP送信する。Ctrl-Cで終了。
+async fn play(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let target = args
+        .options
+        .get("--target")
+        .cloned()
+        .unwrap_or(socket_addr_string("127.0.0.1", DEFAULT_PORT));
+
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    if let FrameStore::Session(session) = &store {
+        println!("parts: {}", session.parts());
+    }
+    // --resampleの場合は一定のフレームレートに補間してから送信する。
+    let store = match args.positive_number("--resample")? {
+        Some(fps) => {
+            let frames =
+                resample(&store, &ResampleOptions { fps }).map_err(|why| why.to_string())?;
+            println!("play: resampled to {} frames at {} fps", frames.len(), fps);
+            FrameStore::Memory(frames)
+        }
+        None => store,
+    };
+
+    let udp_sink = UdpSink::connect(&target).map_err(|why| why.to_string())?;
+    println!("play: sending to {}", target);
+    let mut outputs = args.output_sinks()?;
+    let relay = args.ws_relay().await?;
+    if let Some(relay) = &relay {
+        outputs.push(Box::new(relay.sink()));
+    }
+    // --targetへは記録された行をそのまま送り、root_translationは他の送信先だけに付ける。
+    let sinks: Vec<Box<dyn FrameSink>> =
+        vec![Box::new(udp_sink), Box::new(PositionSink::new(outputs))];
+
+    // --speed maxの場合は待機せずに送信する。
+    let mut options = PlaybackOptions {
+        looping: args.flags.contains("--loop"),
+        ..Default::default()
+    };
+    match args.options.get("--speed").map(|s| s.as_str()) {
+        Some("max") => options.as_fast_as_possible = true,
+        Some(speed) => options.speed = speed.parse::<f64>().map_err(|why| why.to_string())?,
+        None => {}
+    }
+    let control = PlaybackControl::new(options);
+    let mut sinks = args.filter()?.sink(PositionSink::new(sinks));
+
+    let mut player = Player::new(0);
+    tokio::select! {
+        _ = player.play(&store, &mut sinks, &control) => {},
+        _ = tokio::signal::ctrl_c() => {
+            println!("play: stop");
+        },
+    }
+    println!("play: stopped at frame {}.", player.next);
+    Ok(())
+}
+
+fn print_summary(summary: &LoadSummary) {
+    let duration = summary
+        .end_timestamp
+        .saturating_sub(summary.begin_timestamp) as f64
+        * 1e-6;
+    println!("file: {}", summary.path);
+    println!(
+        "frames: {} (lines: {}, skipped: {})",
+        summary.loaded_frames, summary.total_lines, summary.skipped_lines
+    );
+    println!(
+        "timestamp: {} - {} ({:.3} sec)",
+        summary.begin_timestamp, summary.end_timestamp, duration
+    );
+    if duration > 0.0 {
+        println!(
+            "average fps: {:.2}",
+            (summary.loaded_frames - 1) as f64 / duration
+        );
+    }
+}
+
+// ファイルの概要を表示する。
+// 大きなファイルでもメモリに読み込まないように、1フレームずつ読んで数える。
+fn info(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    if let FrameStore::Session(session) = &store {
+        println!("parts: {}", session.parts());
+    }
+
+    let mut camera_params = None;
+    // ストリームごとに含まれているフレーム数と、そのうち欠落を補ったフレーム数
+    let mut counts = [0usize; LandmarkStream::ALL.len()];
+    let mut filled = [0usize; LandmarkStream::ALL.len()];
+    let mut gravity = 0;
+    let mut devices = BTreeSet::new();
+    for i in 0..store.len() {
+        let tf = match store.frame(i) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        if camera_params.is_none() {
+            camera_params = tf.frame.camera_params.clone();
+        }
+        for (i, stream) in LandmarkStream::ALL.into_iter().enumerate() {
+            if tf.frame.landmarks(stream).is_some() {
+                counts[i] += 1;
+            }
+            if let Some(flags) = &tf.frame.filled {
+                if flags.streams.contains(&stream) || flags.landmarks.contains_key(&stream) {
+                    filled[i] += 1;
+                }
+            }
+        }
+        if tf.frame.gravity.is_some() {
+            gravity += 1;
+        }
+        if let Some(device_id) = &tf.frame.device_id {
+            devices.insert(device_id.clone());
+        }
+    }
+
+    match camera_params {
+        Some(camera_params) => println!(
+            "camera_params: focal_length {}, frame {}x{}",
+            camera_params.focal_length, camera_params.frame_width, camera_params.frame_height
+        ),
+        None => println!("camera_params: not found"),
+    }
+    for (i, stream) in LandmarkStream::ALL.into_iter().enumerate() {
+        if filled[i] > 0 {
+            println!(
+                "{}_landmarks: {} frames ({} filled)",
+                stream.name(),
+                counts[i],
+                filled[i]
+            );
+        } else {
+            println!("{}_landmarks: {} frames", stream.name(), counts[i]);
+        }
+    }
+    println!("gravity: {} frames", gravity);
+    if !devices.is_empty() {
+        println!("devices: {:?}", devices);
+    }
+    if let Some(metadata) = &summary.metadata {
+        println!(
+            "metadata: {}",
+            serde_json::to_string_pretty(metadata).map_err(|why| why.to_string())?
+        );
+    }
+    Ok(())
+}
+
+// 壊れた行やタイムスタンプの逆行がないか確認する。
+// 問題があれば終了コード1を返す。
+fn validate(args: &Args) -> Result<bool, String> {
+    let path = args.file()?;
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    for e in summary.errors.iter() {
+        println!("line {}: {}", e.line, e.reason);
+    }
+
+    // ファイルから読む場合は、開いた時点ではタイムスタンプしか確認していない。
+    let mut unreadable = 0;
+    if summary.streaming {
+        for i in 0..store.len() {
+            if store.frame(i).is_none() {
+                unreadable += 1;
+            }
+        }
+    }
+
+    let mut backwards = 0;
+    for i in 1..store.len() {
+        let (t0, t1) = (store.timestamp(i - 1), store.timestamp(i));
+        if t1 < t0 {
+            println!("frame {}: timestamp goes backwards ({} -> {})", i, t0, t1);
+            backwards += 1;
+        }
+    }
+
+    println!(
+        "{}: {} frames, {} broken lines, {} unreadable frames, {} backward timestamps",
+        summary.path,
+        summary.loaded_frames,
+        summary.errors.len(),
+        unreadable,
+        backwards
+    );
+    Ok(summary.errors.is_empty() && unreadable == 0 && backwards == 0)
+}
+
+// 記録ファイルを圧縮形式に変換する。
+fn compress(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let reader = open_reader(&path).map_err(|why| why.to_string())?;
+    let mut writer = CompressedWriter::create(&output).map_err(|why| why.to_string())?;
+    for line in reader.lines() {
+        let line = line.map_err(|why| why.to_string())?;
+        // 空行は書き込まない。
+        if line.trim().is_empty() {
+            continue;
+        }
+        writer.write_line(&line).map_err(|why| why.to_string())?;
+    }
+    writer.finish().map_err(|why| why.to_string())?;
+
+    let before = std::fs::metadata(&path)
+        .map_err(|why| why.to_string())?
+        .len();
+    let after = std::fs::metadata(&output)
+        .map_err(|why| why.to_string())?
+        .len();
+    println!(
+        "compress: {} frames, {} -> {} bytes ({:.1}%)",
+        writer.frames(),
+        before,
+        after,
+        after as f64 / before.max(1) as f64 * 100.0
+    );
+    Ok(())
+}
+
+// フィルタをかけたフレームを1行1フレームのjsonとして書き出す。
+fn filter_file(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let (store, _) = FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    let mut options = args.filter()?.get();
+    if options.filter.is_none() {
+        options.filter = Some(FilterKind::OneEuro(Default::default()));
+    }
+    if let Some(streams) = args.options.get("--streams") {
+        options.streams = streams
+            .split(',')
+            .map(|name| LandmarkStream::from_name(name).ok_or(format!("unknown stream: {}", name)))
+            .collect::<Result<_, _>>()?;
+    }
+    let frames = write_filtered(&store, &output, &options).map_err(|why| why.to_string())?;
+    println!("filter: {} frames written to {:?}", frames, output);
+    Ok(())
+}
+
+// 一定のフレームレートに補間したフレームを1行1フレームのjsonとして書き出す。
+fn resample_file(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let mut options = ResampleOptions::default();
+    if let Some(fps) = args.positive_number("--fps")? {
+        options.fps = fps;
+    }
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    let motion = write_resampled(&store, &output, &options).map_err(|why| why.to_string())?;
+    println!(
+        "resample: {} ({} frames, {:.2} fps)",
+        motion.path, motion.frames, motion.frame_rate
+    );
+    Ok(())
+}
+
+// 手や顔の欠落、visibilityが低いランドマークを補ったフレームを1行1フレームのjsonとして書き出す。
+fn fill_gaps_file(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let mut options = GapFillOptions::default();
+    match args.options.get("--method").map(|s| s.as_str()) {
+        Some("interpolate") => options.method = GapFillMethod::Interpolate,
+        Some("hold") => options.method = GapFillMethod::Hold,
+        Some(method) => return Err(format!("--method: unknown method {}", method)),
+        None => {}
+    }
+    if let Some(max_gap_ms) = args.positive_number("--max-gap-ms")? {
+        options.max_gap_ms = max_gap_ms;
+    }
+    match args.options.get("--min-visibility").map(|s| s.as_str()) {
+        Some("none") => options.min_visibility = None,
+        Some(value) => match value.parse::<f64>() {
+            Ok(min_visibility) if (0.0..=1.0).contains(&min_visibility) => {
+                options.min_visibility = Some(min_visibility)
+            }
+            _ => return Err(format!("--min-visibility: invalid value {}", value)),
+        },
+        None => {}
+    }
+    if let Some(streams) = args.options.get("--streams") {
+        options.streams = streams
+            .split(',')
+            .map(|name| LandmarkStream::from_name(name).ok_or(format!("unknown stream: {}", name)))
+            .collect::<Result<_, _>>()?;
+    }
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    let filled = write_filled(&store, &output, &options).map_err(|why| why.to_string())?;
+    println!(
+        "fill-gaps: {} ({} frames, {} streams and {} landmarks filled)",
+        filled.path, filled.frames, filled.filled_streams, filled.filled_landmarks
+    );
+    Ok(())
+}
+
+// ストリームごとのCSVファイルに書き出す。
+fn export_csv_files(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let mut options = CsvOptions::default();
+    if args.flags.contains("--long") {
+        options.layout = CsvLayout::Long;
+    }
+    if let Some(streams) = args.options.get("--streams") {
+        options.streams = streams
+            .split(',')
+            .map(|name| CsvStream::from_name(name).ok_or(format!("unknown stream: {}", name)))
+            .collect::<Result<_, _>>()?;
+    }
+
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    if let FrameStore::Session(session) = &store {
+        println!("parts: {}", session.parts());
+    }
+    let paths = export_csv(&store, &output, &options).map_err(|why| why.to_string())?;
+    for path in paths {
+        println!("export: {:?}", path);
+    }
+    Ok(())
+}
+
+// pose_world_landmarksから骨格のアニメーションを作ってBVHに書き出す。
+fn export_bvh_file(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let options = BvhOptions {
+        fps: match args.options.get("--fps") {
+            Some(fps) => Some(fps.parse::<f64>().map_err(|why| why.to_string())?),
+            None => None,
+        },
+        fingers: args.flags.contains("--fingers"),
+        root_motion: args.flags.contains("--root-motion"),
+    };
+
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    if let FrameStore::Session(session) = &store {
+        println!("parts: {}", session.parts());
+    }
+    let motion = export_bvh(&store, &output, &options).map_err(|why| why.to_string())?;
+    println!(
+        "export: {} ({} frames, {:.2} fps)",
+        motion.path, motion.frames, motion.frame_rate
+    );
+    Ok(())
+}
+
+// pose_world_landmarksから骨格のアニメーションを作ってglTF(GLB)に書き出す。
+fn export_gltf_file(args: &Args) -> Result<(), String> {
+    let path = args.file()?;
+    let output = args.output()?;
+    let options = GltfOptions {
+        fingers: args.flags.contains("--fingers"),
+        root_motion: args.flags.contains("--root-motion"),
+    };
+
+    let (store, summary) =
+        FrameStore::open(&path, LoadPolicy::Skip).map_err(|why| why.to_string())?;
+    print_summary(&summary);
+    if let FrameStore::Session(session) = &store {
+        println!("parts: {}", session.parts());
+    }
+    let motion = export_gltf(&store, &output, &options).map_err(|why| why.to_string())?;
+    println!(
+        "export: {} ({} frames, {:.2} fps)",
+        motion.path, motion.frames, motion.frame_rate
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(why) => {
+            eprintln!("{}\n{}", why, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match args.positional.first().map(|s| s.as_str()) {
+        Some("record") => record(&args).await.map(|_| true),
+        Some("play") => play(&args).await.map(|_| true),
+        Some("info") => info(&args).map(|_| true),
+        Some("validate") => validate(&args),
+        Some("compress") => compress(&args).map(|_| true),
+        Some("filter") => filter_file(&args).map(|_| true),
+        Some("resample") => resample_file(&args).map(|_| true),
+        Some("fill-gaps") => fill_gaps_file(&args).map(|_| true),
+        Some("export-csv") => export_csv_files(&args).map(|_| true),
+        Some("export-bvh") => export_bvh_file(&args).map(|_| true),
+        Some("export-gltf") => export_gltf_file(&args).map(|_| true),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(why) => {
+            eprintln!("error: {}", why);
+            ExitCode::FAILURE
+        }
+    }
+}
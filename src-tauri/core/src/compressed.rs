@@ -0,0 +1,467 @@
+// 圧縮した記録ファイルの読み書き。
+// 数フレームずつまとめてdeflateで圧縮したブロックを並べ、
+// 最後にタイムスタンプからブロックを引くためのインデックスを付ける。
+// 記録中に落ちてインデックスが書かれなかった場合は、先頭からブロックを読んで復元する。
+//
+// ファイルの構成(数値はすべてリトルエンディアン)
+//   ヘッダ: b"MRUZ", version(u8), 予約(3バイト)
+//   ブロック: 圧縮後の長さ(u32), フレーム数(u32), 圧縮データ
+//     圧縮データを展開すると、改行で終わるjsonがフレーム数だけ並んでいる。
+//   インデックス:
+//     ブロック数(u64), ブロックごとに 位置(u64), 圧縮後の長さ(u32), フレーム数(u32)
+//     フレーム数(u64), フレームごとに タイムスタンプ(u64), ブロック番号(u32), ブロック内の番号(u32)
+//     pose_landmarks_stampがない行(壊れた行を含む)はブロックには書くが、インデックスには入れない。
+//   フッタ: インデックスの位置(u64), b"MRUI"
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::frames::peek_timestamp;
+
+pub const MAGIC: &[u8; 4] = b"MRUZ";
+const INDEX_MAGIC: &[u8; 4] = b"MRUI";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 8;
+const FOOTER_LEN: u64 = 12;
+// 1ブロックにまとめるフレーム数
+const FRAMES_PER_BLOCK: u32 = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockEntry {
+    // ブロックの先頭(長さの位置)
+    pub offset: u64,
+    pub compressed_len: u32,
+    pub frames: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameEntry {
+    pub timestamp: u64,
+    pub block: u32,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameIndex {
+    pub blocks: Vec<BlockEntry>,
+    pub frames: Vec<FrameEntry>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// 先頭のマジックナンバーで圧縮形式かどうかを判定する。
+pub fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 4];
+    match file.read_exact(&mut magic) {
+        Ok(_) => Ok(&magic == MAGIC),
+        Err(why) if why.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(why) => Err(why),
+    }
+}
+
+pub struct CompressedWriter {
+    file: BufWriter<File>,
+    // 次に書き込む位置
+    offset: u64,
+    // 圧縮前のブロック
+    block: Vec<u8>,
+    block_frames: u32,
+    // ブロックに書いた行の合計
+    lines: usize,
+    index: FrameIndex,
+    finished: bool,
+}
+
+impl CompressedWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION, 0, 0, 0])?;
+        Ok(Self {
+            file,
+            offset: HEADER_LEN,
+            block: Vec::new(),
+            block_frames: 0,
+            lines: 0,
+            index: FrameIndex::default(),
+            finished: false,
+        })
+    }
+
+    // 一行分のjsonを書き込む。空行は書き込まない。
+    // ブロックのフレーム数と行数が一致しなくなるので、改行を含む行はエラーにする。
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        if line.contains('\n') {
+            return Err(invalid_data("line contains a newline"));
+        }
+        if let Ok(Some(timestamp)) = peek_timestamp(line) {
+            self.index.frames.push(FrameEntry {
+                timestamp,
+                block: self.index.blocks.len() as u32,
+                line: self.block_frames,
+            });
+        }
+        self.block.extend_from_slice(line.as_bytes());
+        self.block.push(b'\n');
+        self.block_frames += 1;
+        self.lines += 1;
+        if self.block_frames >= FRAMES_PER_BLOCK {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    // 溜まっているフレームを一つのブロックとして書き込む。
+    pub fn write_block(&mut self) -> io::Result<()> {
+        if self.block_frames == 0 {
+            return Ok(());
+        }
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        self.file
+            .write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.file.write_all(&self.block_frames.to_le_bytes())?;
+        self.file.write_all(&compressed)?;
+        self.index.blocks.push(BlockEntry {
+            offset: self.offset,
+            compressed_len: compressed.len() as u32,
+            frames: self.block_frames,
+        });
+        self.offset += 8 + compressed.len() as u64;
+        self.block.clear();
+        self.block_frames = 0;
+        self.file.flush()
+    }
+
+    // 残りのブロックとインデックスを書き込む。何度呼んでもよい。
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.write_block()?;
+        let index_offset = self.offset;
+        self.file
+            .write_all(&(self.index.blocks.len() as u64).to_le_bytes())?;
+        for block in self.index.blocks.iter() {
+            self.file.write_all(&block.offset.to_le_bytes())?;
+            self.file.write_all(&block.compressed_len.to_le_bytes())?;
+            self.file.write_all(&block.frames.to_le_bytes())?;
+        }
+        self.file
+            .write_all(&(self.index.frames.len() as u64).to_le_bytes())?;
+        for frame in self.index.frames.iter() {
+            self.file.write_all(&frame.timestamp.to_le_bytes())?;
+            self.file.write_all(&frame.block.to_le_bytes())?;
+            self.file.write_all(&frame.line.to_le_bytes())?;
+        }
+        self.file.write_all(&index_offset.to_le_bytes())?;
+        self.file.write_all(INDEX_MAGIC)?;
+        self.file.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    // 書き込んだ行数。タイムスタンプがない行も含む。
+    pub fn frames(&self) -> usize {
+        self.lines
+    }
+
+    // 書き込んだバイト数。書きかけのブロックは含まない。
+    pub fn bytes_written(&self) -> u64 {
+        self.offset
+    }
+
+    // 書きかけのブロックを書き込んでディスクに反映する。
+    // インデックスは書き込まないが、読み込み時にブロックを走査して復元できる。
+    // 書きかけのブロックが小さいほど圧縮率は下がる。
+    pub fn sync(&mut self) -> io::Result<()> {
+        if !self.finished {
+            self.write_block()?;
+        }
+        self.file.get_ref().sync_data()
+    }
+}
+
+impl Drop for CompressedWriter {
+    fn drop(&mut self) {
+        if let Err(why) = self.finish() {
+            println!("compressed writer: {}", why);
+        }
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// ブロックを展開する。
+pub fn read_block(file: &mut File, block: &BlockEntry) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(block.offset + 8))?;
+    let mut compressed = vec![0u8; block.compressed_len as usize];
+    file.read_exact(&mut compressed)?;
+    let mut data = Vec::new();
+    DeflateDecoder::new(&compressed[..]).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// フッタからインデックスを読む。
+// フッタがない場合(記録中に落ちた場合など)は先頭から読んで作り直す。
+pub fn read_index(file: &mut File) -> io::Result<FrameIndex> {
+    let mut magic = [0u8; 4];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a compressed recording"));
+    }
+    let file_len = file.metadata()?.len();
+    if file_len >= HEADER_LEN + FOOTER_LEN {
+        file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        let index_offset = read_u64(file)?;
+        file.read_exact(&mut magic)?;
+        if &magic == INDEX_MAGIC && index_offset < file_len {
+            file.seek(SeekFrom::Start(index_offset))?;
+            let mut reader = BufReader::new(&mut *file);
+            return read_index_body(&mut reader);
+        }
+    }
+    println!("compressed reader: index not found, scanning blocks.");
+    scan_index(file)
+}
+
+fn read_index_body<R: Read>(reader: &mut R) -> io::Result<FrameIndex> {
+    let mut index = FrameIndex::default();
+    let block_count = read_u64(reader)?;
+    for _ in 0..block_count {
+        index.blocks.push(BlockEntry {
+            offset: read_u64(reader)?,
+            compressed_len: read_u32(reader)?,
+            frames: read_u32(reader)?,
+        });
+    }
+    let frame_count = read_u64(reader)?;
+    for _ in 0..frame_count {
+        index.frames.push(FrameEntry {
+            timestamp: read_u64(reader)?,
+            block: read_u32(reader)?,
+            line: read_u32(reader)?,
+        });
+    }
+    Ok(index)
+}
+
+// ブロックを先頭から順に読んでインデックスを作る。
+// 途中で切れているブロックと、それ以降は捨てる。
+fn scan_index(file: &mut File) -> io::Result<FrameIndex> {
+    let mut index = FrameIndex::default();
+    let file_len = file.metadata()?.len();
+    let mut offset = HEADER_LEN;
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        let compressed_len = read_u32(file)?;
+        let frames = read_u32(file)?;
+        if offset + 8 + compressed_len as u64 > file_len {
+            break;
+        }
+        let block = BlockEntry {
+            offset,
+            compressed_len,
+            frames,
+        };
+        let data = match read_block(file, &block) {
+            Ok(data) => data,
+            Err(_) => break,
+        };
+        // 改行で終わる行がちょうどフレーム数だけあるブロックだけを使う。
+        let lines = match data.strip_suffix(b"\n") {
+            Some(data) => data.split(|c| *c == b'\n').collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        if lines.len() != frames as usize {
+            break;
+        }
+        for (line_no, line) in lines.iter().enumerate() {
+            let stamp = std::str::from_utf8(line)
+                .ok()
+                .and_then(|line| peek_timestamp(line).ok().flatten());
+            if let Some(timestamp) = stamp {
+                index.frames.push(FrameEntry {
+                    timestamp,
+                    block: index.blocks.len() as u32,
+                    line: line_no as u32,
+                });
+            }
+        }
+        index.blocks.push(block);
+        offset += 8 + compressed_len as u64;
+    }
+    Ok(index)
+}
+
+// ブロックを順に展開して、元の改行区切りのjsonとして読むためのReader
+pub struct CompressedReader {
+    file: File,
+    blocks: Vec<BlockEntry>,
+    next_block: usize,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl CompressedReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let index = read_index(&mut file)?;
+        Ok(Self {
+            file,
+            blocks: index.blocks,
+            next_block: 0,
+            data: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl Read for CompressedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.data.len() {
+            if self.next_block >= self.blocks.len() {
+                return Ok(0);
+            }
+            self.data = read_block(&mut self.file, &self.blocks[self.next_block])?;
+            self.next_block += 1;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "mru_compressed_{}_{}.dat",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn line(stamp: u64) -> String {
+        format!("{{\"pose_landmarks_stamp\":{}}}", stamp)
+    }
+
+    fn read_all(path: &Path) -> String {
+        let mut text = String::new();
+        CompressedReader::open(path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    // 記録中に落ちた状態にする。finishを呼ばずに書き込みを終える。
+    fn crash(mut writer: CompressedWriter) {
+        writer.sync().unwrap();
+        std::mem::forget(writer);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip");
+        let mut writer = CompressedWriter::create(&path).unwrap();
+        let mut expected = String::new();
+        for i in 0..40 {
+            writer.write_line(&line(i * 1000)).unwrap();
+            expected += &line(i * 1000);
+            expected += "\n";
+        }
+        writer.write_line("").unwrap();
+        writer.write_line("{\"face_landmarks\":[]}").unwrap();
+        writer.write_line("{broken").unwrap();
+        expected += "{\"face_landmarks\":[]}\n{broken\n";
+        writer.finish().unwrap();
+        assert_eq!(writer.frames(), 42);
+        drop(writer);
+
+        let index = read_index(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(index.blocks.len(), 2);
+        assert_eq!(index.blocks.iter().map(|b| b.frames).sum::<u32>(), 42);
+        // タイムスタンプのない行はインデックスに入らない。
+        assert_eq!(index.frames.len(), 40);
+        assert!(index
+            .frames
+            .windows(2)
+            .all(|w| w[0].timestamp < w[1].timestamp));
+        assert_eq!(index.frames[35].block, 1);
+        assert_eq!(index.frames[35].line, 3);
+        assert_eq!(read_all(&path), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recover_without_index() {
+        let path = temp_path("recover");
+        let mut writer = CompressedWriter::create(&path).unwrap();
+        for i in 0..70 {
+            writer.write_line(&line(i)).unwrap();
+            // 空行があっても、それ以降のブロックを復元できる。
+            if i == 10 {
+                writer.write_line("").unwrap();
+            }
+        }
+        crash(writer);
+
+        let index = read_index(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(index.blocks.len(), 3);
+        assert_eq!(index.frames.len(), 70);
+        assert_eq!(index.frames[69].timestamp, 69);
+        assert_eq!(read_all(&path).lines().count(), 70);
+
+        // 最後のブロックが途中で切れている場合は、そのブロックだけを捨てる。
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+        let index = read_index(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(index.blocks.len(), 2);
+        assert_eq!(index.frames.len(), 64);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_line_with_newline() {
+        let path = temp_path("newline");
+        let mut writer = CompressedWriter::create(&path).unwrap();
+        assert!(writer.write_line("{}\n{}").is_err());
+        assert_eq!(writer.frames(), 0);
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
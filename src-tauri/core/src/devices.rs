@@ -0,0 +1,229 @@
+// 複数の端末から同じポートに送信されてくる場合に、
+// 送信元アドレスごとにセッションを分けて管理する。
+// device_idには送信元の"IPアドレス:ポート番号"を使う。
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::stats::{PacketStats, SourceStats};
+
+// packet_rateを計算する期間
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+// この時間パケットが届かなければ非アクティブとみなす
+const ACTIVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn device_id(addr: &SocketAddr) -> String {
+    addr.to_string()
+}
+
+// ファイル名に使えるようにdevice_idを変換する。
+pub fn device_file_suffix(addr: &SocketAddr) -> String {
+    device_id(addr)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// jsonの先頭にdevice_idを追加する。
+// 一つのファイルに複数端末のデータを記録する場合に使う。
+// 既にdevice_idがある場合(複数端末の記録を再生したものを受信した場合)は、元の端末を区別できるようにそのままにする。
+pub fn tag_device(json_str: &str, device_id: &str) -> String {
+    if has_device_id(json_str) {
+        return json_str.to_string();
+    }
+    let trimmed = json_str.trim_start();
+    match trimmed.strip_prefix('{') {
+        Some(rest) => {
+            let id = serde_json::to_string(device_id).unwrap();
+            if rest.trim_start().starts_with('}') {
+                format!("{{\"device_id\":{}{}", id, rest)
+            } else {
+                format!("{{\"device_id\":{},{}", id, rest)
+            }
+        }
+        None => json_str.to_string(),
+    }
+}
+
+fn has_device_id(json_str: &str) -> bool {
+    // ほとんどのフレームはdevice_idを含まないので、先に文字列で確認する。
+    json_str.contains("\"device_id\"")
+        && serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(json_str)
+            .is_ok_and(|map| map.contains_key("device_id"))
+}
+
+pub struct DeviceSession {
+    pub addr: SocketAddr,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    pub packets: u64,
+    // RATE_WINDOW内に受信した時刻
+    recent: VecDeque<Instant>,
+    stats: SourceStats,
+}
+
+impl DeviceSession {
+    fn new(addr: SocketAddr, now: Instant) -> Self {
+        Self {
+            addr,
+            first_seen: now,
+            last_seen: now,
+            packets: 0,
+            recent: VecDeque::new(),
+            stats: SourceStats::default(),
+        }
+    }
+
+    fn on_packet(&mut self, json_str: &str, now: Instant) {
+        self.last_seen = now;
+        self.packets += 1;
+        self.recent.push_back(now);
+        self.expire(now);
+        self.stats.on_packet(json_str, now);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(t) = self.recent.front() {
+            if now.duration_since(*t) > RATE_WINDOW {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // 直近RATE_WINDOWに受信したパケット数から計算する[packets/sec]
+    pub fn packet_rate(&mut self, now: Instant) -> f64 {
+        self.expire(now);
+        self.recent.len() as f64 / RATE_WINDOW.as_secs_f64()
+    }
+
+    pub fn is_active(&self, now: Instant) -> bool {
+        now.duration_since(self.last_seen) <= ACTIVE_TIMEOUT
+    }
+}
+
+// list_devicesでフロントエンドに返す情報
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub addr: String,
+    pub packets: u64,
+    pub packet_rate: f64,
+    // 最後に受信してからの経過時間[msec]
+    pub last_seen_ms: u64,
+    // 最初に受信してからの経過時間[msec]
+    pub connected_ms: u64,
+}
+
+#[derive(Default)]
+pub struct DeviceTable {
+    sessions: HashMap<SocketAddr, DeviceSession>,
+}
+
+impl DeviceTable {
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+
+    // パケットを受信したら呼び出す。
+    // 新しい送信元であればtrueを返す。
+    pub fn on_packet(&mut self, addr: SocketAddr, json_str: &str, now: Instant) -> bool {
+        let is_new = !self.sessions.contains_key(&addr);
+        self.sessions
+            .entry(addr)
+            .or_insert_with(|| DeviceSession::new(addr, now))
+            .on_packet(json_str, now);
+        is_new
+    }
+
+    // アクティブな送信元の一覧
+    pub fn active(&mut self, now: Instant) -> Vec<DeviceInfo> {
+        let mut infos: Vec<DeviceInfo> = self
+            .sessions
+            .values_mut()
+            .filter(|session| session.is_active(now))
+            .map(|session| DeviceInfo {
+                device_id: device_id(&session.addr),
+                addr: session.addr.to_string(),
+                packets: session.packets,
+                packet_rate: session.packet_rate(now),
+                last_seen_ms: now.duration_since(session.last_seen).as_millis() as u64,
+                connected_ms: now.duration_since(session.first_seen).as_millis() as u64,
+            })
+            .collect();
+        infos.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        infos
+    }
+
+    // アクティブな送信元ごとの受信の統計
+    pub fn stats(&mut self, now: Instant) -> Vec<PacketStats> {
+        let mut stats: Vec<PacketStats> = self
+            .sessions
+            .values_mut()
+            .filter(|session| session.is_active(now))
+            .map(|session| {
+                let fps = session.packet_rate(now);
+                session
+                    .stats
+                    .report(device_id(&session.addr), session.packets, fps)
+            })
+            .collect();
+        stats.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        stats
+    }
+}
+
+// 記録時に複数端末のデータをどう保存するか
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceRecordMode {
+    // 一つのファイルにdevice_idを付けて保存する
+    #[default]
+    Merged,
+    // 端末ごとに別のファイルに保存する
+    PerDevice,
+}
+
+// 端末ごとのファイル名
+// 例: record.dat -> record_192_168_1_5_49152.dat
+pub fn device_record_path(path: &Path, addr: &SocketAddr) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!(
+            "{}_{}.{}",
+            stem,
+            device_file_suffix(addr),
+            ext.to_string_lossy()
+        ),
+        None => format!("{}_{}", stem, device_file_suffix(addr)),
+    };
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::holistic::HolisticFrame;
+
+    #[test]
+    fn tag_device_adds_device_id() {
+        assert_eq!(
+            tag_device("{\"pose_landmarks_stamp\":1}", "a"),
+            "{\"device_id\":\"a\",\"pose_landmarks_stamp\":1}"
+        );
+        assert_eq!(tag_device("{}", "a"), "{\"device_id\":\"a\"}");
+    }
+
+    #[test]
+    fn tag_device_keeps_existing_device_id() {
+        let tagged = tag_device(&tag_device("{\"pose_landmarks_stamp\":1}", "a"), "b");
+        let frame = HolisticFrame::from_json_str(&tagged).unwrap();
+        assert_eq!(frame.device_id.as_deref(), Some("a"));
+    }
+}
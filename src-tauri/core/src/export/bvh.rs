@@ -0,0 +1,147 @@
+// pose_world_landmarksから求めた骨格のアニメーションをBVHに書き出す。
+// BlenderやMotionBuilderで読み込めるように、単位はセンチメートル、
+// 回転はZXYの順のオイラー角とする。
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::export::{
+    body_samples, frame_rate, hip_positions, resample_body, ExportError, MotionSummary,
+};
+use crate::math::{Quat, Vec3};
+use crate::skeleton::{Skeleton, HIPS};
+use crate::store::FrameSource;
+
+// メートルからセンチメートルへ
+const SCALE: f64 = 100.0;
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BvhOptions {
+    // 書き出すフレームレート。Noneの場合はpose_landmarks_stampの間隔から決める。
+    pub fps: Option<f64>,
+    // hand_landmarksから指の関節も書き出す。
+    pub fingers: bool,
+    // camera_paramsから求めた腰の移動を書き出す。falseの場合は腰の位置を固定する。
+    pub root_motion: bool,
+}
+
+fn write_vec3<W: Write>(w: &mut W, v: Vec3) -> std::io::Result<()> {
+    write!(
+        w,
+        "{:.4} {:.4} {:.4}",
+        v.x * SCALE,
+        v.y * SCALE,
+        v.z * SCALE
+    )
+}
+
+fn write_joint<W: Write>(
+    w: &mut W,
+    skeleton: &Skeleton,
+    joint: usize,
+    depth: usize,
+) -> std::io::Result<()> {
+    let indent = "\t".repeat(depth);
+    let j = &skeleton.joints[joint];
+    if j.parent.is_none() {
+        writeln!(w, "{}ROOT {}", indent, j.name)?;
+    } else {
+        writeln!(w, "{}JOINT {}", indent, j.name)?;
+    }
+    writeln!(w, "{}{{", indent)?;
+    write!(w, "{}\tOFFSET ", indent)?;
+    write_vec3(w, j.offset)?;
+    writeln!(w)?;
+    if j.parent.is_none() {
+        writeln!(
+            w,
+            "{}\tCHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation",
+            indent
+        )?;
+    } else {
+        writeln!(w, "{}\tCHANNELS 3 Zrotation Xrotation Yrotation", indent)?;
+    }
+    for (child, c) in skeleton.joints.iter().enumerate() {
+        if c.parent == Some(joint) {
+            write_joint(w, skeleton, child, depth + 1)?;
+        }
+    }
+    if let Some(end_site) = j.end_site {
+        writeln!(w, "{}\tEnd Site", indent)?;
+        writeln!(w, "{}\t{{", indent)?;
+        write!(w, "{}\t\tOFFSET ", indent)?;
+        write_vec3(w, end_site)?;
+        writeln!(w)?;
+        writeln!(w, "{}\t}}", indent)?;
+    }
+    writeln!(w, "{}}}", indent)
+}
+
+// 関節の並びはHIERARCHYに書いた順(深さ優先)とする。
+fn joint_order(skeleton: &Skeleton, joint: usize, order: &mut Vec<usize>) {
+    order.push(joint);
+    for (child, c) in skeleton.joints.iter().enumerate() {
+        if c.parent == Some(joint) {
+            joint_order(skeleton, child, order);
+        }
+    }
+}
+
+pub fn export_bvh<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &BvhOptions,
+) -> Result<MotionSummary, ExportError> {
+    let samples = body_samples(frames);
+    if samples.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    let fps = options
+        .fps
+        .filter(|fps| fps.is_finite() && *fps > 0.0)
+        .unwrap_or_else(|| frame_rate(&samples));
+    let skeleton = Skeleton::new(&samples, options.fingers);
+    let motion = resample_body(&samples, fps);
+    let hips = hip_positions(&motion, skeleton.hip_height, options.root_motion);
+
+    let mut w = BufWriter::new(File::create(path)?);
+    writeln!(w, "HIERARCHY")?;
+    write_joint(&mut w, &skeleton, HIPS, 0)?;
+    writeln!(w, "MOTION")?;
+    writeln!(w, "Frames: {}", motion.len())?;
+    writeln!(w, "Frame Time: {:.6}", 1.0 / fps)?;
+
+    let mut order = Vec::new();
+    joint_order(&skeleton, HIPS, &mut order);
+    // 決められなかった関節は前のフレームの回転のままにする。
+    let mut rotations = vec![Quat::IDENTITY; skeleton.joints.len()];
+    for (sample, hip) in motion.iter().zip(hips) {
+        for (rotation, solved) in rotations.iter_mut().zip(skeleton.solve(sample)) {
+            if let Some(solved) = solved {
+                *rotation = solved;
+            }
+        }
+        // pose_world_landmarksは腰が原点なので、root_motionでない場合は腰の位置を基準姿勢の高さに固定する。
+        write_vec3(&mut w, hip)?;
+        for joint in order.iter() {
+            let (z, x, y) = rotations[*joint].to_euler_zxy();
+            write!(
+                w,
+                " {:.4} {:.4} {:.4}",
+                z.to_degrees(),
+                x.to_degrees(),
+                y.to_degrees()
+            )?;
+        }
+        writeln!(w)?;
+    }
+    w.flush()?;
+
+    Ok(MotionSummary {
+        path: path.to_string_lossy().to_string(),
+        frames: motion.len(),
+        frame_rate: fps,
+    })
+}
@@ -0,0 +1,342 @@
+// pandasやRで読めるように、ストリームごとのCSVファイルに書き出す。
+// wideは1フレーム1行で各ランドマークを列に並べる。
+// longは1ランドマーク1行で、landmark_index, landmarkの列で区別する。
+// ストリームが含まれていないフレームも行を出力し、値は空欄とする。
+// 列の数は記録の中で最も多いランドマークの数に合わせる(refine_landmarksの顔は478点)。
+// rootはcamera_paramsから求めた腰の中心の位置(position.rs)で、フレームに含まれていない場合は求めて書き出す。
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::export::{suffixed_path, ExportError};
+use crate::frames::TrackingFrame;
+use crate::holistic::{Landmark, LandmarkStream, ROOT_STREAM};
+use crate::position::root_translation;
+use crate::store::FrameSource;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvLayout {
+    #[default]
+    Wide,
+    Long,
+}
+
+// 書き出すストリーム
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CsvStream {
+    Pose,
+    PoseWorld,
+    Face,
+    LeftHand,
+    RightHand,
+    Gravity,
+    Root,
+}
+
+impl CsvStream {
+    pub const ALL: [CsvStream; 7] = [
+        CsvStream::Pose,
+        CsvStream::PoseWorld,
+        CsvStream::Face,
+        CsvStream::LeftHand,
+        CsvStream::RightHand,
+        CsvStream::Gravity,
+        CsvStream::Root,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self.landmarks() {
+            Some(stream) => stream.name(),
+            None if *self == CsvStream::Gravity => "gravity",
+            None => ROOT_STREAM,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CsvStream::ALL
+            .into_iter()
+            .find(|stream| stream.name() == name)
+    }
+
+    pub fn landmarks(&self) -> Option<LandmarkStream> {
+        match self {
+            CsvStream::Pose => Some(LandmarkStream::Pose),
+            CsvStream::PoseWorld => Some(LandmarkStream::PoseWorld),
+            CsvStream::Face => Some(LandmarkStream::Face),
+            CsvStream::LeftHand => Some(LandmarkStream::LeftHand),
+            CsvStream::RightHand => Some(LandmarkStream::RightHand),
+            CsvStream::Gravity | CsvStream::Root => None,
+        }
+    }
+
+    // タイムスタンプの列名(jsonのキー名と同じ)
+    // rootはpose_landmarksから求めるので、pose_landmarks_stampを使う。
+    fn stamp_column(&self) -> String {
+        match self {
+            CsvStream::Gravity => "gravity_stamp".to_string(),
+            CsvStream::Root => "pose_landmarks_stamp".to_string(),
+            _ => format!("{}_landmarks_stamp", self.name()),
+        }
+    }
+
+    fn stamp(&self, tf: &TrackingFrame) -> Option<u64> {
+        match self {
+            CsvStream::Gravity => tf.frame.gravity_stamp,
+            CsvStream::Root => tf.frame.pose_landmarks_stamp,
+            _ => self.landmarks().and_then(|stream| tf.frame.stamp(stream)),
+        }
+    }
+
+    // 出力するランドマークの名前と、そのランドマークが持つ値の名前
+    // countは記録に含まれる最大のランドマークの数
+    fn columns(&self, count: usize) -> (Vec<String>, &'static [&'static str]) {
+        match self.landmarks() {
+            Some(stream) => {
+                let names = (0..stream.landmark_count().max(count))
+                    .map(|i| stream.landmark_name(i))
+                    .collect();
+                if stream.has_visibility() {
+                    (names, &["x", "y", "z", "visibility", "presence"])
+                } else {
+                    (names, &["x", "y", "z"])
+                }
+            }
+            None => (vec![self.name().to_string()], &["x", "y", "z"]),
+        }
+    }
+
+    // i番目のランドマークの値。ストリームがない場合はNone
+    fn values(&self, tf: &TrackingFrame, i: usize) -> Option<[Option<f64>; 5]> {
+        match self.landmarks() {
+            Some(stream) => {
+                let landmark: &Landmark = tf.frame.landmarks(stream)?.get(i)?;
+                Some([
+                    Some(landmark.x),
+                    Some(landmark.y),
+                    Some(landmark.z),
+                    landmark.visibility,
+                    landmark.presence,
+                ])
+            }
+            None => {
+                let v = match self {
+                    CsvStream::Root => root_translation(&tf.frame)?,
+                    _ => tf.frame.gravity?,
+                };
+                Some([Some(v[0]), Some(v[1]), Some(v[2]), None, None])
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct CsvOptions {
+    pub layout: CsvLayout,
+    // 空の場合はすべてのストリームを書き出す。
+    pub streams: Vec<CsvStream>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            layout: CsvLayout::Wide,
+            streams: CsvStream::ALL.to_vec(),
+        }
+    }
+}
+
+struct CsvFile {
+    stream: CsvStream,
+    names: Vec<String>,
+    fields: &'static [&'static str],
+    writer: BufWriter<File>,
+}
+
+impl CsvFile {
+    fn create(path: &Path, stream: CsvStream, layout: CsvLayout, count: usize) -> io::Result<Self> {
+        let (names, fields) = stream.columns(count);
+        let mut writer = BufWriter::new(File::create(path)?);
+        write!(
+            writer,
+            "frame,timestamp,device_id,{}",
+            stream.stamp_column()
+        )?;
+        match layout {
+            CsvLayout::Wide => {
+                for name in names.iter() {
+                    for field in fields.iter() {
+                        write!(writer, ",{}_{}", name, field)?;
+                    }
+                }
+            }
+            CsvLayout::Long => {
+                write!(writer, ",landmark_index,landmark")?;
+                for field in fields.iter() {
+                    write!(writer, ",{}", field)?;
+                }
+            }
+        }
+        writeln!(writer)?;
+        Ok(Self {
+            stream,
+            names,
+            fields,
+            writer,
+        })
+    }
+
+    fn write_frame(&mut self, idx: usize, tf: &TrackingFrame, layout: CsvLayout) -> io::Result<()> {
+        let prefix = format!(
+            "{},{},{},{}",
+            idx,
+            tf.timestamp,
+            escape(tf.frame.device_id.as_deref().unwrap_or("")),
+            optional(self.stream.stamp(tf)),
+        );
+        match layout {
+            CsvLayout::Wide => {
+                write!(self.writer, "{}", prefix)?;
+                for i in 0..self.names.len() {
+                    let values = self.stream.values(tf, i).unwrap_or_default();
+                    for value in values.iter().take(self.fields.len()) {
+                        write!(self.writer, ",{}", optional(*value))?;
+                    }
+                }
+                writeln!(self.writer)?;
+            }
+            CsvLayout::Long => {
+                for (i, name) in self.names.iter().enumerate() {
+                    write!(self.writer, "{},{},{}", prefix, i, name)?;
+                    let values = self.stream.values(tf, i).unwrap_or_default();
+                    for value in values.iter().take(self.fields.len()) {
+                        write!(self.writer, ",{}", optional(*value))?;
+                    }
+                    writeln!(self.writer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+// カンマや引用符を含む場合だけ引用符で囲む。
+fn escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// ストリームごとの、記録に含まれる最大のランドマークの数
+// 列を決めるために、書き出す前に一度すべてのフレームを読む。
+fn landmark_counts<S: FrameSource + ?Sized>(frames: &S, streams: &[CsvStream]) -> Vec<usize> {
+    let mut counts = vec![0; streams.len()];
+    if streams.iter().all(|stream| stream.landmarks().is_none()) {
+        return counts;
+    }
+    for idx in 0..frames.len() {
+        let tf = match frames.frame(idx) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        for (stream, count) in streams.iter().zip(counts.iter_mut()) {
+            let len = stream
+                .landmarks()
+                .and_then(|stream| tf.frame.landmarks(stream))
+                .map_or(0, |landmarks| landmarks.len());
+            *count = (*count).max(len);
+        }
+    }
+    counts
+}
+
+// pathにストリーム名を付けたファイルに書き出し、書き出したファイルの一覧を返す。
+// 例: export.csv -> export_pose.csv, export_face.csv, ...
+pub fn export_csv<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &CsvOptions,
+) -> Result<Vec<PathBuf>, ExportError> {
+    if frames.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    let streams = if options.streams.is_empty() {
+        CsvStream::ALL.to_vec()
+    } else {
+        options.streams.clone()
+    };
+
+    let counts = landmark_counts(frames, &streams);
+    let mut paths = Vec::new();
+    let mut files = Vec::new();
+    for (stream, count) in streams.into_iter().zip(counts) {
+        let stream_path = suffixed_path(path, stream.name());
+        files.push(CsvFile::create(
+            &stream_path,
+            stream,
+            options.layout,
+            count,
+        )?);
+        paths.push(stream_path);
+    }
+
+    // ファイルから読む場合も各フレームを一度だけ読むように、フレームごとに全ファイルへ書く。
+    for idx in 0..frames.len() {
+        let tf = match frames.frame(idx) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        for file in files.iter_mut() {
+            file.write_frame(idx, &tf, options.layout)?;
+        }
+    }
+    for file in files.iter_mut() {
+        file.writer.flush()?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::holistic::HolisticFrame;
+
+    #[test]
+    fn face_columns_follow_refined_landmarks() {
+        let face = |n: usize| {
+            let frame = HolisticFrame {
+                face_landmarks: Some(vec![Landmark::default(); n]),
+                ..Default::default()
+            };
+            TrackingFrame {
+                json_str: String::new(),
+                frame,
+                timestamp: 0,
+            }
+        };
+        let frames = vec![face(468), face(478)];
+        let path = std::env::temp_dir().join(format!("mru_csv_{}.csv", std::process::id()));
+        let options = CsvOptions {
+            layout: CsvLayout::Wide,
+            streams: vec![CsvStream::Face],
+        };
+        let paths = export_csv(&frames, &path, &options).unwrap();
+        let text = std::fs::read_to_string(&paths[0]).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with(",point_477_z"));
+        // 468点のフレームは残りの列を空欄にする。
+        assert_eq!(lines[1].split(',').count(), 4 + 478 * 3);
+        assert_eq!(lines[2].split(',').count(), 4 + 478 * 3);
+        assert!(lines[1].ends_with(",,,"));
+        std::fs::remove_file(&paths[0]).unwrap();
+    }
+}
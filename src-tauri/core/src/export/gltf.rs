@@ -0,0 +1,399 @@
+// pose_world_landmarksから求めた骨格のアニメーションをglTF 2.0のバイナリ(GLB)に書き出す。
+// 関節をノードとしたスキンと、骨の形の簡単なメッシュを含むので、
+// 一般的なglTFビューワでそのまま動きを確認できる。
+// キーフレームの時刻はTrackingFrame::timestampをそのまま使い、補間はビューワに任せる。
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::export::{body_samples, hip_positions, ExportError, MotionSummary};
+use crate::math::{Quat, Vec3};
+use crate::skeleton::{Skeleton, HIPS};
+use crate::store::FrameSource;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+// accessorのcomponentType
+const FLOAT: u32 = 5126;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct GltfOptions {
+    // hand_landmarksから指の関節も書き出す。
+    pub fingers: bool,
+    // camera_paramsから求めた腰の移動を書き出す。falseの場合は腰の位置を固定する。
+    pub root_motion: bool,
+}
+
+// バイナリチャンクとbufferView, accessorをまとめて作るための入れ物
+#[derive(Default)]
+struct GlbBuilder {
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl GlbBuilder {
+    // dataをbufferViewとして追加し、そのaccessorの番号を返す。
+    fn push(&mut self, data: &[u8], accessor: Value, target: Option<u32>) -> usize {
+        // bufferViewの先頭は4バイト境界に揃える。
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(data);
+        self.buffer_views.push(view);
+
+        let mut accessor = accessor;
+        accessor["bufferView"] = json!(self.buffer_views.len() - 1);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_floats(&mut self, values: &[f32], kind: &str, minmax: bool) -> usize {
+        let width = match kind {
+            "SCALAR" => 1,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => 16,
+        };
+        let mut accessor = json!({
+            "componentType": FLOAT,
+            "count": values.len() / width,
+            "type": kind,
+        });
+        if minmax {
+            let mut min = vec![f32::MAX; width];
+            let mut max = vec![f32::MIN; width];
+            for chunk in values.chunks(width) {
+                for (i, v) in chunk.iter().enumerate() {
+                    min[i] = min[i].min(*v);
+                    max[i] = max[i].max(*v);
+                }
+            }
+            accessor["min"] = json!(min);
+            accessor["max"] = json!(max);
+        }
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.push(&data, accessor, None)
+    }
+}
+
+// 骨の形のメッシュ。関節から子の関節(先端)に向かう四角錐とし、その関節に100%の重みを付ける。
+struct BoneMesh {
+    positions: Vec<f32>,
+    joints: Vec<u16>,
+    weights: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl BoneMesh {
+    fn new(skeleton: &Skeleton, rest: &[Vec3]) -> Self {
+        let mut mesh = BoneMesh {
+            positions: Vec::new(),
+            joints: Vec::new(),
+            weights: Vec::new(),
+            indices: Vec::new(),
+        };
+        for (j, joint) in skeleton.joints.iter().enumerate() {
+            let mut tips: Vec<Vec3> = skeleton
+                .joints
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.parent == Some(j))
+                .map(|(c, _)| rest[c])
+                .collect();
+            if let Some(end_site) = joint.end_site {
+                tips.push(rest[j] + end_site);
+            }
+            for tip in tips {
+                mesh.push_bone(j as u16, rest[j], tip);
+            }
+        }
+        mesh
+    }
+
+    fn push_bone(&mut self, joint: u16, from: Vec3, to: Vec3) {
+        let axis = match (to - from).normalize() {
+            Some(axis) => axis,
+            None => return,
+        };
+        let length = (to - from).length();
+        let width = (length * 0.1).min(0.02);
+        // 骨と直交する2方向
+        let u = axis
+            .cross(Vec3::Y)
+            .normalize()
+            .or_else(|| axis.cross(Vec3::X).normalize())
+            .unwrap_or(Vec3::Z);
+        let v = axis.cross(u);
+        let base = from + axis * (length * 0.2);
+        let first = (self.positions.len() / 3) as u32;
+        let points = [
+            from,
+            base + u * width,
+            base + v * width,
+            base - u * width,
+            base - v * width,
+            to,
+        ];
+        for p in points {
+            self.positions
+                .extend_from_slice(&[p.x as f32, p.y as f32, p.z as f32]);
+            self.joints.extend_from_slice(&[joint, 0, 0, 0]);
+            self.weights.extend_from_slice(&[1.0, 0.0, 0.0, 0.0]);
+        }
+        for i in 0..4 {
+            let a = first + 1 + i;
+            let b = first + 1 + (i + 1) % 4;
+            self.indices
+                .extend_from_slice(&[first, b, a, a, b, first + 5]);
+        }
+    }
+}
+
+pub fn export_gltf<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &GltfOptions,
+) -> Result<MotionSummary, ExportError> {
+    let samples = body_samples(frames);
+    if samples.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    let skeleton = Skeleton::new(&samples, options.fingers);
+    let n = skeleton.joints.len();
+    let root_position = Vec3::new(0.0, skeleton.hip_height, 0.0);
+
+    // 基準姿勢での各関節の位置
+    let mut rest = vec![Vec3::ZERO; n];
+    for (j, joint) in skeleton.joints.iter().enumerate() {
+        rest[j] = match joint.parent {
+            Some(parent) => rest[parent] + joint.offset,
+            None => root_position,
+        };
+    }
+
+    let mut glb = GlbBuilder::default();
+
+    // メッシュとスキン
+    let mesh = BoneMesh::new(&skeleton, &rest);
+    let position = glb.push_floats(&mesh.positions, "VEC3", true);
+    let joints_data: Vec<u8> = mesh.joints.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let joints = glb.push(
+        &joints_data,
+        json!({
+            "componentType": UNSIGNED_SHORT,
+            "count": mesh.joints.len() / 4,
+            "type": "VEC4",
+        }),
+        Some(34962),
+    );
+    let weights = glb.push_floats(&mesh.weights, "VEC4", false);
+    let indices_data: Vec<u8> = mesh.indices.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let indices = glb.push(
+        &indices_data,
+        json!({
+            "componentType": UNSIGNED_INT,
+            "count": mesh.indices.len(),
+            "type": "SCALAR",
+        }),
+        Some(34963),
+    );
+    // 基準姿勢では回転がないので、逆バインド行列は平行移動だけになる。
+    let inverse_bind: Vec<f32> = rest
+        .iter()
+        .flat_map(|p| {
+            [
+                1.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+                0.0,
+                0.0,
+                0.0,
+                0.0,
+                1.0,
+                0.0,
+                -p.x as f32,
+                -p.y as f32,
+                -p.z as f32,
+                1.0,
+            ]
+        })
+        .collect();
+    let inverse_bind = glb.push_floats(&inverse_bind, "MAT4", false);
+
+    // アニメーション
+    // 決められなかった関節は前のフレームの回転のままにする。
+    // また、ビューワが遠回りに補間しないようにクォータニオンの符号を揃える。
+    let first = samples[0].timestamp;
+    let times: Vec<f32> = samples
+        .iter()
+        .map(|s| (s.timestamp - first) as f32 * 1e-6)
+        .collect();
+    let mut rotations: Vec<Vec<f32>> = vec![Vec::with_capacity(samples.len() * 4); n];
+    let mut current = vec![Quat::IDENTITY; n];
+    for sample in samples.iter() {
+        for (j, solved) in skeleton.solve(sample).into_iter().enumerate() {
+            if let Some(mut q) = solved {
+                if q.dot(current[j]) < 0.0 {
+                    q = Quat {
+                        w: -q.w,
+                        x: -q.x,
+                        y: -q.y,
+                        z: -q.z,
+                    };
+                }
+                current[j] = q;
+            }
+            let q = current[j];
+            rotations[j].extend_from_slice(&[q.x as f32, q.y as f32, q.z as f32, q.w as f32]);
+        }
+    }
+    let input = glb.push_floats(&times, "SCALAR", true);
+    let mut samplers = Vec::new();
+    let mut channels = Vec::new();
+    for (j, rotation) in rotations.iter().enumerate() {
+        let output = glb.push_floats(rotation, "VEC4", false);
+        samplers.push(json!({
+            "input": input,
+            "output": output,
+            "interpolation": "LINEAR",
+        }));
+        channels.push(json!({
+            "sampler": j,
+            "target": { "node": j, "path": "rotation" },
+        }));
+    }
+    if options.root_motion {
+        let hips: Vec<f32> = hip_positions(&samples, skeleton.hip_height, true)
+            .iter()
+            .flat_map(|p| [p.x as f32, p.y as f32, p.z as f32])
+            .collect();
+        let output = glb.push_floats(&hips, "VEC3", false);
+        samplers.push(json!({
+            "input": input,
+            "output": output,
+            "interpolation": "LINEAR",
+        }));
+        channels.push(json!({
+            "sampler": samplers.len() - 1,
+            "target": { "node": HIPS, "path": "translation" },
+        }));
+    }
+
+    // ノード。関節の後ろにメッシュのノードを置く。
+    let mut nodes: Vec<Value> = skeleton
+        .joints
+        .iter()
+        .enumerate()
+        .map(|(j, joint)| {
+            let translation = match joint.parent {
+                Some(_) => joint.offset,
+                None => root_position,
+            };
+            let mut node = json!({
+                "name": joint.name,
+                "translation": [translation.x, translation.y, translation.z],
+            });
+            let children: Vec<usize> = skeleton
+                .joints
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.parent == Some(j))
+                .map(|(c, _)| c)
+                .collect();
+            if !children.is_empty() {
+                node["children"] = json!(children);
+            }
+            node
+        })
+        .collect();
+    nodes.push(json!({ "name": "Skeleton", "mesh": 0, "skin": 0 }));
+
+    let gltf = json!({
+        "asset": { "version": "2.0", "generator": "mediapipe_receiver_utility" },
+        "scene": 0,
+        "scenes": [{ "nodes": [HIPS, n] }],
+        "nodes": nodes,
+        "meshes": [{
+            "name": "Skeleton",
+            "primitives": [{
+                "attributes": {
+                    "POSITION": position,
+                    "JOINTS_0": joints,
+                    "WEIGHTS_0": weights,
+                },
+                "indices": indices,
+            }],
+        }],
+        "skins": [{
+            "joints": (0..n).collect::<Vec<usize>>(),
+            "inverseBindMatrices": inverse_bind,
+            "skeleton": HIPS,
+        }],
+        "animations": [{
+            "name": "mediapipe",
+            "samplers": samplers,
+            "channels": channels,
+        }],
+        "buffers": [{ "byteLength": glb.bin.len() }],
+        "bufferViews": glb.buffer_views,
+        "accessors": glb.accessors,
+    });
+
+    write_glb(path, &gltf, &glb.bin)?;
+
+    let duration = *times.last().unwrap_or(&0.0) as f64;
+    Ok(MotionSummary {
+        path: path.to_string_lossy().to_string(),
+        frames: samples.len(),
+        frame_rate: if duration > 0.0 {
+            (samples.len() - 1) as f64 / duration
+        } else {
+            0.0
+        },
+    })
+}
+
+// JSONチャンクは空白で、バイナリチャンクは0で4バイト境界に揃える。
+fn write_glb(path: &Path, gltf: &Value, bin: &[u8]) -> std::io::Result<()> {
+    let mut json_chunk = serde_json::to_vec(gltf)?;
+    while !json_chunk.len().is_multiple_of(4) {
+        json_chunk.push(b' ');
+    }
+    let mut bin_chunk = bin.to_vec();
+    while !bin_chunk.len().is_multiple_of(4) {
+        bin_chunk.push(0);
+    }
+    let total = 12 + 8 + json_chunk.len() + 8 + bin_chunk.len();
+
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(GLB_MAGIC)?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(total as u32).to_le_bytes())?;
+    w.write_all(&(json_chunk.len() as u32).to_le_bytes())?;
+    w.write_all(&CHUNK_JSON.to_le_bytes())?;
+    w.write_all(&json_chunk)?;
+    w.write_all(&(bin_chunk.len() as u32).to_le_bytes())?;
+    w.write_all(&CHUNK_BIN.to_le_bytes())?;
+    w.write_all(&bin_chunk)?;
+    w.flush()
+}
@@ -0,0 +1,200 @@
+// 読み込んだ記録を他のツールで扱える形式に書き出す。
+
+pub mod bvh;
+pub mod csv;
+pub mod gltf;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::math::Vec3;
+use crate::skeleton::BodySample;
+use crate::store::FrameSource;
+
+// 書き出しに失敗した場合のエラー
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExportError {
+    // ファイルダイアログがキャンセルされた
+    Cancelled,
+    // 書き出すフレームがない
+    Empty,
+    Io { reason: String },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Cancelled => write!(f, "cancelled"),
+            ExportError::Empty => write!(f, "no frames to export"),
+            ExportError::Io { reason } => write!(f, "io error: {}", reason),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(why: std::io::Error) -> Self {
+        ExportError::Io {
+            reason: why.to_string(),
+        }
+    }
+}
+
+// 出力ファイル名にsuffixを付ける。
+// 例: export.csv, pose -> export_pose.csv
+pub fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, suffix),
+    };
+    path.with_file_name(file_name)
+}
+
+// 骨格のアニメーションを書き出した結果
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct MotionSummary {
+    pub path: String,
+    pub frames: usize,
+    pub frame_rate: f64,
+}
+
+// pose_world_landmarksを含むフレームを集める。
+// glTFのアニメーションの時刻は狭義単調増加でなければならないので、
+// タイムスタンプが前のフレーム以下のフレームは使わない。
+// 複数端末を一つのファイルに記録した場合は、最初の端末のフレームだけを使う。
+pub(crate) fn body_samples<S: FrameSource + ?Sized>(frames: &S) -> Vec<BodySample> {
+    let mut samples: Vec<BodySample> = Vec::new();
+    let mut device_id: Option<Option<String>> = None;
+    let mut other_devices = 0;
+    for idx in 0..frames.len() {
+        let tf = match frames.frame(idx) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        if let Some(sample) = BodySample::from_frame(&tf.frame, tf.timestamp) {
+            if *device_id.get_or_insert_with(|| tf.frame.device_id.clone()) != tf.frame.device_id {
+                other_devices += 1;
+                continue;
+            }
+            if samples
+                .last()
+                .is_some_and(|last| sample.timestamp <= last.timestamp)
+            {
+                continue;
+            }
+            samples.push(sample);
+        }
+    }
+    if other_devices > 0 {
+        println!(
+            "export: using device {:?}, skipped {} frames of other devices.",
+            device_id.flatten().unwrap_or_default(),
+            other_devices
+        );
+    }
+    samples
+}
+
+// pose_landmarks_stampの間隔の中央値からフレームレートを求める。
+pub(crate) fn frame_rate(samples: &[BodySample]) -> f64 {
+    let mut deltas: Vec<u64> = samples
+        .windows(2)
+        .map(|pair| pair[1].timestamp - pair[0].timestamp)
+        .filter(|delta| *delta > 0)
+        .collect();
+    if deltas.is_empty() {
+        return 30.0;
+    }
+    deltas.sort_unstable();
+    1e6 / deltas[deltas.len() / 2] as f64
+}
+
+// 腰の位置。root_motionの場合は基準姿勢の高さに、最初のフレームからの腰の移動量を足す。
+// 位置が求められなかったフレームは前のフレームの位置のままにする。
+pub(crate) fn hip_positions(
+    samples: &[BodySample],
+    hip_height: f64,
+    root_motion: bool,
+) -> Vec<Vec3> {
+    let rest = Vec3::new(0.0, hip_height, 0.0);
+    if !root_motion {
+        return vec![rest; samples.len()];
+    }
+    let origin = samples.iter().find_map(|sample| sample.root);
+    let mut current = rest;
+    samples
+        .iter()
+        .map(|sample| {
+            if let (Some(root), Some(origin)) = (sample.root, origin) {
+                current = rest + (root - origin);
+            }
+            current
+        })
+        .collect()
+}
+
+// 一定のフレームレートで補間したサンプルを返す。
+pub(crate) fn resample_body(samples: &[BodySample], frame_rate: f64) -> Vec<BodySample> {
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => (first.timestamp, last.timestamp),
+        _ => return Vec::new(),
+    };
+    let frame_time = 1e6 / frame_rate;
+    let count = ((last - first) as f64 / frame_time).floor() as usize + 1;
+    (0..count)
+        .map(|i| {
+            let t = first + (i as f64 * frame_time).round() as u64;
+            let n = samples.partition_point(|s| s.timestamp <= t).max(1);
+            let a = &samples[n - 1];
+            match samples.get(n) {
+                Some(b) if b.timestamp > a.timestamp => {
+                    let alpha = (t - a.timestamp) as f64 / (b.timestamp - a.timestamp) as f64;
+                    a.lerp(b, alpha)
+                }
+                _ => a.clone(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frames::TrackingFrame;
+    use crate::holistic::{HolisticFrame, Landmark};
+
+    fn frame(device_id: &str, timestamp: u64) -> TrackingFrame {
+        let frame = HolisticFrame {
+            device_id: Some(device_id.to_string()),
+            pose_world_landmarks: Some(vec![Landmark::default(); 33]),
+            ..Default::default()
+        };
+        TrackingFrame {
+            json_str: String::new(),
+            frame,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn body_samples_are_strictly_increasing_for_one_device() {
+        let frames = vec![
+            frame("a", 1000),
+            frame("b", 1500),
+            frame("a", 2000),
+            frame("a", 2000),
+            frame("b", 2500),
+            frame("a", 1800),
+            frame("a", 3000),
+        ];
+        let stamps: Vec<u64> = body_samples(&frames)
+            .iter()
+            .map(|sample| sample.timestamp)
+            .collect();
+        assert_eq!(stamps, vec![1000, 2000, 3000]);
+    }
+}
@@ -0,0 +1,470 @@
+// ランドマークの時間方向の平滑化。
+// mediapipeのランドマークは細かく揺れるので、送信先ごとに平滑化しなくて済むように、
+// 受信・再生したフレームをFrameSinkに渡す前にフィルタをかけられるようにする。
+//
+// フィルタはストリームごと、ランドマークごと、x, y, zごとに独立にかける。
+// 時間の間隔には受信時刻ではなく、各ストリームの*_stampの差を使う。
+// タイムスタンプが逆行した場合(シーク、ループなど)や大きく空いた場合は、フィルタを初期化する。
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportError;
+use crate::holistic::{HolisticFrame, Landmark, LandmarkStream};
+use crate::sink::{FrameEvent, FrameSink};
+use crate::store::FrameSource;
+
+// タイムスタンプがこれ以上空いた場合はフィルタを初期化する[usec]
+const RESET_GAP: u64 = 1_000_000;
+
+// One Euro Filter
+// https://gery.casiez.net/1euro/
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OneEuroParams {
+    // 止まっているときのカットオフ周波数[Hz]。小さいほど揺れが減るが遅れる。
+    pub min_cutoff: f64,
+    // 速度に応じてカットオフ周波数を上げる係数。大きいほど速い動きに追従する。
+    pub beta: f64,
+    // 速度を求めるときのカットオフ周波数[Hz]
+    pub d_cutoff: f64,
+}
+
+impl Default for OneEuroParams {
+    fn default() -> Self {
+        Self {
+            min_cutoff: 1.0,
+            beta: 1.0,
+            d_cutoff: 1.0,
+        }
+    }
+}
+
+// 指数移動平均
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmaParams {
+    // 時定数[sec]。フレーム間隔dtに対して1 - exp(-dt / time_constant)の重みで更新する。
+    pub time_constant: f64,
+}
+
+impl Default for EmaParams {
+    fn default() -> Self {
+        Self { time_constant: 0.1 }
+    }
+}
+
+// 等速度モデルのカルマンフィルタ
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KalmanParams {
+    // 観測の誤差の標準偏差(ランドマークと同じ単位)
+    pub measurement_std: f64,
+    // 加速度の標準偏差(単位/sec^2)。大きいほど速い動きに追従する。
+    pub acceleration_std: f64,
+}
+
+impl Default for KalmanParams {
+    fn default() -> Self {
+        Self {
+            measurement_std: 0.01,
+            acceleration_std: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterKind {
+    OneEuro(OneEuroParams),
+    Ema(EmaParams),
+    Kalman(KalmanParams),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterOptions {
+    // Noneの場合はフィルタをかけない。
+    pub filter: Option<FilterKind>,
+    // フィルタをかけるストリーム。空の場合はすべてのストリームにかける。
+    pub streams: Vec<LandmarkStream>,
+}
+
+// 一つの値に対するフィルタの状態
+enum ScalarFilter {
+    OneEuro { x: f64, dx: f64 },
+    Ema { x: f64 },
+    // 位置、速度と、その共分散行列
+    Kalman { x: f64, v: f64, p: [f64; 3] },
+}
+
+// 時定数tauの一次ローパスフィルタの重み
+fn smoothing_factor(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+impl ScalarFilter {
+    fn new(kind: &FilterKind, x: f64) -> Self {
+        match kind {
+            FilterKind::OneEuro(_) => ScalarFilter::OneEuro { x, dx: 0.0 },
+            FilterKind::Ema(_) => ScalarFilter::Ema { x },
+            FilterKind::Kalman(params) => ScalarFilter::Kalman {
+                x,
+                v: 0.0,
+                // 最初は速度が分からないので、速度の分散を大きくしておく。
+                p: [params.measurement_std.powi(2), 0.0, 1.0],
+            },
+        }
+    }
+
+    fn value(&self) -> f64 {
+        match self {
+            ScalarFilter::OneEuro { x, .. } => *x,
+            ScalarFilter::Ema { x } => *x,
+            ScalarFilter::Kalman { x, .. } => *x,
+        }
+    }
+
+    // dtは前の値からの経過時間[sec]
+    fn update(&mut self, kind: &FilterKind, z: f64, dt: f64) -> f64 {
+        match (self, kind) {
+            (ScalarFilter::OneEuro { x, dx }, FilterKind::OneEuro(params)) => {
+                let a_d = smoothing_factor(params.d_cutoff, dt);
+                *dx += a_d * ((z - *x) / dt - *dx);
+                let cutoff = params.min_cutoff + params.beta * dx.abs();
+                let a = smoothing_factor(cutoff, dt);
+                *x += a * (z - *x);
+                *x
+            }
+            (ScalarFilter::Ema { x }, FilterKind::Ema(params)) => {
+                let a = 1.0 - (-dt / params.time_constant.max(f64::EPSILON)).exp();
+                *x += a * (z - *x);
+                *x
+            }
+            (ScalarFilter::Kalman { x, v, p }, FilterKind::Kalman(params)) => {
+                // 予測
+                *x += *v * dt;
+                let q = params.acceleration_std.powi(2);
+                let [p00, p01, p11] = *p;
+                let p00 = p00 + 2.0 * dt * p01 + dt * dt * p11 + q * dt.powi(4) / 4.0;
+                let p01 = p01 + dt * p11 + q * dt.powi(3) / 2.0;
+                let p11 = p11 + q * dt * dt;
+                // 観測で更新
+                let s = p00 + params.measurement_std.powi(2);
+                let (k0, k1) = (p00 / s, p01 / s);
+                let y = z - *x;
+                *x += k0 * y;
+                *v += k1 * y;
+                *p = [(1.0 - k0) * p00, (1.0 - k0) * p01, p11 - k1 * p01];
+                *x
+            }
+            // 種類が変わった場合はFilterSinkが状態を作り直すので、ここには来ない。
+            (filter, kind) => {
+                *filter = ScalarFilter::new(kind, z);
+                z
+            }
+        }
+    }
+}
+
+// ストリーム一つ分の状態
+struct StreamState {
+    stamp: u64,
+    // ランドマークごとのx, y, z
+    filters: Vec<[ScalarFilter; 3]>,
+}
+
+impl StreamState {
+    fn new(kind: &FilterKind, stamp: u64, landmarks: &[Landmark]) -> Self {
+        Self {
+            stamp,
+            filters: landmarks
+                .iter()
+                .map(|lm| {
+                    [
+                        ScalarFilter::new(kind, lm.x),
+                        ScalarFilter::new(kind, lm.y),
+                        ScalarFilter::new(kind, lm.z),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+// 送信元一つ分のフィルタ
+pub struct LandmarkFilter {
+    kind: FilterKind,
+    streams: Vec<LandmarkStream>,
+    states: HashMap<LandmarkStream, StreamState>,
+}
+
+impl LandmarkFilter {
+    pub fn new(kind: FilterKind, streams: &[LandmarkStream]) -> Self {
+        Self {
+            kind,
+            streams: if streams.is_empty() {
+                LandmarkStream::ALL.to_vec()
+            } else {
+                streams.to_vec()
+            },
+            states: HashMap::new(),
+        }
+    }
+
+    // フレームのランドマークを平滑化した値に置き換える。
+    // 前のフレームと同じstampのストリームは、フィルタを進めずに前の値を使う。
+    pub fn apply(&mut self, frame: &mut HolisticFrame) {
+        for stream in self.streams.iter().copied() {
+            let stamp = match frame.stamp(stream) {
+                Some(stamp) => stamp,
+                None => continue,
+            };
+            let landmarks = match frame.landmarks_mut(stream) {
+                Some(landmarks) => landmarks,
+                None => continue,
+            };
+            let state = match self.states.get_mut(&stream) {
+                Some(state)
+                    if stamp >= state.stamp
+                        && stamp - state.stamp <= RESET_GAP
+                        && state.filters.len() == landmarks.len() =>
+                {
+                    state
+                }
+                _ => {
+                    self.states
+                        .insert(stream, StreamState::new(&self.kind, stamp, landmarks));
+                    continue;
+                }
+            };
+            let dt = (stamp - state.stamp) as f64 / 1e6;
+            state.stamp = stamp;
+            for (lm, filters) in landmarks.iter_mut().zip(state.filters.iter_mut()) {
+                let values = [&mut lm.x, &mut lm.y, &mut lm.z];
+                for (value, filter) in values.into_iter().zip(filters.iter_mut()) {
+                    *value = if dt > 0.0 {
+                        filter.update(&self.kind, *value, dt)
+                    } else {
+                        filter.value()
+                    };
+                }
+            }
+        }
+    }
+}
+
+// 受信・再生の途中でフィルタの設定を変更するための入れ物
+// cloneしたものは同じ設定を共有するので、sinkを作った後でも変更が反映される。
+#[derive(Clone, Default)]
+pub struct FilterControl {
+    options: Arc<Mutex<FilterOptions>>,
+    // 設定を変更するたびに増やす。FilterSinkはこれを見てフィルタを作り直す。
+    generation: Arc<AtomicU64>,
+}
+
+impl FilterControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> FilterOptions {
+        self.options.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, options: FilterOptions) {
+        *self.options.lock().unwrap() = options;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // innerに渡す前にフィルタをかけるFrameSink
+    pub fn sink<S: FrameSink>(&self, inner: S) -> FilterSink<S> {
+        FilterSink {
+            control: self.clone(),
+            // 最初のフレームで設定を読むように、存在しない番号にしておく。
+            generation: u64::MAX,
+            options: FilterOptions::default(),
+            filters: HashMap::new(),
+            inner,
+        }
+    }
+}
+
+// 平滑化したフレームをinnerに渡すFrameSink
+// 複数の端末から受信している場合は、device_idごとに別のフィルタを使う。
+// フィルタをかけない設定の場合は、受け取ったフレームをそのまま渡す。
+pub struct FilterSink<S> {
+    control: FilterControl,
+    generation: u64,
+    options: FilterOptions,
+    filters: HashMap<Option<String>, LandmarkFilter>,
+    inner: S,
+}
+
+impl<S: FrameSink> FrameSink for FilterSink<S> {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        let generation = self.control.generation.load(Ordering::SeqCst);
+        if generation != self.generation {
+            self.generation = generation;
+            self.options = self.control.get();
+            self.filters.clear();
+        }
+        let kind = match &self.options.filter {
+            Some(kind) => kind,
+            None => return self.inner.on_frame(event),
+        };
+        let mut frame = match event.holistic() {
+            Some(frame) => frame.into_owned(),
+            None => return self.inner.on_frame(event),
+        };
+        let key = event.device_id().or_else(|| frame.device_id.clone());
+        self.filters
+            .entry(key)
+            .or_insert_with(|| LandmarkFilter::new(kind.clone(), &self.options.streams))
+            .apply(&mut frame);
+        let json_str = match frame.to_json_string() {
+            Ok(json_str) => json_str,
+            Err(why) => {
+                println!("filter: {}", why);
+                return self.inner.on_frame(event);
+            }
+        };
+        self.inner.on_frame(&FrameEvent {
+            json_str: &json_str,
+            frame: Some(&frame),
+            ..*event
+        });
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+// 読み込んだフレームにフィルタをかけて、1行1フレームのjsonとして書き出す。
+// 元のファイルは変更しない。書き出したフレーム数を返す。
+pub fn write_filtered<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &FilterOptions,
+) -> Result<usize, ExportError> {
+    if frames.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut filters: HashMap<Option<String>, LandmarkFilter> = HashMap::new();
+    let mut written = 0;
+    for idx in 0..frames.len() {
+        let tf = match frames.frame(idx) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        let line = match &options.filter {
+            Some(kind) => {
+                let mut frame = tf.frame.clone();
+                filters
+                    .entry(frame.device_id.clone())
+                    .or_insert_with(|| LandmarkFilter::new(kind.clone(), &options.streams))
+                    .apply(&mut frame);
+                frame.to_json_string().map_err(|why| ExportError::Io {
+                    reason: why.to_string(),
+                })?
+            }
+            None => tf.json_str.clone(),
+        };
+        writeln!(writer, "{}", line)?;
+        written += 1;
+    }
+    writer.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(stamp: u64, x: f64) -> HolisticFrame {
+        HolisticFrame {
+            pose_landmarks: Some(vec![Landmark {
+                x,
+                ..Default::default()
+            }]),
+            pose_landmarks_stamp: Some(stamp),
+            ..Default::default()
+        }
+    }
+
+    // 33msごとにxを与えて、フィルタ後の値を返す。
+    fn run(filter: &mut LandmarkFilter, xs: &[f64]) -> Vec<f64> {
+        xs.iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let mut frame = frame(i as u64 * 33_333, *x);
+                filter.apply(&mut frame);
+                frame.pose_landmarks.unwrap()[0].x
+            })
+            .collect()
+    }
+
+    fn kinds() -> [FilterKind; 3] {
+        [
+            FilterKind::OneEuro(OneEuroParams::default()),
+            FilterKind::Ema(EmaParams::default()),
+            FilterKind::Kalman(KalmanParams::default()),
+        ]
+    }
+
+    #[test]
+    fn constant_input_is_unchanged() {
+        for kind in kinds() {
+            let mut filter = LandmarkFilter::new(kind, &[]);
+            for x in run(&mut filter, &[0.5; 10]) {
+                assert!((x - 0.5).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn step_is_smoothed_and_followed() {
+        let mut xs = vec![0.0; 5];
+        xs.extend([1.0; 60]);
+        for kind in kinds() {
+            let mut filter = LandmarkFilter::new(kind.clone(), &[]);
+            let ys = run(&mut filter, &xs);
+            // 段差の直後は元の値より小さく、時間がたてば追従する。
+            assert!(ys[5] > 0.0 && ys[5] < 1.0, "{:?}: {}", kind, ys[5]);
+            assert!((ys[64] - 1.0).abs() < 0.05, "{:?}: {}", kind, ys[64]);
+        }
+    }
+
+    #[test]
+    fn reset_on_backward_stamp_and_hold_on_same_stamp() {
+        let mut filter = LandmarkFilter::new(FilterKind::Ema(EmaParams::default()), &[]);
+        run(&mut filter, &[0.0; 5]);
+        // 同じstampのフレームは前の値のまま
+        let mut same = frame(4 * 33_333, 1.0);
+        filter.apply(&mut same);
+        assert_eq!(same.pose_landmarks.unwrap()[0].x, 0.0);
+        // 逆行した場合は初期化して、そのフレームの値をそのまま使う。
+        let mut back = frame(0, 1.0);
+        filter.apply(&mut back);
+        assert_eq!(back.pose_landmarks.unwrap()[0].x, 1.0);
+    }
+
+    #[test]
+    fn only_selected_streams_are_filtered() {
+        let mut filter = LandmarkFilter::new(
+            FilterKind::Ema(EmaParams::default()),
+            &[LandmarkStream::Face],
+        );
+        let ys = run(&mut filter, &[0.0, 1.0]);
+        assert_eq!(ys, vec![0.0, 1.0]);
+    }
+}
@@ -0,0 +1,240 @@
+// 記録ファイルの一行(=1フレーム)と、その読み込み処理。
+// 壊れた行があってもアプリ全体が落ちないように、
+// 行ごとのエラーを集めてLoadSummaryとして返す。
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::compressed::{self, CompressedReader};
+use crate::holistic::HolisticFrame;
+use crate::metadata::SessionMetadata;
+
+// 複数行にわたるJSONを格納するための構造体
+// json_strは送信用にそのまま保持し、frameには型付きの内容を保持する。
+#[derive(Clone)]
+pub struct TrackingFrame {
+    pub json_str: String,
+    pub frame: HolisticFrame,
+    pub timestamp: u64,
+}
+
+impl TrackingFrame {
+    // JSONをHolisticFrameとして読み込み、
+    // pose_landmarks_stampをtimestampとする。
+    // pose_landmarks_stampがない場合、fallbackがtrueなら他の*_stampを使う。
+    // 戻り値の2番目はfallbackしたかどうか。
+    pub fn parse(json_str: String, fallback: bool) -> Result<(Self, bool), String> {
+        let frame = match HolisticFrame::from_json_str(&json_str) {
+            Ok(frame) => frame,
+            Err(why) => return Err(format!("invalid json: {}", why)),
+        };
+        let (timestamp, fell_back) = match frame.pose_landmarks_stamp {
+            Some(stamp) => (stamp, false),
+            None if fallback => match fallback_timestamp(&frame) {
+                Some(stamp) => (stamp, true),
+                None => return Err("no *_stamp field found".to_string()),
+            },
+            None => return Err("pose_landmarks_stamp not found".to_string()),
+        };
+        Ok((
+            Self {
+                json_str,
+                frame,
+                timestamp,
+            },
+            fell_back,
+        ))
+    }
+}
+
+// タイムスタンプだけを読むための構造体
+#[derive(serde::Deserialize)]
+struct StampOnly {
+    pose_landmarks_stamp: Option<u64>,
+}
+
+// 行全体をHolisticFrameとして読まずに、pose_landmarks_stampだけを取り出す。
+// jsonとして読めない場合はエラーを返す。
+pub(crate) fn peek_timestamp(json_str: &str) -> Result<Option<u64>, String> {
+    match serde_json::from_str::<StampOnly>(json_str) {
+        Ok(stamp) => Ok(stamp.pose_landmarks_stamp),
+        Err(why) => Err(format!("invalid json: {}", why)),
+    }
+}
+
+// pose_landmarks_stamp以外から代わりのタイムスタンプを探す。
+// gravity_stampだけはナノ秒なのでマイクロ秒に直す。
+pub(crate) fn fallback_timestamp(frame: &HolisticFrame) -> Option<u64> {
+    frame
+        .pose_world_landmarks_stamp
+        .or(frame.face_landmarks_stamp)
+        .or(frame.right_hand_landmarks_stamp)
+        .or(frame.left_hand_landmarks_stamp)
+        .or(frame.gravity_stamp.map(|stamp| stamp / 1000))
+}
+
+// 壊れた行を見つけたときの振る舞い
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadPolicy {
+    // その行を読み飛ばす
+    #[default]
+    Skip,
+    // 読み込みを中断してエラーを返す
+    Abort,
+    // pose_landmarks_stampがなければ他の*_stampを使って読み込む。
+    // それでも読めない行は読み飛ばす。
+    Fallback,
+}
+
+// 行ごとのエラー。lineは1始まり。
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct LineError {
+    pub line: usize,
+    pub reason: String,
+}
+
+// 読み込み結果の概要
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct LoadSummary {
+    pub path: String,
+    pub total_lines: usize,
+    pub loaded_frames: usize,
+    pub skipped_lines: usize,
+    pub fallback_frames: usize,
+    pub begin_timestamp: u64,
+    pub end_timestamp: u64,
+    pub errors: Vec<LineError>,
+    // trueの場合はメモリに読み込まず、ファイルから必要なフレームだけを読む。
+    pub streaming: bool,
+    // 記録時に保存したセッションの情報。サイドカーがない場合はNone
+    pub metadata: Option<SessionMetadata>,
+}
+
+// 読み込み自体が失敗した場合のエラー
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoadError {
+    // ファイルダイアログがキャンセルされた
+    Cancelled,
+    Io { reason: String },
+    // LoadPolicy::Abortで壊れた行が見つかった
+    Malformed { line: usize, reason: String },
+    // 読み込めるフレームが1つもなかった
+    Empty { errors: Vec<LineError> },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Cancelled => write!(f, "cancelled"),
+            LoadError::Io { reason } => write!(f, "io error: {}", reason),
+            LoadError::Malformed { line, reason } => write!(f, "line {}: {}", line, reason),
+            LoadError::Empty { errors } => {
+                write!(f, "no frames loaded ({} broken lines)", errors.len())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(why: std::io::Error) -> Self {
+        LoadError::Io {
+            reason: why.to_string(),
+        }
+    }
+}
+
+// 一行ずつ読み込んでTrackingFrameのVecを作成する。
+// 空行は数えるがエラーにはしない。
+pub fn load_frames<R: BufRead>(
+    reader: R,
+    policy: LoadPolicy,
+) -> Result<(Vec<TrackingFrame>, LoadSummary), LoadError> {
+    let mut tf_buf = Vec::new();
+    let mut summary = LoadSummary::default();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        summary.total_lines = line_no;
+        let s = match line {
+            Ok(s) => s,
+            Err(why) => {
+                // UTF-8として読めない行などはここでエラーになる。
+                if policy == LoadPolicy::Abort {
+                    return Err(LoadError::Malformed {
+                        line: line_no,
+                        reason: why.to_string(),
+                    });
+                }
+                summary.errors.push(LineError {
+                    line: line_no,
+                    reason: why.to_string(),
+                });
+                summary.skipped_lines += 1;
+                continue;
+            }
+        };
+        if s.trim().is_empty() {
+            summary.skipped_lines += 1;
+            continue;
+        }
+
+        match TrackingFrame::parse(s, policy == LoadPolicy::Fallback) {
+            Ok((tf, fell_back)) => {
+                if fell_back {
+                    summary.fallback_frames += 1;
+                }
+                tf_buf.push(tf);
+            }
+            Err(reason) => {
+                println!("load_frames: line {}: {}", line_no, reason);
+                if policy == LoadPolicy::Abort {
+                    return Err(LoadError::Malformed {
+                        line: line_no,
+                        reason,
+                    });
+                }
+                summary.errors.push(LineError {
+                    line: line_no,
+                    reason,
+                });
+                summary.skipped_lines += 1;
+            }
+        }
+    }
+
+    if tf_buf.is_empty() {
+        return Err(LoadError::Empty {
+            errors: summary.errors,
+        });
+    }
+
+    summary.loaded_frames = tf_buf.len();
+    summary.begin_timestamp = tf_buf[0].timestamp;
+    summary.end_timestamp = tf_buf[tf_buf.len() - 1].timestamp;
+
+    Ok((tf_buf, summary))
+}
+
+// 記録ファイルを改行区切りのjsonとして読むためのReaderを返す。
+// 圧縮形式の場合は展開しながら読む。
+pub fn open_reader(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    if compressed::is_compressed(path)? {
+        Ok(Box::new(BufReader::new(CompressedReader::open(path)?)))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+// ファイルを開いて読み込む。
+pub fn load_file(
+    path: &Path,
+    policy: LoadPolicy,
+) -> Result<(Vec<TrackingFrame>, LoadSummary), LoadError> {
+    let (tf_buf, mut summary) = load_frames(open_reader(path)?, policy)?;
+    summary.path = path.to_string_lossy().to_string();
+    Ok((tf_buf, summary))
+}
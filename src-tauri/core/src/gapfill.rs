@@ -0,0 +1,406 @@
+// 手や顔が認識されなかったフレーム(キー自体がない)や、visibilityが低いランドマークを補う。
+// 前後の記録された値からの線形補間か、直前の値の保持で補い、
+// 一定の時間より長い欠落はそのままにする。
+// 補ったフレームにはHolisticFrame::filledを付け、記録された値と区別できるようにする。
+//
+// 欠落の長さには各フレームのTrackingFrame::timestampの差を使う。
+// 補間の場合は欠落の前後に記録された値が必要なので、記録の最初と最後の欠落は補わない。
+// 複数端末を一つのファイルに記録した場合は、端末ごとに補う。
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::ExportError;
+use crate::frames::TrackingFrame;
+use crate::holistic::{FilledFlags, LandmarkStream};
+use crate::resample::lerp_landmarks;
+use crate::store::FrameSource;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapFillMethod {
+    // 前後の値から線形補間する。
+    #[default]
+    Interpolate,
+    // 直前の値をそのまま使う。
+    Hold,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GapFillOptions {
+    pub method: GapFillMethod,
+    // これより長い欠落は補わない[msec]
+    pub max_gap_ms: f64,
+    // visibilityがこれ未満のランドマークを補う。Noneの場合はランドマークごとには補わない。
+    // hand_landmarksのvisibilityは常に0なので、pose_landmarksとpose_world_landmarksだけが対象
+    pub min_visibility: Option<f64>,
+    // 補うストリーム。空の場合はすべて
+    pub streams: Vec<LandmarkStream>,
+}
+
+impl Default for GapFillOptions {
+    fn default() -> Self {
+        Self {
+            method: GapFillMethod::Interpolate,
+            max_gap_ms: 300.0,
+            min_visibility: Some(0.5),
+            streams: Vec::new(),
+        }
+    }
+}
+
+// 補った結果
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GapFillSummary {
+    pub path: String,
+    pub frames: usize,
+    // ストリーム全体を補った数(フレーム数のストリームごとの合計)
+    pub filled_streams: usize,
+    // visibilityが低いために補ったランドマークの数
+    pub filled_landmarks: usize,
+}
+
+// 補う値の求め方
+// k番目をa番目とb番目の間のalphaの位置で補間する。保持の場合はb == a
+struct Fill {
+    k: usize,
+    a: usize,
+    b: usize,
+    alpha: f64,
+}
+
+// goodでない位置ごとに、補い方を決める。
+// 欠落の長さは直前の記録された値から次の記録された値(最後の欠落は記録の最後)までで、
+// max_gapより長い欠落は補間・保持のどちらでも全体を補わない。
+fn plan(times: &[u64], good: &[bool], max_gap: u64, method: GapFillMethod) -> Vec<Fill> {
+    let mut fills = Vec::new();
+    let mut last_good: Option<usize> = None;
+    let mut pending: Vec<usize> = Vec::new();
+    for (k, good) in good.iter().enumerate() {
+        if !good {
+            if last_good.is_some() {
+                pending.push(k);
+            }
+            continue;
+        }
+        if let Some(a) = last_good {
+            fill_run(times, a, Some(k), &mut pending, max_gap, method, &mut fills);
+        }
+        last_good = Some(k);
+    }
+    // 最後の欠落は、次の値がないので保持の場合だけ補う。
+    if let Some(a) = last_good {
+        fill_run(times, a, None, &mut pending, max_gap, method, &mut fills);
+    }
+    fills
+}
+
+// a番目とb番目(Noneは記録の最後)の間の欠落pendingを補う。
+fn fill_run(
+    times: &[u64],
+    a: usize,
+    b: Option<usize>,
+    pending: &mut Vec<usize>,
+    max_gap: u64,
+    method: GapFillMethod,
+    fills: &mut Vec<Fill>,
+) {
+    let run: Vec<usize> = std::mem::take(pending);
+    let end = match (b, run.last()) {
+        (Some(b), _) => times[b],
+        (None, Some(last)) => times[*last],
+        (None, None) => return,
+    };
+    let ta = times[a];
+    if end < ta || end - ta > max_gap {
+        return;
+    }
+    for k in run {
+        let tk = times[k].clamp(ta, end);
+        match (method, b) {
+            (GapFillMethod::Interpolate, Some(b)) => {
+                let alpha = if end > ta {
+                    (tk - ta) as f64 / (end - ta) as f64
+                } else {
+                    0.0
+                };
+                fills.push(Fill { k, a, b, alpha });
+            }
+            (GapFillMethod::Interpolate, None) => {}
+            (GapFillMethod::Hold, _) => fills.push(Fill {
+                k,
+                a,
+                b: a,
+                alpha: 0.0,
+            }),
+        }
+    }
+}
+
+fn filled_mut(tf: &mut TrackingFrame) -> &mut FilledFlags {
+    tf.frame.filled.get_or_insert_with(Default::default)
+}
+
+// 端末一つ分のフレーム(framesの番号)の、visibilityが低いランドマークを補う。
+fn fill_landmarks(
+    frames: &mut [TrackingFrame],
+    indices: &[usize],
+    stream: LandmarkStream,
+    min_visibility: f64,
+    max_gap: u64,
+    method: GapFillMethod,
+) -> usize {
+    // ストリームを含むフレームだけを対象にする。
+    let present: Vec<usize> = indices
+        .iter()
+        .copied()
+        .filter(|idx| frames[*idx].frame.landmarks(stream).is_some())
+        .collect();
+    let times: Vec<u64> = present.iter().map(|idx| frames[*idx].timestamp).collect();
+    let mut filled = 0;
+    for j in 0..stream.landmark_count() {
+        let good: Vec<bool> = present
+            .iter()
+            .map(|idx| {
+                frames[*idx]
+                    .frame
+                    .landmarks(stream)
+                    .and_then(|landmarks| landmarks.get(j))
+                    .is_some_and(|l| l.visibility.is_none_or(|v| v >= min_visibility))
+            })
+            .collect();
+        for fill in plan(&times, &good, max_gap, method) {
+            let point = |n: usize| {
+                frames[present[n]]
+                    .frame
+                    .landmarks(stream)
+                    .and_then(|landmarks| landmarks.get(j))
+                    .cloned()
+            };
+            let (a, b) = match (point(fill.a), point(fill.b)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let tf = &mut frames[present[fill.k]];
+            let landmark = match tf
+                .frame
+                .landmarks_mut(stream)
+                .as_mut()
+                .and_then(|landmarks| landmarks.get_mut(j))
+            {
+                Some(landmark) => landmark,
+                None => continue,
+            };
+            // visibilityは記録された値のままにする。
+            landmark.x = a.x + (b.x - a.x) * fill.alpha;
+            landmark.y = a.y + (b.y - a.y) * fill.alpha;
+            landmark.z = a.z + (b.z - a.z) * fill.alpha;
+            filled_mut(tf).landmarks.entry(stream).or_default().push(j);
+            filled += 1;
+        }
+    }
+    filled
+}
+
+// 端末一つ分のフレームの、ストリームがないフレームを補う。
+fn fill_stream(
+    frames: &mut [TrackingFrame],
+    indices: &[usize],
+    stream: LandmarkStream,
+    max_gap: u64,
+    method: GapFillMethod,
+) -> usize {
+    let times: Vec<u64> = indices.iter().map(|idx| frames[*idx].timestamp).collect();
+    let good: Vec<bool> = indices
+        .iter()
+        .map(|idx| frames[*idx].frame.landmarks(stream).is_some())
+        .collect();
+    let mut filled = 0;
+    for fill in plan(&times, &good, max_gap, method) {
+        let (a, b) = (&frames[indices[fill.a]], &frames[indices[fill.b]]);
+        let (landmarks_a, landmarks_b) =
+            match (a.frame.landmarks(stream), b.frame.landmarks(stream)) {
+                (Some(la), Some(lb)) => (la, lb),
+                _ => continue,
+            };
+        let landmarks = lerp_landmarks(landmarks_a, landmarks_b, fill.alpha);
+        // タイムスタンプはフレームの間隔だけずらす。
+        let stamp_a = a.frame.stamp(stream).unwrap_or(a.timestamp);
+        let stamp = stamp_a + (times[fill.k] - times[fill.a]);
+        let tf = &mut frames[indices[fill.k]];
+        *tf.frame.landmarks_mut(stream) = Some(landmarks);
+        *tf.frame.stamp_mut(stream) = Some(stamp);
+        filled_mut(tf).streams.push(stream);
+        filled += 1;
+    }
+    filled
+}
+
+// 欠落を補ったフレームを返す。
+pub fn fill_gaps<S: FrameSource + ?Sized>(
+    frames: &S,
+    options: &GapFillOptions,
+) -> Result<(Vec<TrackingFrame>, GapFillSummary), ExportError> {
+    let mut filled: Vec<TrackingFrame> = (0..frames.len())
+        .filter_map(|idx| frames.frame(idx).map(|tf| tf.into_owned()))
+        .collect();
+    if filled.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    let mut devices: HashMap<Option<String>, Vec<usize>> = HashMap::new();
+    for (idx, tf) in filled.iter().enumerate() {
+        devices
+            .entry(tf.frame.device_id.clone())
+            .or_default()
+            .push(idx);
+    }
+    let streams = if options.streams.is_empty() {
+        LandmarkStream::ALL.to_vec()
+    } else {
+        options.streams.clone()
+    };
+    let max_gap = (options.max_gap_ms.max(0.0) * 1000.0) as u64;
+
+    let mut summary = GapFillSummary {
+        frames: filled.len(),
+        ..Default::default()
+    };
+    for indices in devices.values() {
+        for stream in streams.iter().copied() {
+            let landmark_level = matches!(stream, LandmarkStream::Pose | LandmarkStream::PoseWorld);
+            if let (Some(min_visibility), true) = (options.min_visibility, landmark_level) {
+                summary.filled_landmarks += fill_landmarks(
+                    &mut filled,
+                    indices,
+                    stream,
+                    min_visibility,
+                    max_gap,
+                    options.method,
+                );
+            }
+            summary.filled_streams +=
+                fill_stream(&mut filled, indices, stream, max_gap, options.method);
+        }
+    }
+
+    // 補ったフレームのjsonを作り直す。
+    for tf in filled.iter_mut() {
+        if tf.frame.filled.is_some() {
+            tf.json_str = tf.frame.to_json_string().map_err(|why| ExportError::Io {
+                reason: why.to_string(),
+            })?;
+        }
+    }
+    Ok((filled, summary))
+}
+
+// 欠落を補ったフレームを、1行1フレームのjsonとして書き出す。
+pub fn write_filled<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &GapFillOptions,
+) -> Result<GapFillSummary, ExportError> {
+    let (filled, mut summary) = fill_gaps(frames, options)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    for tf in filled.iter() {
+        writeln!(writer, "{}", tf.json_str)?;
+    }
+    writer.flush()?;
+    summary.path = path.to_string_lossy().to_string();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::holistic::{HolisticFrame, Landmark};
+
+    fn fills(fills: Vec<Fill>) -> Vec<(usize, usize, usize, f64)> {
+        fills
+            .into_iter()
+            .map(|fill| (fill.k, fill.a, fill.b, fill.alpha))
+            .collect()
+    }
+
+    const TIMES: [u64; 6] = [0, 100, 200, 300, 400, 500];
+
+    #[test]
+    fn plan_interpolate() {
+        let good = [true, false, false, true, false, false];
+        let planned = fills(plan(&TIMES, &good, 300, GapFillMethod::Interpolate));
+        assert_eq!(planned.len(), 2);
+        assert_eq!(planned[0].0, 1);
+        assert_eq!((planned[0].1, planned[0].2), (0, 3));
+        assert!((planned[0].3 - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(planned[1].0, 2);
+        // 前後の間隔がmax_gapより長い場合と、最後の欠落は補わない。
+        assert!(plan(&TIMES, &good, 200, GapFillMethod::Interpolate).is_empty());
+    }
+
+    #[test]
+    fn plan_hold() {
+        let good = [false, true, false, false, false, false];
+        // 最初の欠落は補わず、max_gapより長い欠落は全体を補わない。
+        assert!(plan(&TIMES, &good, 200, GapFillMethod::Hold).is_empty());
+        let planned = fills(plan(&TIMES, &good, 400, GapFillMethod::Hold));
+        assert_eq!(
+            planned,
+            vec![
+                (2, 1, 1, 0.0),
+                (3, 1, 1, 0.0),
+                (4, 1, 1, 0.0),
+                (5, 1, 1, 0.0)
+            ]
+        );
+        let good = [true, false, false, true, false, true];
+        let planned = fills(plan(&TIMES, &good, 200, GapFillMethod::Hold));
+        assert_eq!(planned, vec![(4, 3, 3, 0.0)]);
+    }
+
+    fn frame(timestamp: u64, hand: Option<f64>) -> TrackingFrame {
+        let frame = HolisticFrame {
+            pose_landmarks_stamp: Some(timestamp),
+            right_hand_landmarks: hand.map(|x| {
+                vec![
+                    Landmark {
+                        x,
+                        ..Default::default()
+                    };
+                    21
+                ]
+            }),
+            right_hand_landmarks_stamp: hand.map(|_| timestamp),
+            ..Default::default()
+        };
+        TrackingFrame {
+            json_str: String::new(),
+            frame,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn fill_missing_stream() {
+        let frames = vec![
+            frame(0, Some(0.0)),
+            frame(100_000, None),
+            frame(200_000, Some(1.0)),
+            frame(300_000, None),
+        ];
+        let (filled, summary) = fill_gaps(&frames, &GapFillOptions::default()).unwrap();
+        assert_eq!(summary.filled_streams, 1);
+        let hand = filled[1].frame.right_hand_landmarks.as_ref().unwrap();
+        assert!((hand[0].x - 0.5).abs() < 1e-9);
+        assert_eq!(filled[1].frame.right_hand_landmarks_stamp, Some(100_000));
+        let flags = filled[1].frame.filled.as_ref().unwrap();
+        assert_eq!(flags.streams, vec![LandmarkStream::RightHand]);
+        assert!(filled[1].json_str.contains("\"filled\""));
+        assert!(filled[3].frame.right_hand_landmarks.is_none());
+        assert!(filled[0].frame.filled.is_none());
+    }
+}
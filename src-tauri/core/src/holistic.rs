@@ -0,0 +1,333 @@
+// mediapipe holisticの認識結果を型付きで扱うための構造体。
+// 送信されてくるjsonのキーと同じ名前にしてあるので、
+// serdeでそのまま読み書きできる。
+// 手や顔が認識されなかったフレームではキー自体が存在しないので、
+// 各ストリームはOptionで保持する。
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// ランドマーク一点分。
+// face_landmarksにはvisibility, presenceが含まれない。
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Landmark {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence: Option<f64>,
+}
+
+// カメラパラメータ
+// focal_lengthの単位はピクセル。
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraParams {
+    pub focal_length: f64,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+// pose_landmarks, pose_world_landmarksの各点の名前
+pub const POSE_LANDMARK_NAMES: [&str; 33] = [
+    "nose",
+    "left_eye_inner",
+    "left_eye",
+    "left_eye_outer",
+    "right_eye_inner",
+    "right_eye",
+    "right_eye_outer",
+    "left_ear",
+    "right_ear",
+    "mouth_left",
+    "mouth_right",
+    "left_shoulder",
+    "right_shoulder",
+    "left_elbow",
+    "right_elbow",
+    "left_wrist",
+    "right_wrist",
+    "left_pinky",
+    "right_pinky",
+    "left_index",
+    "right_index",
+    "left_thumb",
+    "right_thumb",
+    "left_hip",
+    "right_hip",
+    "left_knee",
+    "right_knee",
+    "left_ankle",
+    "right_ankle",
+    "left_heel",
+    "right_heel",
+    "left_foot_index",
+    "right_foot_index",
+];
+
+// left_hand_landmarks, right_hand_landmarksの各点の名前
+pub const HAND_LANDMARK_NAMES: [&str; 21] = [
+    "wrist",
+    "thumb_cmc",
+    "thumb_mcp",
+    "thumb_ip",
+    "thumb_tip",
+    "index_finger_mcp",
+    "index_finger_pip",
+    "index_finger_dip",
+    "index_finger_tip",
+    "middle_finger_mcp",
+    "middle_finger_pip",
+    "middle_finger_dip",
+    "middle_finger_tip",
+    "ring_finger_mcp",
+    "ring_finger_pip",
+    "ring_finger_dip",
+    "ring_finger_tip",
+    "pinky_mcp",
+    "pinky_pip",
+    "pinky_dip",
+    "pinky_tip",
+];
+
+// face_landmarksの点の数(refine_landmarksを使わない場合)
+pub const FACE_LANDMARK_COUNT: usize = 468;
+
+// ランドマークの種類
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LandmarkStream {
+    Pose,
+    PoseWorld,
+    Face,
+    LeftHand,
+    RightHand,
+}
+
+impl LandmarkStream {
+    pub const ALL: [LandmarkStream; 5] = [
+        LandmarkStream::Pose,
+        LandmarkStream::PoseWorld,
+        LandmarkStream::Face,
+        LandmarkStream::LeftHand,
+        LandmarkStream::RightHand,
+    ];
+
+    // jsonのキー名(_landmarksを除いたもの)
+    pub fn name(&self) -> &'static str {
+        match self {
+            LandmarkStream::Pose => "pose",
+            LandmarkStream::PoseWorld => "pose_world",
+            LandmarkStream::Face => "face",
+            LandmarkStream::LeftHand => "left_hand",
+            LandmarkStream::RightHand => "right_hand",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        LandmarkStream::ALL
+            .into_iter()
+            .find(|stream| stream.name() == name)
+    }
+
+    // 1フレームあたりのランドマークの数
+    pub fn landmark_count(&self) -> usize {
+        match self {
+            LandmarkStream::Pose | LandmarkStream::PoseWorld => POSE_LANDMARK_NAMES.len(),
+            LandmarkStream::Face => FACE_LANDMARK_COUNT,
+            LandmarkStream::LeftHand | LandmarkStream::RightHand => HAND_LANDMARK_NAMES.len(),
+        }
+    }
+
+    // i番目のランドマークの名前。顔の点には名前がないので番号にする。
+    pub fn landmark_name(&self, i: usize) -> String {
+        let names: &[&str] = match self {
+            LandmarkStream::Pose | LandmarkStream::PoseWorld => &POSE_LANDMARK_NAMES,
+            LandmarkStream::Face => &[],
+            LandmarkStream::LeftHand | LandmarkStream::RightHand => &HAND_LANDMARK_NAMES,
+        };
+        match names.get(i) {
+            Some(name) => name.to_string(),
+            None => format!("point_{}", i),
+        }
+    }
+
+    // face_landmarksにはvisibility, presenceが含まれない。
+    pub fn has_visibility(&self) -> bool {
+        *self != LandmarkStream::Face
+    }
+}
+
+// ランドマーク以外に選択できるストリームの名前
+pub const GRAVITY_STREAM: &str = "gravity";
+pub const CAMERA_STREAM: &str = "camera";
+pub const ROOT_STREAM: &str = "root";
+
+// 転送・配信するストリームとして選択できる名前か
+pub fn is_stream_name(name: &str) -> bool {
+    name == GRAVITY_STREAM
+        || name == CAMERA_STREAM
+        || name == ROOT_STREAM
+        || LandmarkStream::ALL.iter().any(|s| s.name() == name)
+}
+
+// 欠落を補った(gapfill.rs)値の印
+// 記録された値と補った値を区別できるように、補ったフレームのjsonにfilledとして付ける。
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilledFlags {
+    // ストリーム全体を補ったもの
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub streams: Vec<LandmarkStream>,
+    // visibilityが低いために補ったランドマークの番号
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub landmarks: BTreeMap<LandmarkStream, Vec<usize>>,
+}
+
+impl FilledFlags {
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty() && self.landmarks.is_empty()
+    }
+}
+
+// 1フレーム分の認識結果
+// タイムスタンプはストリームごとに異なるので、それぞれ保持する。
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct HolisticFrame {
+    // 複数端末のデータを一つのファイルに記録した場合の送信元
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_params: Option<CameraParams>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_landmarks: Option<Vec<Landmark>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_landmarks_stamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_world_landmarks: Option<Vec<Landmark>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose_world_landmarks_stamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub face_landmarks: Option<Vec<Landmark>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub face_landmarks_stamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_hand_landmarks: Option<Vec<Landmark>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_hand_landmarks_stamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_hand_landmarks: Option<Vec<Landmark>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_hand_landmarks_stamp: Option<u64>,
+
+    // 重力ベクトル
+    // gravity_stampは他のstampと単位が異なる(ナノ秒)ので注意。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gravity: Option<[f64; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gravity_stamp: Option<u64>,
+
+    // camera_paramsから推定した、カメラ座標系(x右、y下、z奥)での腰の中心の位置[m](position.rs)
+    // 受信したデータには含まれない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_translation: Option<[f64; 3]>,
+
+    // 受信したデータには含まれない。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filled: Option<FilledFlags>,
+}
+
+impl HolisticFrame {
+    pub fn from_json_str(json_str: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json_str)
+    }
+
+    pub fn to_json_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    pub fn landmarks(&self, stream: LandmarkStream) -> Option<&Vec<Landmark>> {
+        match stream {
+            LandmarkStream::Pose => self.pose_landmarks.as_ref(),
+            LandmarkStream::PoseWorld => self.pose_world_landmarks.as_ref(),
+            LandmarkStream::Face => self.face_landmarks.as_ref(),
+            LandmarkStream::LeftHand => self.left_hand_landmarks.as_ref(),
+            LandmarkStream::RightHand => self.right_hand_landmarks.as_ref(),
+        }
+    }
+
+    pub fn landmarks_mut(&mut self, stream: LandmarkStream) -> &mut Option<Vec<Landmark>> {
+        match stream {
+            LandmarkStream::Pose => &mut self.pose_landmarks,
+            LandmarkStream::PoseWorld => &mut self.pose_world_landmarks,
+            LandmarkStream::Face => &mut self.face_landmarks,
+            LandmarkStream::LeftHand => &mut self.left_hand_landmarks,
+            LandmarkStream::RightHand => &mut self.right_hand_landmarks,
+        }
+    }
+
+    pub fn stamp(&self, stream: LandmarkStream) -> Option<u64> {
+        match stream {
+            LandmarkStream::Pose => self.pose_landmarks_stamp,
+            LandmarkStream::PoseWorld => self.pose_world_landmarks_stamp,
+            LandmarkStream::Face => self.face_landmarks_stamp,
+            LandmarkStream::LeftHand => self.left_hand_landmarks_stamp,
+            LandmarkStream::RightHand => self.right_hand_landmarks_stamp,
+        }
+    }
+
+    pub fn stamp_mut(&mut self, stream: LandmarkStream) -> &mut Option<u64> {
+        match stream {
+            LandmarkStream::Pose => &mut self.pose_landmarks_stamp,
+            LandmarkStream::PoseWorld => &mut self.pose_world_landmarks_stamp,
+            LandmarkStream::Face => &mut self.face_landmarks_stamp,
+            LandmarkStream::LeftHand => &mut self.left_hand_landmarks_stamp,
+            LandmarkStream::RightHand => &mut self.right_hand_landmarks_stamp,
+        }
+    }
+
+    // 指定した名前のストリームだけを含むフレーム。
+    // 選択したストリームが一つも含まれていない場合はNone
+    pub fn select_streams(&self, names: &[String]) -> Option<HolisticFrame> {
+        let contains = |name: &str| names.iter().any(|n| n == name);
+        let mut selected = HolisticFrame {
+            device_id: self.device_id.clone(),
+            filled: self.filled.clone(),
+            ..Default::default()
+        };
+        let mut found = false;
+        for stream in LandmarkStream::ALL {
+            if contains(stream.name()) && self.landmarks(stream).is_some() {
+                *selected.landmarks_mut(stream) = self.landmarks(stream).cloned();
+                *selected.stamp_mut(stream) = self.stamp(stream);
+                found = true;
+            }
+        }
+        if contains(GRAVITY_STREAM) && self.gravity.is_some() {
+            selected.gravity = self.gravity;
+            selected.gravity_stamp = self.gravity_stamp;
+            found = true;
+        }
+        if contains(ROOT_STREAM) && self.root_translation.is_some() {
+            selected.root_translation = self.root_translation;
+            found = true;
+        }
+        if contains(CAMERA_STREAM) && self.camera_params.is_some() {
+            selected.camera_params = self.camera_params.clone();
+            found = true;
+        }
+        if found {
+            Some(selected)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// mediapipe_receiver_utilityのGUIに依存しない部分。
+// UDPでの受信、ファイルへの記録、記録したファイルの読み込みと再生を行う。
+// 受信・再生したフレームはFrameSinkに渡されるので、
+// Tauriのウィンドウ以外からも同じ処理を利用できる。
+
+pub mod compressed;
+pub mod devices;
+pub mod export;
+pub mod filter;
+pub mod frames;
+pub mod gapfill;
+pub mod holistic;
+pub mod math;
+pub mod metadata;
+pub mod network;
+pub mod osc;
+pub mod playback;
+pub mod position;
+pub mod receiver;
+pub mod recorder;
+pub mod relay;
+pub mod resample;
+pub mod seek;
+pub mod session;
+pub mod sink;
+pub mod skeleton;
+pub mod stats;
+pub mod store;
+pub mod vmc;
+pub mod websocket;
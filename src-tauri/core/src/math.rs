@@ -0,0 +1,310 @@
+// 骨格の計算で使うベクトルとクォータニオン。
+// 外部のライブラリを使うほどではないので、必要な演算だけを実装している。
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vec3 {
+    pub const ZERO: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+    pub const X: Vec3 = Vec3::new(1.0, 0.0, 0.0);
+    pub const Y: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+    pub const Z: Vec3 = Vec3::new(0.0, 0.0, 1.0);
+
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn dot(self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    // 長さがほぼ0の場合はNoneを返す。
+    pub fn normalize(self) -> Option<Vec3> {
+        let len = self.length();
+        if len < 1e-9 || !len.is_finite() {
+            None
+        } else {
+            Some(self * (1.0 / len))
+        }
+    }
+
+    pub fn lerp(self, other: Vec3, t: f64) -> Vec3 {
+        self + (other - self) * t
+    }
+
+    pub fn midpoint(self, other: Vec3) -> Vec3 {
+        self.lerp(other, 0.5)
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Mul<f64> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, s: f64) -> Vec3 {
+        Vec3::new(self.x * s, self.y * s, self.z * s)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+// 回転を表す単位クォータニオン
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Quat::IDENTITY
+    }
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Quat {
+        let axis = axis.normalize().unwrap_or(Vec3::X);
+        let (s, c) = (angle * 0.5).sin_cos();
+        Quat {
+            w: c,
+            x: axis.x * s,
+            y: axis.y * s,
+            z: axis.z * s,
+        }
+    }
+
+    // fromをtoに向ける最小の回転
+    pub fn from_two_vectors(from: Vec3, to: Vec3) -> Option<Quat> {
+        let from = from.normalize()?;
+        let to = to.normalize()?;
+        let d = from.dot(to);
+        if d < -1.0 + 1e-9 {
+            // 正反対を向いている場合は、fromと直交する適当な軸で180度回す。
+            let axis = from
+                .cross(Vec3::X)
+                .normalize()
+                .or_else(|| from.cross(Vec3::Y).normalize())?;
+            return Some(Quat::from_axis_angle(axis, std::f64::consts::PI));
+        }
+        let c = from.cross(to);
+        Quat {
+            w: 1.0 + d,
+            x: c.x,
+            y: c.y,
+            z: c.z,
+        }
+        .normalize()
+    }
+
+    // 列ベクトルが回転後のX, Y, Z軸となる回転行列から作る。
+    pub fn from_axes(x: Vec3, y: Vec3, z: Vec3) -> Quat {
+        let (m00, m01, m02) = (x.x, y.x, z.x);
+        let (m10, m11, m12) = (x.y, y.y, z.y);
+        let (m20, m21, m22) = (x.z, y.z, z.z);
+        let trace = m00 + m11 + m22;
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quat {
+                w: 0.25 * s,
+                x: (m21 - m12) / s,
+                y: (m02 - m20) / s,
+                z: (m10 - m01) / s,
+            }
+        } else if m00 > m11 && m00 > m22 {
+            let s = (1.0 + m00 - m11 - m22).sqrt() * 2.0;
+            Quat {
+                w: (m21 - m12) / s,
+                x: 0.25 * s,
+                y: (m01 + m10) / s,
+                z: (m02 + m20) / s,
+            }
+        } else if m11 > m22 {
+            let s = (1.0 + m11 - m00 - m22).sqrt() * 2.0;
+            Quat {
+                w: (m02 - m20) / s,
+                x: (m01 + m10) / s,
+                y: 0.25 * s,
+                z: (m12 + m21) / s,
+            }
+        } else {
+            let s = (1.0 + m22 - m00 - m11).sqrt() * 2.0;
+            Quat {
+                w: (m10 - m01) / s,
+                x: (m02 + m20) / s,
+                y: (m12 + m21) / s,
+                z: 0.25 * s,
+            }
+        };
+        q.normalize().unwrap_or_default()
+    }
+
+    // primaryを第1軸、primaryと直交化したsecondaryを第2軸とする座標系
+    // rest_primary, rest_secondaryで表される座標系からの回転を返す。
+    pub fn from_basis(
+        rest_primary: Vec3,
+        rest_secondary: Vec3,
+        primary: Vec3,
+        secondary: Vec3,
+    ) -> Option<Quat> {
+        let rest = orthonormal(rest_primary, rest_secondary)?;
+        let current = orthonormal(primary, secondary)?;
+        Some(current * rest.conjugate())
+    }
+
+    pub fn length(self) -> f64 {
+        (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    pub fn normalize(self) -> Option<Quat> {
+        let len = self.length();
+        if len < 1e-12 || !len.is_finite() {
+            None
+        } else {
+            Some(Quat {
+                w: self.w / len,
+                x: self.x / len,
+                y: self.y / len,
+                z: self.z / len,
+            })
+        }
+    }
+
+    pub fn conjugate(self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn dot(self, other: Quat) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn rotate(self, v: Vec3) -> Vec3 {
+        let u = Vec3::new(self.x, self.y, self.z);
+        let t = u.cross(v) * 2.0;
+        v + t * self.w + u.cross(t)
+    }
+
+    // 球面線形補間。常に短い方の経路で補間する。
+    pub fn slerp(self, other: Quat, t: f64) -> Quat {
+        let mut other = other;
+        let mut d = self.dot(other);
+        if d < 0.0 {
+            other = Quat {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+            d = -d;
+        }
+        if d > 0.9995 {
+            // ほぼ同じ向きの場合は線形補間で十分
+            let q = Quat {
+                w: self.w + (other.w - self.w) * t,
+                x: self.x + (other.x - self.x) * t,
+                y: self.y + (other.y - self.y) * t,
+                z: self.z + (other.z - self.z) * t,
+            };
+            return q.normalize().unwrap_or(self);
+        }
+        let theta = d.acos();
+        let s = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / s;
+        let b = (t * theta).sin() / s;
+        Quat {
+            w: self.w * a + other.w * b,
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+        }
+    }
+
+    // Rz * Rx * Ryの順に回転するオイラー角[rad]を(z, x, y)で返す。
+    // BVHのCHANNELS Zrotation Xrotation Yrotationに対応する。
+    pub fn to_euler_zxy(self) -> (f64, f64, f64) {
+        let Quat { w, x, y, z } = self;
+        let m01 = 2.0 * (x * y - w * z);
+        let m11 = 1.0 - 2.0 * (x * x + z * z);
+        let m21 = 2.0 * (y * z + w * x);
+        let m20 = 2.0 * (x * z - w * y);
+        let m22 = 1.0 - 2.0 * (x * x + y * y);
+        let rx = m21.clamp(-1.0, 1.0).asin();
+        if m21.abs() < 0.999999 {
+            ((-m01).atan2(m11), rx, (-m20).atan2(m22))
+        } else {
+            // ジンバルロックの場合はY軸回りを0とする。
+            let m10 = 2.0 * (x * y + w * z);
+            let m00 = 1.0 - 2.0 * (y * y + z * z);
+            (m10.atan2(m00), rx, 0.0)
+        }
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+    fn mul(self, o: Quat) -> Quat {
+        Quat {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+// primary, secondaryから作った正規直交基底への回転
+fn orthonormal(primary: Vec3, secondary: Vec3) -> Option<Quat> {
+    let e1 = primary.normalize()?;
+    let e2 = (secondary - e1 * secondary.dot(e1)).normalize()?;
+    let e3 = e1.cross(e2);
+    // 第1軸をX、第2軸をYとした回転行列
+    Some(Quat::from_axes(e1, e2, e3))
+}
@@ -0,0 +1,201 @@
+// 記録したファイルの横に保存するセッションの情報(サイドカー)。
+// record.datに対してrecord.dat.meta.jsonを作る。
+// 記録ファイル本体の形式は変えないので、古いバージョンや他のツールでもそのまま読み込める。
+//
+// 記録の開始時に一度書き込み、停止時にフレーム数や長さを確定して書き直す。
+// 途中で終了した場合も、開始時の情報は残る。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::frames::fallback_timestamp;
+use crate::holistic::{CameraParams, HolisticFrame};
+
+pub const METADATA_SUFFIX: &str = ".meta.json";
+
+// ファイル形式のバージョン
+const METADATA_VERSION: u32 = 1;
+
+// 記録を開始するときに利用者が入力する情報
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionInfo {
+    // 送信した端末の名前(機種など)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    // 送信したアプリのバージョン
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    // 改造版mediapipeの種類(HIROMEIRO同梱のapk、自前でビルドしたものなど)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mediapipe_variant: Option<String>,
+    // 使用したカメラ(front, back, front_mirroredなど)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionMetadata {
+    pub version: u32,
+    // 記録したmru-coreのバージョン
+    pub recorder_version: String,
+    // 記録の開始・停止時刻(UTC, RFC 3339)
+    pub started_at: String,
+    pub stopped_at: Option<String>,
+    // 送信元のdevice_id
+    pub sources: Vec<String>,
+    // 最初に受信したcamera_params
+    pub camera_params: Option<CameraParams>,
+    pub frames: u64,
+    // 最初と最後のフレームのタイムスタンプ(マイクロ秒, 端末の起動からの時刻)
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    pub duration_sec: Option<f64>,
+    #[serde(flatten)]
+    pub info: SessionInfo,
+}
+
+// 記録ファイルに対応するサイドカーのパス
+pub fn metadata_path(record_path: &Path) -> PathBuf {
+    let mut name = record_path.as_os_str().to_os_string();
+    name.push(METADATA_SUFFIX);
+    PathBuf::from(name)
+}
+
+impl SessionMetadata {
+    // サイドカーがない場合はNone
+    pub fn load_for(record_path: &Path) -> io::Result<Option<Self>> {
+        let path = metadata_path(record_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let s = fs::read_to_string(&path)?;
+        serde_json::from_str(&s)
+            .map(Some)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+    }
+
+    pub fn save_for(&self, record_path: &Path) -> io::Result<()> {
+        let s = serde_json::to_string_pretty(self)?;
+        fs::write(metadata_path(record_path), s)
+    }
+}
+
+// 受信時と同じ方法でフレームのタイムスタンプを決める。
+fn frame_timestamp(line: &str) -> Option<u64> {
+    let frame = HolisticFrame::from_json_str(line).ok()?;
+    frame
+        .pose_landmarks_stamp
+        .or_else(|| fallback_timestamp(&frame))
+}
+
+// 記録中のファイル一つ分のサイドカー
+pub struct MetadataWriter {
+    record_path: PathBuf,
+    metadata: SessionMetadata,
+    // 停止時にタイムスタンプを読むために、最後の行だけを保持する。
+    last_line: Option<String>,
+}
+
+impl MetadataWriter {
+    // 開始時の情報をすぐに書き込む。
+    pub fn create(record_path: &Path, info: SessionInfo) -> io::Result<Self> {
+        let writer = Self {
+            record_path: record_path.to_path_buf(),
+            metadata: SessionMetadata {
+                version: METADATA_VERSION,
+                recorder_version: env!("CARGO_PKG_VERSION").to_string(),
+                started_at: format_utc(SystemTime::now()),
+                info,
+                ..Default::default()
+            },
+            last_line: None,
+        };
+        writer.metadata.save_for(record_path)?;
+        Ok(writer)
+    }
+
+    // 書き込んだ行ごとに呼ぶ。
+    // jsonを読み込むのは最初の行と、camera_paramsが見つかるまでの行だけにする。
+    pub fn on_line(&mut self, device_id: &str, line: &str) {
+        if !self.metadata.sources.iter().any(|s| s == device_id) {
+            self.metadata.sources.push(device_id.to_string());
+        }
+        if self.metadata.frames == 0 {
+            self.metadata.first_timestamp = frame_timestamp(line);
+        }
+        if self.metadata.camera_params.is_none() && line.contains("\"camera_params\"") {
+            if let Ok(frame) = HolisticFrame::from_json_str(line) {
+                self.metadata.camera_params = frame.camera_params;
+            }
+        }
+        self.metadata.frames += 1;
+        match &mut self.last_line {
+            Some(last_line) => {
+                last_line.clear();
+                last_line.push_str(line);
+            }
+            None => self.last_line = Some(line.to_string()),
+        }
+    }
+
+    // フレーム数と長さを確定して書き直す。
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.metadata.stopped_at = Some(format_utc(SystemTime::now()));
+        self.metadata.last_timestamp = self.last_line.as_deref().and_then(frame_timestamp);
+        self.metadata.duration_sec =
+            match (self.metadata.first_timestamp, self.metadata.last_timestamp) {
+                (Some(first), Some(last)) if last >= first => Some((last - first) as f64 / 1e6),
+                _ => None,
+            };
+        self.metadata.save_for(&self.record_path)
+    }
+
+    pub fn metadata(&self) -> &SessionMetadata {
+        &self.metadata
+    }
+}
+
+// UTCの時刻をRFC 3339の文字列(2024-01-02T03:04:05.678Z)にする。
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// 1970-01-01からの日数を年月日にする。
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
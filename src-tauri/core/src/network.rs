@@ -0,0 +1,154 @@
+// UDPの待ち受けアドレス、ポート番号の設定。
+// 設定はjsonファイルに保存しておき、次回起動時のデフォルトとする。
+
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::sink::{FrameEvent, FrameSink};
+
+// 0x947D
+pub const DEFAULT_PORT: u16 = 38013;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    // 受信時にbindするアドレス
+    pub bind_addr: String,
+    // 受信時にbindするポート番号
+    pub port: u16,
+    // 再生時の送信先ポート番号
+    pub dest_port: u16,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "0.0.0.0".to_string(),
+            port: DEFAULT_PORT,
+            dest_port: DEFAULT_PORT,
+        }
+    }
+}
+
+impl NetworkConfig {
+    // ファイルがない、または壊れている場合はデフォルト値を使う。
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(s) => match serde_json::from_str(&s) {
+                Ok(config) => config,
+                Err(why) => {
+                    println!("network config: broken file, use default. ({})", why);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = serde_json::to_string_pretty(self)?;
+        fs::write(path, s)
+    }
+}
+
+// IPv6アドレスの場合は[]で囲む。
+pub fn socket_addr_string(host: &str, port: u16) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// フロントエンドに返すエラー
+#[derive(Clone, Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NetworkError {
+    // 他のプロセス(または受信中のスレッド)がポートを使っている
+    AddrInUse { addr: String },
+    InvalidAddress { addr: String, reason: String },
+    Io { addr: String, reason: String },
+    // 転送・配信するストリームとして指定できない名前
+    UnknownStream { stream: String },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::AddrInUse { addr } => write!(f, "{}: address already in use", addr),
+            NetworkError::InvalidAddress { addr, reason } => write!(f, "{}: {}", addr, reason),
+            NetworkError::Io { addr, reason } => write!(f, "{}: {}", addr, reason),
+            NetworkError::UnknownStream { stream } => write!(f, "unknown stream: {}", stream),
+        }
+    }
+}
+
+// アドレスを検証する。
+fn parse_addr(addr: &str) -> Result<SocketAddr, NetworkError> {
+    addr.parse().map_err(
+        |why: std::net::AddrParseError| NetworkError::InvalidAddress {
+            addr: addr.to_string(),
+            reason: why.to_string(),
+        },
+    )
+}
+
+fn bind_error(addr: &str, why: io::Error) -> NetworkError {
+    if why.kind() == ErrorKind::AddrInUse {
+        NetworkError::AddrInUse {
+            addr: addr.to_string(),
+        }
+    } else {
+        NetworkError::Io {
+            addr: addr.to_string(),
+            reason: why.to_string(),
+        }
+    }
+}
+
+// アドレスを検証してからbindする。
+pub async fn bind_udp(addr: &str) -> Result<UdpSocket, NetworkError> {
+    let sock_addr = parse_addr(addr)?;
+    UdpSocket::bind(sock_addr)
+        .await
+        .map_err(|why| bind_error(addr, why))
+}
+
+// TCPで待ち受ける場合も同じようにエラーを返す。
+pub async fn bind_tcp(addr: &str) -> Result<TcpListener, NetworkError> {
+    let sock_addr = parse_addr(addr)?;
+    TcpListener::bind(sock_addr)
+        .await
+        .map_err(|why| bind_error(addr, why))
+}
+
+// フレームをそのままUDPで送信するFrameSink
+pub struct UdpSink {
+    sock: std::net::UdpSocket,
+}
+
+impl UdpSink {
+    // 送信だけが必要なのでconnectで送信先を指定する。
+    pub fn connect(target: &str) -> io::Result<Self> {
+        // bindでは0.0.0.0を指定しておく。
+        let sock = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(target)?;
+        Ok(Self { sock })
+    }
+}
+
+impl FrameSink for UdpSink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        if let Err(why) = self.sock.send(event.json_str.as_bytes()) {
+            println!("udp sink: {}", why);
+        }
+    }
+}
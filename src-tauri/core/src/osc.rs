@@ -0,0 +1,295 @@
+// OSC(Open Sound Control)のメッセージとバンドルのエンコードと、
+// ランドマークをそのままOSCで送信するFrameSink。
+// VMCプロトコルやTouchDesigner, MaxなどOSCで受け取るツールに送信するために使う。
+// 送信に必要な型(int32, float32, string)だけを実装している。
+// https://opensoundcontrol.stanford.edu/spec-1_0.html
+
+use std::io;
+use std::net::UdpSocket;
+
+use crate::frames::fallback_timestamp;
+use crate::holistic::{HolisticFrame, LandmarkStream};
+use crate::network::socket_addr_string;
+use crate::position::root_translation;
+use crate::sink::{FrameEvent, FrameOrigin, FrameSink};
+
+// バンドルをすぐに実行させるタイムタグ
+pub const IMMEDIATELY: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+// 文字列はnull終端し、4バイト境界まで0で埋める。
+fn push_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+impl OscMessage {
+    pub fn new(address: &str, args: Vec<OscArg>) -> Self {
+        Self {
+            address: address.to_string(),
+            args,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push_string(&mut buf, &self.address);
+        let mut tags = String::from(",");
+        for arg in self.args.iter() {
+            tags.push(match arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::String(_) => 's',
+            });
+        }
+        push_string(&mut buf, &tags);
+        for arg in self.args.iter() {
+            match arg {
+                OscArg::Int(v) => buf.extend_from_slice(&v.to_be_bytes()),
+                OscArg::Float(v) => buf.extend_from_slice(&v.to_be_bytes()),
+                OscArg::String(s) => push_string(&mut buf, s),
+            }
+        }
+        buf
+    }
+}
+
+// マイクロ秒のタイムスタンプをタイムタグ(上位32bitが秒、下位32bitが秒未満)にする。
+pub fn time_tag(micros: u64) -> u64 {
+    let seconds = micros / 1_000_000;
+    let fraction = ((micros % 1_000_000) << 32) / 1_000_000;
+    (seconds << 32) | fraction
+}
+
+// 複数のメッセージを一つのバンドルにまとめる。
+pub fn encode_bundle(time_tag: u64, messages: &[OscMessage]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    push_string(&mut buf, "#bundle");
+    buf.extend_from_slice(&time_tag.to_be_bytes());
+    for message in messages.iter() {
+        let data = message.encode();
+        buf.extend_from_slice(&(data.len() as i32).to_be_bytes());
+        buf.extend_from_slice(&data);
+    }
+    buf
+}
+
+// 送信するストリーム
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OscStream {
+    Pose,
+    PoseWorld,
+    Face,
+    LeftHand,
+    RightHand,
+    Gravity,
+    Camera,
+    Root,
+}
+
+impl OscStream {
+    pub const ALL: [OscStream; 8] = [
+        OscStream::Pose,
+        OscStream::PoseWorld,
+        OscStream::Face,
+        OscStream::LeftHand,
+        OscStream::RightHand,
+        OscStream::Gravity,
+        OscStream::Camera,
+        OscStream::Root,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self.landmarks() {
+            Some(stream) => stream.name(),
+            None if *self == OscStream::Gravity => "gravity",
+            None if *self == OscStream::Camera => "camera",
+            None => "root",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        OscStream::ALL
+            .into_iter()
+            .find(|stream| stream.name() == name)
+    }
+
+    pub fn landmarks(&self) -> Option<LandmarkStream> {
+        match self {
+            OscStream::Pose => Some(LandmarkStream::Pose),
+            OscStream::PoseWorld => Some(LandmarkStream::PoseWorld),
+            OscStream::Face => Some(LandmarkStream::Face),
+            OscStream::LeftHand => Some(LandmarkStream::LeftHand),
+            OscStream::RightHand => Some(LandmarkStream::RightHand),
+            OscStream::Gravity | OscStream::Camera | OscStream::Root => None,
+        }
+    }
+
+    // プレフィックスの後ろに付けるアドレス
+    fn address(&self) -> &'static str {
+        match self {
+            OscStream::Pose => "/pose",
+            OscStream::PoseWorld => "/pose/world",
+            OscStream::Face => "/face",
+            OscStream::LeftHand => "/hand/left",
+            OscStream::RightHand => "/hand/right",
+            OscStream::Gravity => "/gravity",
+            OscStream::Camera => "/camera",
+            OscStream::Root => "/root",
+        }
+    }
+
+    // 1フレーム分のメッセージ。ストリームが含まれていない場合は空になる。
+    fn messages(&self, prefix: &str, frame: &HolisticFrame) -> Vec<OscMessage> {
+        let address = format!("{}{}", prefix, self.address());
+        match self {
+            OscStream::Gravity => match frame.gravity {
+                Some(g) => vec![OscMessage::new(
+                    &address,
+                    g.iter().map(|v| OscArg::Float(*v as f32)).collect(),
+                )],
+                None => Vec::new(),
+            },
+            OscStream::Camera => match &frame.camera_params {
+                Some(camera) => vec![OscMessage::new(
+                    &address,
+                    vec![
+                        OscArg::Float(camera.focal_length as f32),
+                        OscArg::Int(camera.frame_width as i32),
+                        OscArg::Int(camera.frame_height as i32),
+                    ],
+                )],
+                None => Vec::new(),
+            },
+            OscStream::Root => match root_translation(frame) {
+                Some(t) => vec![OscMessage::new(
+                    &address,
+                    t.iter().map(|v| OscArg::Float(*v as f32)).collect(),
+                )],
+                None => Vec::new(),
+            },
+            _ => {
+                let landmarks = match self.landmarks().and_then(|s| frame.landmarks(s)) {
+                    Some(landmarks) => landmarks,
+                    None => return Vec::new(),
+                };
+                landmarks
+                    .iter()
+                    .enumerate()
+                    .map(|(i, l)| {
+                        let mut args = vec![
+                            OscArg::Float(l.x as f32),
+                            OscArg::Float(l.y as f32),
+                            OscArg::Float(l.z as f32),
+                        ];
+                        if let Some(visibility) = l.visibility {
+                            args.push(OscArg::Float(visibility as f32));
+                        }
+                        OscMessage::new(&format!("{}/{}", address, i), args)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct OscOptions {
+    // 送信先
+    pub host: String,
+    pub port: u16,
+    // アドレスの先頭に付ける文字列(例: "/mru")。空の場合は"/pose"などから始まる。
+    pub prefix: String,
+    pub streams: Vec<OscStream>,
+    // 受信時に送る端末。Noneの場合はすべての端末のフレームを送る。
+    pub device_id: Option<String>,
+}
+
+impl Default for OscOptions {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 9000,
+            prefix: String::new(),
+            streams: OscStream::ALL.to_vec(),
+            device_id: None,
+        }
+    }
+}
+
+// ランドマークをストリームごとのOSCバンドルとして送信するFrameSink
+// バンドルのタイムタグにはフレームのタイムスタンプ(端末の起動からの時刻)をそのまま入れるので、
+// タイムタグを解釈する受信側でも過去の時刻としてすぐに実行される。
+pub struct OscSink {
+    sock: UdpSocket,
+    options: OscOptions,
+    prefix: String,
+}
+
+impl OscSink {
+    pub fn connect(options: OscOptions) -> io::Result<Self> {
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(socket_addr_string(&options.host, options.port))?;
+        // 先頭に/を付け、末尾の/は取り除く。
+        let prefix = options.prefix.trim_matches('/');
+        let prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        };
+        Ok(Self {
+            sock,
+            options,
+            prefix,
+        })
+    }
+}
+
+impl FrameSink for OscSink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        let frame = match event.holistic() {
+            Some(frame) => frame,
+            None => return,
+        };
+        if let Some(device_id) = &self.options.device_id {
+            let source = event.device_id().or_else(|| frame.device_id.clone());
+            if source.as_ref() != Some(device_id) {
+                return;
+            }
+        }
+        // 受信時はTrackingFrameと同じ方法でタイムスタンプを決める。
+        let timestamp = match event.origin {
+            FrameOrigin::Playback => Some(event.timestamp),
+            FrameOrigin::Live => frame
+                .pose_landmarks_stamp
+                .or_else(|| fallback_timestamp(&frame)),
+        };
+        let tag = timestamp.map(time_tag).unwrap_or(IMMEDIATELY);
+        for stream in self.options.streams.iter() {
+            let messages = stream.messages(&self.prefix, &frame);
+            if messages.is_empty() {
+                continue;
+            }
+            if let Err(why) = self.sock.send(&encode_bundle(tag, &messages)) {
+                println!("osc sink: {}", why);
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// 再生時のタイミング制御。
+// 長時間のデータの場合、誤差が累積しないように
+// 送信開始時のタイムスタンプと現在のタイムスタンプの差分が
+// 送信開始時の時刻と現在の時刻の差分と同じになるようにする。
+// 再生速度を変更した場合は、その時点を新しい始点とする。
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::sink::{FrameEvent, FrameSink};
+use crate::store::FrameSource;
+
+pub const MIN_SPEED: f64 = 0.1;
+pub const MAX_SPEED: f64 = 10.0;
+
+pub struct Pacer {
+    // 送信開始時のタイムスタンプ
+    timeline_origin: u64,
+    // 送信開始時の時刻
+    stream_origin: Instant,
+    // 前回送信直後の時刻を保持する。
+    timestamp_prev: Instant,
+    // 前回のタイムスタンプ
+    t0: u64,
+    // 再生速度の倍率。Noneの場合は待機しない。
+    speed: Option<f64>,
+}
+
+impl Pacer {
+    pub fn new(timeline_origin: u64) -> Self {
+        let stream_origin = Instant::now();
+        Self {
+            timeline_origin,
+            stream_origin,
+            timestamp_prev: stream_origin,
+            t0: timeline_origin,
+            speed: Some(1.0),
+        }
+    }
+
+    // 再生速度を変更する。
+    // 前回送信したフレームを新しい始点とするので、変更前の誤差は引き継がない。
+    pub fn set_speed(&mut self, speed: Option<f64>) {
+        if speed != self.speed {
+            self.timeline_origin = self.t0;
+            self.stream_origin = self.timestamp_prev;
+            self.speed = speed;
+        }
+    }
+
+    // タイムスタンプt1のフレームを送信する時刻まで待機する。
+    // 待機した時間[microsec]を返す。
+    // タイムラインが壊れている(時間が逆行している)場合は待機せずにNoneを返すので、
+    // そのフレームは送信しない。
+    pub async fn wait(&mut self, t1: u64) -> Option<u64> {
+        if t1 < self.t0 {
+            // 逆行したフレームを新しい始点とし、以降のフレームはそこからの差分で待機する。
+            self.timeline_origin = t1;
+            self.stream_origin = self.timestamp_prev;
+            self.t0 = t1;
+            return None;
+        }
+        self.t0 = t1;
+        let speed = match self.speed {
+            Some(speed) => speed,
+            None => {
+                // 待機しない場合も、停止できるように一度制御を返す。
+                tokio::task::yield_now().await;
+                return Some(0);
+            }
+        };
+        // タイムスタンプにおける現在フレームと始点との差分(1)
+        let td_from_origin = ((t1 - self.timeline_origin) as f64 / speed) as u64;
+        // 前回送信時刻と最初に送信した時刻の差分(2)
+        let duration_from_origin = self.timestamp_prev - self.stream_origin;
+        // 待機時間は(1)-(2)
+        // 送信が遅れている場合は待機しない。
+        let td_from_prev = td_from_origin.saturating_sub(duration_from_origin.as_micros() as u64);
+        tokio::time::sleep(Duration::from_micros(td_from_prev)).await;
+        Some(td_from_prev)
+    }
+
+    // 送信を開始した時刻を保存しておく。
+    // 送信にかかった時間は加算しない。
+    pub fn sent(&mut self, duration0: Instant) {
+        self.timestamp_prev = duration0;
+    }
+}
+
+// A-B区間。フレームのインデックスで指定し、endも含む。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PlaybackRange {
+    pub begin: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PlaybackOptions {
+    // 再生速度の倍率。MIN_SPEED〜MAX_SPEEDに制限する。
+    pub speed: f64,
+    // trueの場合は待機せずにできるだけ速く送信する。
+    pub as_fast_as_possible: bool,
+    // 最後まで再生したら先頭(rangeがあればrange.begin)に戻る。
+    pub looping: bool,
+    // 指定されている場合はこの区間だけを再生する。
+    pub range: Option<PlaybackRange>,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            as_fast_as_possible: false,
+            looping: false,
+            range: None,
+        }
+    }
+}
+
+impl PlaybackOptions {
+    fn pacer_speed(&self) -> Option<f64> {
+        if self.as_fast_as_possible {
+            None
+        } else {
+            Some(self.speed)
+        }
+    }
+
+    // 再生する区間[begin, end]。frames_lenは1以上とする。
+    fn bounds(&self, frames_len: usize) -> (usize, usize) {
+        let last = frames_len - 1;
+        match self.range {
+            Some(range) => {
+                let end = range.end.min(last);
+                (range.begin.min(end), end)
+            }
+            None => (0, last),
+        }
+    }
+}
+
+// 再生中に別のスレッドから設定を変更するための入れ物
+#[derive(Default)]
+pub struct PlaybackControl(Mutex<PlaybackOptions>);
+
+impl PlaybackControl {
+    pub fn new(options: PlaybackOptions) -> Self {
+        let control = Self::default();
+        control.set(options);
+        control
+    }
+
+    pub fn get(&self) -> PlaybackOptions {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, mut options: PlaybackOptions) {
+        if !options.speed.is_finite() {
+            options.speed = 1.0;
+        }
+        options.speed = options.speed.clamp(MIN_SPEED, MAX_SPEED);
+        if let Some(range) = options.range {
+            if range.end < range.begin {
+                options.range = Some(PlaybackRange {
+                    begin: range.end,
+                    end: range.begin,
+                });
+            }
+        }
+        *self.0.lock().unwrap() = options;
+    }
+}
+
+// 記録したフレームを記録時と同じ間隔でFrameSinkに渡す。
+pub struct Player {
+    // 次に再生するフレームのインデックス
+    // 再生中のfutureをdropして止めた場合も、ここから再開できる。
+    pub next: usize,
+    // 最後にFrameSinkに渡したフレームのインデックス
+    pub last: Option<usize>,
+}
+
+impl Player {
+    pub fn new(start: usize) -> Self {
+        Self {
+            next: start,
+            last: None,
+        }
+    }
+
+    // 1フレームごとにcontrolの設定を読み直すので、
+    // 再生を止めずに速度やループの設定を変更できる。
+    pub async fn play<S: FrameSource + ?Sized>(
+        &mut self,
+        frames: &S,
+        sink: &mut dyn FrameSink,
+        control: &PlaybackControl,
+    ) {
+        if self.next >= frames.len() {
+            println!("player: idx is out of range.");
+            return;
+        }
+        let mut pacer: Option<Pacer> = None;
+        loop {
+            let options = control.get();
+            let (begin, end) = options.bounds(frames.len());
+            if self.next < begin || self.next > end {
+                if self.next > end && !options.looping {
+                    break;
+                }
+                // 区間の先頭に戻ったら、そこを新しい始点とする。
+                self.next = begin;
+                pacer = None;
+            }
+
+            let i = self.next;
+            let timestamp = frames.timestamp(i);
+            let pacer = pacer.get_or_insert_with(|| Pacer::new(timestamp));
+            pacer.set_speed(options.pacer_speed());
+            // ファイルから読む場合は、待機する前に読んでおく。
+            let tf = frames.frame(i);
+            match (pacer.wait(timestamp).await, tf) {
+                (None, _) => {
+                    // この場合タイムラインが壊れているので送信しない。
+                    println!("player: time diff is negative.");
+                }
+                (Some(_), None) => {
+                    println!("player: frame {} could not be read.", i);
+                }
+                (Some(_), Some(tf)) => {
+                    // 送信前のタイムスタンプを保持する
+                    let duration0 = Instant::now();
+                    sink.on_frame(&FrameEvent::playback(&tf.json_str, &tf.frame, i, timestamp));
+                    // 送信にかかった時間は加算せず、今回送信を開始した時刻を保存しておく。
+                    pacer.sent(duration0);
+                    self.last = Some(i);
+                }
+            }
+            self.next = i + 1;
+        }
+        println!("player: loop end.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 時間が二回逆行しても、始点からの差分が負にならない。
+    #[tokio::test]
+    async fn pacer_reanchors_on_backward_timestamps() {
+        let mut pacer = Pacer::new(1_000_000);
+        pacer.set_speed(Some(MAX_SPEED));
+        assert_eq!(pacer.wait(1_000_000).await, Some(0));
+        pacer.sent(Instant::now());
+        assert!(pacer.wait(1_100_000).await.is_some());
+        pacer.sent(Instant::now());
+        assert_eq!(pacer.wait(500_000).await, None);
+        let waited = pacer.wait(600_000).await;
+        assert!(waited.is_some_and(|us| us <= 100_000 / MAX_SPEED as u64));
+        pacer.sent(Instant::now());
+        assert_eq!(pacer.wait(400_000).await, None);
+        assert!(pacer.wait(450_000).await.is_some());
+    }
+}
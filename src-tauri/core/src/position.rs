@@ -0,0 +1,246 @@
+// camera_paramsを使って、カメラ座標系での人物の位置(腰の中心)を推定する。
+// pose_world_landmarksは腰の中心を原点とし、向きはカメラ座標系(x右、y下、z奥)と同じメートル単位の座標なので、
+// 平行移動Tだけを未知数として、pose_landmarksの画像上の位置に投影が合うようにTを最小二乗法で求める。
+//
+//   u = f (X + Tx) / (Z + Tz),  v = f (Y + Ty) / (Z + Tz)
+//   (u, vは画像の中心を原点としたピクセル座標、fはfocal_length)
+//
+// を変形した、Tについて線形な式
+//
+//   f Tx - u Tz = u Z - f X,  f Ty - v Tz = v Z - f Y
+//
+// をvisibilityを重みとして解く。
+
+use crate::holistic::HolisticFrame;
+use crate::sink::{FrameEvent, FrameSink};
+
+// visibilityがこれ未満の点は使わない。
+const MIN_VISIBILITY: f64 = 0.5;
+// 解くのに使う点の最小数
+const MIN_POINTS: usize = 4;
+
+// フレームの腰の中心のカメラ座標系での位置[m]。
+// camera_params, pose_landmarks, pose_world_landmarksのどれかがない場合や、解けない場合はNone
+pub fn solve_root_translation(frame: &HolisticFrame) -> Option<[f64; 3]> {
+    let camera = frame.camera_params.as_ref()?;
+    let image = frame.pose_landmarks.as_ref()?;
+    let world = frame.pose_world_landmarks.as_ref()?;
+    let f = camera.focal_length;
+    let (w, h) = (camera.frame_width as f64, camera.frame_height as f64);
+    if f <= 0.0 || w <= 0.0 || h <= 0.0 {
+        return None;
+    }
+
+    // 正規方程式 A^T W A T = A^T W b
+    let mut ata = [[0.0; 3]; 3];
+    let mut atb = [0.0; 3];
+    let mut points = 0;
+    for (p, q) in image.iter().zip(world.iter()) {
+        let weight = p.visibility.unwrap_or(1.0).min(q.visibility.unwrap_or(1.0));
+        if weight < MIN_VISIBILITY {
+            continue;
+        }
+        let u = p.x * w - w / 2.0;
+        let v = p.y * h - h / 2.0;
+        let rows = [
+            ([f, 0.0, -u], u * q.z - f * q.x),
+            ([0.0, f, -v], v * q.z - f * q.y),
+        ];
+        for (a, b) in rows {
+            for i in 0..3 {
+                for j in 0..3 {
+                    ata[i][j] += weight * a[i] * a[j];
+                }
+                atb[i] += weight * a[i] * b;
+            }
+        }
+        points += 1;
+    }
+    if points < MIN_POINTS {
+        return None;
+    }
+    let t = solve3(ata, atb)?;
+    // カメラの後ろになる解は使わない。
+    if t[2] <= 0.0 || !t.iter().all(|v| v.is_finite()) {
+        return None;
+    }
+    Some(t)
+}
+
+// 3x3の連立一次方程式をクラメルの公式で解く。
+fn solve3(a: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(&a);
+    if d.abs() < 1e-12 {
+        return None;
+    }
+    let mut t = [0.0; 3];
+    for (k, t) in t.iter_mut().enumerate() {
+        let mut m = a;
+        for i in 0..3 {
+            m[i][k] = b[i];
+        }
+        *t = det(&m) / d;
+    }
+    Some(t)
+}
+
+// root_translationがないフレームに求めた位置を付ける。付けた場合はtrue
+pub fn attach_root_translation(frame: &mut HolisticFrame) -> bool {
+    if frame.root_translation.is_some() {
+        return false;
+    }
+    frame.root_translation = solve_root_translation(frame);
+    frame.root_translation.is_some()
+}
+
+// root_translationがあればそれを、なければ求めた位置を返す。
+pub fn root_translation(frame: &HolisticFrame) -> Option<[f64; 3]> {
+    frame
+        .root_translation
+        .or_else(|| solve_root_translation(frame))
+}
+
+// root_translationを付けたjson。求められない場合はjson_strをそのまま返す。
+pub fn json_with_root_translation(json_str: &str, frame: &HolisticFrame) -> String {
+    let mut frame = frame.clone();
+    if !attach_root_translation(&mut frame) {
+        return json_str.to_string();
+    }
+    frame
+        .to_json_string()
+        .unwrap_or_else(|_| json_str.to_string())
+}
+
+// root_translationを付けたフレームをinnerに渡すFrameSink
+// 求められないフレームはそのまま渡す。
+pub struct PositionSink<S> {
+    inner: S,
+}
+
+impl<S: FrameSink> PositionSink<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: FrameSink> FrameSink for PositionSink<S> {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        let mut frame = match event.holistic() {
+            Some(frame) => frame.into_owned(),
+            None => return self.inner.on_frame(event),
+        };
+        if !attach_root_translation(&mut frame) {
+            return self.inner.on_frame(event);
+        }
+        let json_str = match frame.to_json_string() {
+            Ok(json_str) => json_str,
+            Err(why) => {
+                println!("position: {}", why);
+                return self.inner.on_frame(event);
+            }
+        };
+        self.inner.on_frame(&FrameEvent {
+            json_str: &json_str,
+            frame: Some(&frame),
+            ..*event
+        });
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::holistic::{CameraParams, Landmark};
+
+    const F: f64 = 900.0;
+    const W: u32 = 1280;
+    const H: u32 = 720;
+
+    // 腰の中心をtに置いたときの、pose_world_landmarksとその投影
+    fn frame(t: [f64; 3]) -> HolisticFrame {
+        let mut world = Vec::new();
+        let mut image = Vec::new();
+        for i in 0..33 {
+            let a = i as f64 * 0.7;
+            let (x, y, z) = (0.3 * a.cos(), 0.8 * (a * 0.3).sin(), 0.2 * a.sin());
+            let zc = z + t[2];
+            world.push(Landmark {
+                x,
+                y,
+                z,
+                visibility: Some(0.9),
+                ..Default::default()
+            });
+            image.push(Landmark {
+                x: (F * (x + t[0]) / zc + W as f64 / 2.0) / W as f64,
+                y: (F * (y + t[1]) / zc + H as f64 / 2.0) / H as f64,
+                z: 0.0,
+                visibility: Some(0.9),
+                ..Default::default()
+            });
+        }
+        HolisticFrame {
+            camera_params: Some(CameraParams {
+                focal_length: F,
+                frame_width: W,
+                frame_height: H,
+            }),
+            pose_landmarks: Some(image),
+            pose_world_landmarks: Some(world),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn recover_translation() {
+        let t = [0.2, -0.1, 2.5];
+        let solved = solve_root_translation(&frame(t)).unwrap();
+        for (a, b) in solved.iter().zip(t.iter()) {
+            assert!((a - b).abs() < 1e-6, "{:?}", solved);
+        }
+    }
+
+    #[test]
+    fn ignore_low_visibility_points() {
+        let t = [0.0, 0.1, 3.0];
+        let mut frame = frame(t);
+        // visibilityが低い点は大きくずれていても使わない。
+        for landmark in frame.pose_landmarks.as_mut().unwrap().iter_mut().take(5) {
+            landmark.x += 0.3;
+            landmark.visibility = Some(0.1);
+        }
+        let solved = solve_root_translation(&frame).unwrap();
+        assert!((solved[2] - t[2]).abs() < 1e-6);
+
+        for landmark in frame.pose_landmarks.as_mut().unwrap().iter_mut() {
+            landmark.visibility = Some(0.1);
+        }
+        assert!(solve_root_translation(&frame).is_none());
+    }
+
+    #[test]
+    fn require_camera_params() {
+        let mut frame = frame([0.0, 0.0, 2.0]);
+        frame.camera_params = None;
+        assert!(solve_root_translation(&frame).is_none());
+        assert!(!attach_root_translation(&mut frame));
+        assert_eq!(json_with_root_translation("{}", &frame), "{}");
+    }
+
+    #[test]
+    fn keep_attached_translation() {
+        let mut frame = frame([0.0, 0.0, 2.0]);
+        frame.root_translation = Some([1.0, 2.0, 3.0]);
+        assert!(!attach_root_translation(&mut frame));
+        assert_eq!(root_translation(&frame), Some([1.0, 2.0, 3.0]));
+    }
+}
@@ -0,0 +1,183 @@
+// UDPで受信したjsonを一行ずつFrameSinkに渡す。
+// 以下の投稿を参考にしている。
+// https://github.com/tokio-rs/tokio/discussions/4533
+//
+// Receiverはソケットを一つだけ開いて受信を続け、受信したフレームをSinkSetに渡す。
+// 表示・記録・転送はSinkSetに名前を付けて追加・削除するので、
+// 受信を止めずに記録だけを開始・停止できる。
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures_util::StreamExt;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_util::codec::LinesCodec;
+use tokio_util::sync::CancellationToken;
+use tokio_util::udp::UdpFramed;
+
+use crate::devices::DeviceTable;
+use crate::network::{bind_udp, NetworkError};
+use crate::sink::{FrameEvent, FrameSink};
+
+pub async fn bind_receiver(addr: &str) -> Result<UdpFramed<LinesCodec>, NetworkError> {
+    let sock = bind_udp(addr).await?;
+    Ok(UdpFramed::new(sock, LinesCodec::new()))
+}
+
+// ソケットが閉じられるまで受信を続ける。
+// 止める場合はtokio::select!などでこのfutureをdropする。
+pub async fn receive_frames(
+    framed: &mut UdpFramed<LinesCodec>,
+    devices: &Mutex<DeviceTable>,
+    sink: &mut dyn FrameSink,
+) {
+    while let Some(msg) = framed.next().await {
+        let (msg_str, addr) = match msg {
+            Ok(msg) => msg,
+            Err(why) => {
+                println!("receiver: {}", why);
+                continue;
+            }
+        };
+        if devices
+            .lock()
+            .await
+            .on_packet(addr, &msg_str, Instant::now())
+        {
+            println!("receiver: new device {}", addr);
+        }
+        sink.on_frame(&FrameEvent::live(&msg_str, addr));
+    }
+}
+
+// 名前を付けたFrameSinkの集合
+// cloneしたものは同じ集合を共有する。
+// 受信のループと同じロックの中で追加・削除するので、切り替えの前後でフレームを取りこぼさない。
+#[derive(Clone, Default)]
+pub struct SinkSet {
+    sinks: Arc<std::sync::Mutex<Vec<NamedSink>>>,
+}
+
+type NamedSink = (String, Box<dyn FrameSink>);
+
+impl SinkSet {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<NamedSink>> {
+        self.sinks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // 同じ名前のsinkがある場合は、closeしてから置き換える。
+    pub fn insert(&self, name: &str, sink: Box<dyn FrameSink>) {
+        let mut sinks = self.lock();
+        match sinks.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => {
+                existing.close();
+                *existing = sink;
+            }
+            None => sinks.push((name.to_string(), sink)),
+        }
+    }
+
+    // 取り外してcloseする。登録されていなかった場合はfalse
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = {
+            let mut sinks = self.lock();
+            sinks
+                .iter()
+                .position(|(n, _)| n == name)
+                .map(|i| sinks.remove(i).1)
+        };
+        match removed {
+            Some(mut sink) => {
+                sink.close();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.lock().iter().any(|(n, _)| n == name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.lock().iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    // すべて取り外してcloseする。
+    pub fn clear(&self) {
+        let sinks = std::mem::take(&mut *self.lock());
+        for (_, mut sink) in sinks {
+            sink.close();
+        }
+    }
+}
+
+impl FrameSink for SinkSet {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        for (_, sink) in self.lock().iter_mut() {
+            sink.on_frame(event);
+        }
+    }
+
+    fn close(&mut self) {
+        self.clear();
+    }
+}
+
+// 受信を続けるタスク
+// dropするとタスクを止めるが、SinkSetの中身はそのまま残る。
+pub struct Receiver {
+    addr: String,
+    cancel: CancellationToken,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Receiver {
+    // 待ち受けを開始する。tokioのランタイムの中で呼び出す。
+    // 別のアドレスで開き直す場合も、同じSinkSetを渡せば表示・記録はそのまま続く。
+    pub async fn start(
+        addr: &str,
+        devices: Arc<Mutex<DeviceTable>>,
+        sinks: SinkSet,
+    ) -> Result<Self, NetworkError> {
+        let mut framed = bind_receiver(addr).await?;
+        println!("receiver: start on {}", addr);
+        let cancel = CancellationToken::new();
+        let task_cancel = cancel.clone();
+        let mut task_sinks = sinks;
+        let task = tokio::spawn(async move {
+            tokio::select! {
+                _ = receive_frames(&mut framed, &devices, &mut task_sinks) => {},
+                _ = task_cancel.cancelled() => {},
+            }
+            println!("receiver: stop");
+        });
+        Ok(Self {
+            addr: addr.to_string(),
+            cancel,
+            task: Some(task),
+        })
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    // タスクが終わってソケットが閉じられるまで待つ。
+    // 直後に同じポートで開き直す場合に使う。
+    pub async fn stop(mut self) {
+        self.cancel.cancel();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
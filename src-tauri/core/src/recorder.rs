@@ -0,0 +1,292 @@
+// 受信したパケットをファイルに書き込むFrameSink。
+// 端末ごとに保存する場合は、新しい端末からの受信時にファイルを作成する。
+// RecordFormat::Compressedの場合は圧縮形式(compressed.rs)で保存する。
+// ファイルごとにセッションの情報(metadata.rs)をサイドカーとして保存する。
+// RotationOptionsを指定した場合は、一定の時間・サイズごとに番号を付けた新しいファイルに切り替え、
+// 各ファイルの一覧をセッションのインデックス(session.rs)に保存する。
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::compressed::CompressedWriter;
+use crate::devices::{device_id, device_record_path, tag_device, DeviceRecordMode};
+use crate::metadata::{MetadataWriter, SessionInfo};
+use crate::session::{part_path, SessionIndex};
+use crate::sink::{FrameEvent, FrameSink};
+
+// 記録ファイルの形式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordFormat {
+    // 1行に1フレームのjson
+    #[default]
+    Jsonl,
+    // 圧縮してインデックスを付けた形式
+    Compressed,
+}
+
+// 書き込んだ内容をディスクに反映(fsync)する頻度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPolicy {
+    // 記録の停止時とファイルの切り替え時だけ
+    #[default]
+    OnClose,
+    // 1秒ごと
+    EverySecond,
+    // フレームごと。クラッシュしても失うフレームが最も少ないが、書き込みが遅くなる。
+    EveryFrame,
+}
+
+// ファイルを分割する条件。どちらもNoneの場合は分割しない。
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RotationOptions {
+    // この時間(分)ごとに新しいファイルにする。
+    pub max_minutes: Option<f64>,
+    // ファイルがこの大きさ(MB)を超えたら新しいファイルにする。
+    pub max_megabytes: Option<f64>,
+}
+
+impl RotationOptions {
+    pub fn enabled(&self) -> bool {
+        self.max_minutes.is_some() || self.max_megabytes.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RecordOptions {
+    pub mode: DeviceRecordMode,
+    pub format: RecordFormat,
+    // サイドカーに保存するセッションの情報
+    pub info: SessionInfo,
+    pub rotation: RotationOptions,
+    pub sync: SyncPolicy,
+}
+
+enum RecordWriter {
+    // 書き込んだバイト数も数える。
+    Jsonl(BufWriter<File>, u64),
+    Compressed(CompressedWriter),
+}
+
+impl RecordWriter {
+    fn create(path: &Path, format: RecordFormat) -> io::Result<Self> {
+        Ok(match format {
+            RecordFormat::Jsonl => RecordWriter::Jsonl(BufWriter::new(File::create(path)?), 0),
+            RecordFormat::Compressed => RecordWriter::Compressed(CompressedWriter::create(path)?),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            // 最後に改行を追加して書き込む
+            RecordWriter::Jsonl(file, bytes) => {
+                writeln!(file, "{}", line)?;
+                *bytes += line.len() as u64 + 1;
+                Ok(())
+            }
+            RecordWriter::Compressed(writer) => writer.write_line(line),
+        }
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            RecordWriter::Jsonl(_, bytes) => *bytes,
+            RecordWriter::Compressed(writer) => writer.bytes_written(),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordWriter::Jsonl(file, _) => file.flush(),
+            // 圧縮形式の場合は書きかけのブロックとインデックスを書き込む。
+            RecordWriter::Compressed(writer) => writer.finish(),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match self {
+            RecordWriter::Jsonl(file, _) => {
+                file.flush()?;
+                file.get_ref().sync_data()
+            }
+            RecordWriter::Compressed(writer) => writer.sync(),
+        }
+    }
+}
+
+// 記録ファイルとそのサイドカー
+// 分割する場合はpathをもとに番号を付けたファイルを順に作る。
+struct RecordFile {
+    path: PathBuf,
+    writer: RecordWriter,
+    metadata: MetadataWriter,
+    // 分割する場合のみ
+    index: Option<SessionIndex>,
+    part_started: Instant,
+    last_sync: Instant,
+}
+
+impl RecordFile {
+    fn create(path: &Path, options: &RecordOptions) -> io::Result<Self> {
+        let index = options.rotation.enabled().then(SessionIndex::default);
+        let part = match index {
+            Some(_) => part_path(path, 1),
+            None => path.to_path_buf(),
+        };
+        let mut file = Self {
+            path: path.to_path_buf(),
+            writer: RecordWriter::create(&part, options.format)?,
+            metadata: MetadataWriter::create(&part, options.info.clone())?,
+            index,
+            part_started: Instant::now(),
+            last_sync: Instant::now(),
+        };
+        file.add_part(&part)?;
+        Ok(file)
+    }
+
+    // 次の番号のファイルに切り替える。
+    fn start_part(&mut self, options: &RecordOptions) -> io::Result<()> {
+        let number = match &self.index {
+            Some(index) => index.parts.len() + 1,
+            None => return Ok(()),
+        };
+        let part = part_path(&self.path, number);
+        self.writer = RecordWriter::create(&part, options.format)?;
+        self.metadata = MetadataWriter::create(&part, options.info.clone())?;
+        self.part_started = Instant::now();
+        self.add_part(&part)
+    }
+
+    // 分割する場合はインデックスにファイルを追加して保存する。
+    // 記録中に落ちても書きかけのファイルまで開けるように、書き込む前に保存しておく。
+    fn add_part(&mut self, part: &Path) -> io::Result<()> {
+        if let Some(index) = &mut self.index {
+            println!("recorder: new part {:?}", part);
+            index.push_part(part);
+            index.save_for(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self, rotation: &RotationOptions) -> bool {
+        if self.index.is_none() || self.metadata.metadata().frames == 0 {
+            return false;
+        }
+        let by_time = rotation
+            .max_minutes
+            .is_some_and(|minutes| self.part_started.elapsed().as_secs_f64() >= minutes * 60.0);
+        let by_size = rotation.max_megabytes.is_some_and(|megabytes| {
+            self.writer.bytes_written() as f64 >= megabytes * 1024.0 * 1024.0
+        });
+        by_time || by_size
+    }
+
+    fn write_line(
+        &mut self,
+        device_id: &str,
+        line: &str,
+        options: &RecordOptions,
+    ) -> io::Result<()> {
+        if self.should_rotate(&options.rotation) {
+            self.close()?;
+            self.start_part(options)?;
+        }
+        self.writer.write_line(line)?;
+        self.metadata.on_line(device_id, line);
+        let sync = match options.sync {
+            SyncPolicy::OnClose => false,
+            SyncPolicy::EverySecond => self.last_sync.elapsed() >= Duration::from_secs(1),
+            SyncPolicy::EveryFrame => true,
+        };
+        if sync {
+            self.writer.sync()?;
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    // 書きかけのファイルを閉じて、サイドカーにフレーム数と長さを書き込む。
+    fn close(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.sync()?;
+        self.metadata.finish()
+    }
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    options: RecordOptions,
+    // DeviceRecordMode::Mergedの場合はキーをNoneとする。
+    files: HashMap<Option<SocketAddr>, RecordFile>,
+    // 書き込んだ行数
+    pub frames: u64,
+}
+
+impl Recorder {
+    // Mergedの場合はこの時点でファイルを作成する。
+    pub fn create(path: &Path, options: RecordOptions) -> io::Result<Self> {
+        let mut files = HashMap::new();
+        if options.mode == DeviceRecordMode::Merged {
+            files.insert(None, RecordFile::create(path, &options)?);
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            options,
+            files,
+            frames: 0,
+        })
+    }
+
+    pub fn write(&mut self, addr: SocketAddr, msg_str: &str) -> io::Result<()> {
+        let device_id = device_id(&addr);
+        let (key, line) = match self.options.mode {
+            DeviceRecordMode::Merged => (None, tag_device(msg_str, &device_id)),
+            DeviceRecordMode::PerDevice => (Some(addr), msg_str.to_string()),
+        };
+        if !self.files.contains_key(&key) {
+            let device_path = device_record_path(&self.path, &addr);
+            println!("recorder: new device {}, {:?}", addr, device_path);
+            self.files
+                .insert(key, RecordFile::create(&device_path, &self.options)?);
+        }
+        let file = self.files.get_mut(&key).unwrap();
+        file.write_line(&device_id, &line, &self.options)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // 記録を終える前に呼ぶ。
+    // 呼ばなかった場合も、圧縮形式のインデックスはdrop時に書き込まれる。
+    // サイドカーにもフレーム数と長さを書き込む。
+    pub fn flush(&mut self) -> io::Result<()> {
+        for file in self.files.values_mut() {
+            file.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for Recorder {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        // 再生中のフレームは記録しない。
+        if let Some(addr) = event.source {
+            if let Err(why) = self.write(addr, event.json_str) {
+                println!("recorder: {}", why);
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        match self.flush() {
+            Ok(()) => println!("recorder: {} frames written.", self.frames),
+            Err(why) => println!("recorder: {}", why),
+        }
+    }
+}
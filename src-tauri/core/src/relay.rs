@@ -0,0 +1,151 @@
+// 受信・再生したフレームを複数の送信先にUDPで転送する。
+// 一台の端末からHIROMEIRO、Unityのクライアント、別のPCでの記録などに同時に送るために使う。
+// 送信先は受信・再生の途中でも追加・削除できる。
+//
+// ストリームを指定しない送信先には受信したjsonをそのまま送り、
+// 指定した場合はそのストリームだけを含むjsonに作り直して送る。
+
+use std::io;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::holistic::is_stream_name;
+use crate::network::{socket_addr_string, NetworkError};
+use crate::sink::{FrameEvent, FrameSink};
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RelayTarget {
+    pub host: String,
+    pub port: u16,
+    // 送信するストリーム。Noneの場合はすべて送る。
+    #[serde(default)]
+    pub streams: Option<Vec<String>>,
+}
+
+impl RelayTarget {
+    pub fn addr(&self) -> String {
+        socket_addr_string(&self.host, self.port)
+    }
+}
+
+struct Connection {
+    target: RelayTarget,
+    sock: UdpSocket,
+}
+
+impl Connection {
+    fn open(target: RelayTarget) -> io::Result<Self> {
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(target.addr())?;
+        Ok(Self { target, sock })
+    }
+}
+
+// 送信先の一覧
+// cloneしたものは同じ一覧を共有するので、sinkを作った後でも追加・削除が反映される。
+#[derive(Clone, Default)]
+pub struct RelayTargets {
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl RelayTargets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 転送中のスレッドがpanicしても一覧は壊れないので、そのまま使う。
+    fn lock(&self) -> MutexGuard<'_, Vec<Connection>> {
+        self.connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // 送信先を追加する。同じアドレスが登録済みの場合はストリームの指定を置き換える。
+    pub fn add(&self, target: RelayTarget) -> Result<(), NetworkError> {
+        if let Some(streams) = &target.streams {
+            if let Some(name) = streams.iter().find(|name| !is_stream_name(name)) {
+                return Err(NetworkError::UnknownStream {
+                    stream: name.clone(),
+                });
+            }
+        }
+        let addr = target.addr();
+        let connection = Connection::open(target).map_err(|why| NetworkError::Io {
+            addr: addr.clone(),
+            reason: why.to_string(),
+        })?;
+        let mut connections = self.lock();
+        match connections.iter_mut().find(|c| c.target.addr() == addr) {
+            Some(existing) => *existing = connection,
+            None => connections.push(connection),
+        }
+        println!("relay: add {}", addr);
+        Ok(())
+    }
+
+    // 送信先を削除する。登録されていなかった場合はfalse
+    pub fn remove(&self, host: &str, port: u16) -> bool {
+        let addr = socket_addr_string(host, port);
+        let mut connections = self.lock();
+        let len = connections.len();
+        connections.retain(|c| c.target.addr() != addr);
+        let removed = connections.len() != len;
+        if removed {
+            println!("relay: remove {}", addr);
+        }
+        removed
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub fn list(&self) -> Vec<RelayTarget> {
+        self.lock().iter().map(|c| c.target.clone()).collect()
+    }
+
+    // 受信・再生のFrameSinkに加えるためのsink
+    pub fn sink(&self) -> RelaySink {
+        RelaySink {
+            targets: self.clone(),
+        }
+    }
+}
+
+// 登録されているすべての送信先にフレームを転送するFrameSink
+// 送信先がない場合は何もしない。
+pub struct RelaySink {
+    targets: RelayTargets,
+}
+
+impl FrameSink for RelaySink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        let connections = self.targets.lock();
+        if connections.is_empty() {
+            return;
+        }
+        // ストリームを指定した送信先がある場合だけjsonを読み込む。
+        let mut frame = None;
+        for connection in connections.iter() {
+            let data = match &connection.target.streams {
+                None => event.json_str.to_string(),
+                Some(names) => {
+                    let holistic = match frame.get_or_insert_with(|| event.holistic()) {
+                        Some(holistic) => holistic,
+                        None => continue,
+                    };
+                    match holistic
+                        .select_streams(names)
+                        .and_then(|selected| selected.to_json_string().ok())
+                    {
+                        Some(data) => data,
+                        None => continue,
+                    }
+                }
+            };
+            if let Err(why) = connection.sock.send(data.as_bytes()) {
+                println!("relay: {}: {}", connection.target.addr(), why);
+            }
+        }
+    }
+}
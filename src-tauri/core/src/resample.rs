@@ -0,0 +1,415 @@
+// 記録を一定のフレームレートに揃える。
+// 記録のpose_landmarks_stampの間隔は一定ではなく、顔や手のストリームは
+// pose_landmarks_stampとは異なる(遅れた)タイムスタンプを持つ。
+// 出力の各時刻について、ストリームごとに自身の*_stampで前後のフレームを探して補間する。
+//
+// ランドマークの位置は線形補間、gravityは向きを球面線形補間し、大きさを線形補間する。
+// 出力の*_stampはすべて出力の時刻にする(gravity_stampはナノ秒)。
+// ストリームの範囲外の時刻や、前後のフレームが大きく空いている時刻は補間せず、そのストリームを含めない。
+// 前後の値の間にそのストリームを含まないフレームがある(認識されなかった区間をまたいで補間した)場合と、
+// 元のフレームで補った値(HolisticFrame::filled)を使った場合は、出力のfilledにそのストリームを入れる。
+// gravityはfilledで区別できないので、含まないフレームをまたいでは補間しない。
+// 複数端末を一つのファイルに記録した場合は、端末ごとに補間してから時刻順に並べる。
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::export::{ExportError, MotionSummary};
+use crate::frames::TrackingFrame;
+use crate::holistic::{CameraParams, HolisticFrame, Landmark, LandmarkStream};
+use crate::math::{Quat, Vec3};
+use crate::store::FrameSource;
+
+// 前後のフレームがこれ以上空いている場合は補間しない[usec]
+const MAX_GAP: u64 = 500_000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResampleOptions {
+    // 出力のフレームレート[fps]
+    pub fps: f64,
+}
+
+impl Default for ResampleOptions {
+    fn default() -> Self {
+        Self { fps: 30.0 }
+    }
+}
+
+impl ResampleOptions {
+    // 不正な値の場合は既定のフレームレートを使う。
+    pub fn frame_rate(&self) -> f64 {
+        if self.fps.is_finite() && self.fps > 0.0 {
+            self.fps
+        } else {
+            Self::default().fps
+        }
+    }
+}
+
+// 一つのタイムスタンプの値と、その値を含んでいた元のフレームの範囲(端末ごとの番号)
+// 顔や手は同じタイムスタンプの値が複数のフレームに含まれることがある。
+struct Sample<T> {
+    stamp: u64,
+    value: T,
+    first_frame: usize,
+    last_frame: usize,
+}
+
+// 時刻の前後の値
+struct Bracket<'a, T> {
+    a: &'a T,
+    b: &'a T,
+    // aとbの間での位置(0から1)
+    alpha: f64,
+    // aとbの間に、この値を含まないフレームがある。
+    bridged: bool,
+}
+
+// タイムスタンプ順に並べた値。タイムスタンプが前の値より小さいものは捨てる。
+struct Track<T> {
+    samples: Vec<Sample<T>>,
+}
+
+impl<T> Default for Track<T> {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl<T> Track<T> {
+    // frameは元のフレームの番号
+    fn push(&mut self, stamp: u64, value: T, frame: usize) {
+        if let Some(last) = self.samples.last_mut() {
+            if stamp == last.stamp {
+                last.last_frame = frame;
+                return;
+            }
+            if stamp < last.stamp {
+                return;
+            }
+        }
+        self.samples.push(Sample {
+            stamp,
+            value,
+            first_frame: frame,
+            last_frame: frame,
+        });
+    }
+
+    fn bracket(&self, t: u64) -> Option<Bracket<'_, T>> {
+        let n = self.samples.partition_point(|sample| sample.stamp <= t);
+        if n == 0 {
+            return None;
+        }
+        let a = &self.samples[n - 1];
+        if a.stamp == t {
+            return Some(Bracket {
+                a: &a.value,
+                b: &a.value,
+                alpha: 0.0,
+                bridged: false,
+            });
+        }
+        let b = self.samples.get(n)?;
+        if b.stamp - a.stamp > MAX_GAP {
+            return None;
+        }
+        Some(Bracket {
+            a: &a.value,
+            b: &b.value,
+            alpha: (t - a.stamp) as f64 / (b.stamp - a.stamp) as f64,
+            bridged: b.first_frame > a.last_frame + 1,
+        })
+    }
+
+    // 時刻t以前の最後の値。t以前にない場合は最初の値
+    fn latest(&self, t: u64) -> Option<&T> {
+        let n = self.samples.partition_point(|sample| sample.stamp <= t);
+        self.samples
+            .get(n.saturating_sub(1))
+            .map(|sample| &sample.value)
+    }
+}
+
+// 端末一つ分のフレームをストリームごとに分けたもの
+#[derive(Default)]
+struct DeviceTracks {
+    // TrackingFrame::timestampの範囲
+    first: Option<u64>,
+    last: u64,
+    // 追加したフレームの数
+    frames: usize,
+    // ランドマークと、元のフレームで補った値かどうか
+    landmarks: HashMap<LandmarkStream, Track<(Vec<Landmark>, bool)>>,
+    // gravity_stampはマイクロ秒に直して持つ。
+    gravity: Track<[f64; 3]>,
+    camera_params: Track<CameraParams>,
+}
+
+impl DeviceTracks {
+    fn add(&mut self, tf: &TrackingFrame) {
+        self.first.get_or_insert(tf.timestamp);
+        self.last = self.last.max(tf.timestamp);
+        let n = self.frames;
+        self.frames += 1;
+        let frame = &tf.frame;
+        for stream in LandmarkStream::ALL {
+            if let Some(landmarks) = frame.landmarks(stream) {
+                let stamp = frame.stamp(stream).unwrap_or(tf.timestamp);
+                let filled = frame
+                    .filled
+                    .as_ref()
+                    .is_some_and(|filled| filled.streams.contains(&stream));
+                self.landmarks.entry(stream).or_default().push(
+                    stamp,
+                    (landmarks.clone(), filled),
+                    n,
+                );
+            }
+        }
+        if let (Some(gravity), Some(stamp)) = (frame.gravity, frame.gravity_stamp) {
+            self.gravity.push(stamp / 1000, gravity, n);
+        }
+        if let Some(camera_params) = &frame.camera_params {
+            self.camera_params
+                .push(tf.timestamp, camera_params.clone(), n);
+        }
+    }
+
+    // 時刻tのフレーム。補間できたストリームが一つもない場合はNone
+    fn sample(&self, device_id: &Option<String>, t: u64) -> Option<HolisticFrame> {
+        let mut frame = HolisticFrame {
+            device_id: device_id.clone(),
+            camera_params: self.camera_params.latest(t).cloned(),
+            ..Default::default()
+        };
+        let mut found = false;
+        for stream in LandmarkStream::ALL {
+            let bracket = self
+                .landmarks
+                .get(&stream)
+                .and_then(|track| track.bracket(t));
+            if let Some(Bracket {
+                a: (a, a_filled),
+                b: (b, b_filled),
+                alpha,
+                bridged,
+            }) = bracket
+            {
+                *frame.landmarks_mut(stream) = Some(lerp_landmarks(a, b, alpha));
+                *frame.stamp_mut(stream) = Some(t);
+                if bridged || *a_filled || (alpha > 0.0 && *b_filled) {
+                    let filled = frame.filled.get_or_insert_with(Default::default);
+                    filled.streams.push(stream);
+                }
+                found = true;
+            }
+        }
+        let gravity = self.gravity.bracket(t).filter(|bracket| !bracket.bridged);
+        if let Some(Bracket { a, b, alpha, .. }) = gravity {
+            frame.gravity = Some(slerp_gravity(*a, *b, alpha));
+            frame.gravity_stamp = Some(t * 1000);
+            found = true;
+        }
+        found.then_some(frame)
+    }
+}
+
+fn lerp_option(a: Option<f64>, b: Option<f64>, t: f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + (b - a) * t),
+        _ if t < 0.5 => a,
+        _ => b,
+    }
+}
+
+// 点の数が違う場合は近い方のフレームのものを使う。
+pub(crate) fn lerp_landmarks(a: &[Landmark], b: &[Landmark], t: f64) -> Vec<Landmark> {
+    if a.len() != b.len() {
+        return if t < 0.5 { a.to_vec() } else { b.to_vec() };
+    }
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| Landmark {
+            x: a.x + (b.x - a.x) * t,
+            y: a.y + (b.y - a.y) * t,
+            z: a.z + (b.z - a.z) * t,
+            visibility: lerp_option(a.visibility, b.visibility, t),
+            presence: lerp_option(a.presence, b.presence, t),
+        })
+        .collect()
+}
+
+// aからbへの回転をslerpしてaの向きを回し、大きさは線形補間する。
+// 向きが決められない場合(長さが0、正反対の向き)は線形補間する。
+fn slerp_gravity(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    let va = Vec3::new(a[0], a[1], a[2]);
+    let vb = Vec3::new(b[0], b[1], b[2]);
+    let length = va.length() + (vb.length() - va.length()) * t;
+    let v = match (va.normalize(), Quat::from_two_vectors(va, vb)) {
+        (Some(direction), Some(rotation)) => {
+            Quat::IDENTITY.slerp(rotation, t).rotate(direction) * length
+        }
+        _ => va.lerp(vb, t),
+    };
+    [v.x, v.y, v.z]
+}
+
+// 一定のフレームレートで補間したフレームを返す。
+pub fn resample<S: FrameSource + ?Sized>(
+    frames: &S,
+    options: &ResampleOptions,
+) -> Result<Vec<TrackingFrame>, ExportError> {
+    // 端末の順番は最初に現れた順にする。
+    let mut devices: Vec<(Option<String>, DeviceTracks)> = Vec::new();
+    for idx in 0..frames.len() {
+        let tf = match frames.frame(idx) {
+            Some(tf) => tf,
+            None => continue,
+        };
+        let device_id = &tf.frame.device_id;
+        let n = match devices.iter().position(|(id, _)| id == device_id) {
+            Some(n) => n,
+            None => {
+                devices.push((device_id.clone(), DeviceTracks::default()));
+                devices.len() - 1
+            }
+        };
+        devices[n].1.add(&tf);
+    }
+
+    let frame_time = 1e6 / options.frame_rate();
+    let mut resampled = Vec::new();
+    for (device_id, tracks) in devices.iter() {
+        let first = match tracks.first {
+            Some(first) => first,
+            None => continue,
+        };
+        let count = ((tracks.last - first) as f64 / frame_time).floor() as usize + 1;
+        for i in 0..count {
+            let t = first + (i as f64 * frame_time).round() as u64;
+            if let Some(frame) = tracks.sample(device_id, t) {
+                let json_str = frame.to_json_string().map_err(|why| ExportError::Io {
+                    reason: why.to_string(),
+                })?;
+                resampled.push(TrackingFrame {
+                    json_str,
+                    frame,
+                    timestamp: t,
+                });
+            }
+        }
+    }
+    if resampled.is_empty() {
+        return Err(ExportError::Empty);
+    }
+    // 端末ごとに時刻順なので、安定ソートで端末の順番を保ったまま並べる。
+    resampled.sort_by_key(|tf| tf.timestamp);
+    Ok(resampled)
+}
+
+// 一定のフレームレートで補間したフレームを、1行1フレームのjsonとして書き出す。
+pub fn write_resampled<S: FrameSource + ?Sized>(
+    frames: &S,
+    path: &Path,
+    options: &ResampleOptions,
+) -> Result<MotionSummary, ExportError> {
+    let resampled = resample(frames, options)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    for tf in resampled.iter() {
+        writeln!(writer, "{}", tf.json_str)?;
+    }
+    writer.flush()?;
+    Ok(MotionSummary {
+        path: path.to_string_lossy().to_string(),
+        frames: resampled.len(),
+        frame_rate: options.frame_rate(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: u64, hand_stamp: Option<u64>) -> TrackingFrame {
+        let landmarks = |x: f64| {
+            vec![
+                Landmark {
+                    x,
+                    ..Default::default()
+                };
+                21
+            ]
+        };
+        let frame = HolisticFrame {
+            pose_landmarks: Some(landmarks(0.0)),
+            pose_landmarks_stamp: Some(timestamp),
+            right_hand_landmarks: hand_stamp.map(|stamp| landmarks(stamp as f64)),
+            right_hand_landmarks_stamp: hand_stamp,
+            ..Default::default()
+        };
+        TrackingFrame {
+            json_str: String::new(),
+            frame,
+            timestamp,
+        }
+    }
+
+    fn hand_filled(tf: &TrackingFrame) -> bool {
+        tf.frame
+            .filled
+            .as_ref()
+            .is_some_and(|filled| filled.streams.contains(&LandmarkStream::RightHand))
+    }
+
+    #[test]
+    fn flag_streams_interpolated_across_dropouts() {
+        let frames = vec![
+            frame(0, Some(0)),
+            frame(100_000, None),
+            frame(200_000, None),
+            frame(300_000, Some(300_000)),
+        ];
+        let resampled = resample(&frames, &ResampleOptions { fps: 10.0 }).unwrap();
+        assert_eq!(resampled.len(), 4);
+        let filled: Vec<bool> = resampled.iter().map(hand_filled).collect();
+        assert_eq!(filled, vec![false, true, true, false]);
+        let x = resampled[1].frame.right_hand_landmarks.as_ref().unwrap()[0].x;
+        assert!((x - 100_000.0).abs() < 1e-6);
+        assert!(resampled.iter().all(|tf| tf
+            .frame
+            .filled
+            .as_ref()
+            .is_none_or(|f| f.streams.len() == 1)));
+    }
+
+    #[test]
+    fn held_stamps_are_not_dropouts() {
+        // 手のタイムスタンプが前のフレームと同じ場合は、認識されていたものとして扱う。
+        let frames = vec![
+            frame(0, Some(0)),
+            frame(100_000, Some(0)),
+            frame(200_000, Some(200_000)),
+        ];
+        let resampled = resample(&frames, &ResampleOptions { fps: 20.0 }).unwrap();
+        assert_eq!(resampled.len(), 5);
+        assert!(resampled.iter().all(|tf| !hand_filled(tf)));
+        assert!(resampled
+            .iter()
+            .all(|tf| tf.frame.right_hand_landmarks.is_some()));
+    }
+
+    #[test]
+    fn do_not_interpolate_long_gaps() {
+        let frames = vec![frame(0, Some(0)), frame(1_000_000, Some(1_000_000))];
+        let resampled = resample(&frames, &ResampleOptions { fps: 10.0 }).unwrap();
+        assert_eq!(resampled.len(), 2);
+    }
+}
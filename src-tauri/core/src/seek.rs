@@ -0,0 +1,220 @@
+// フレーム番号ではなく時刻で移動するための関数。
+// タイムスタンプは昇順に並んでいるものとして二分探索する。
+
+use crate::store::FrameSource;
+
+// 移動先の指定方法
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeekTarget {
+    // タイムスタンプ[microsec]
+    Timestamp(u64),
+    // 先頭フレームからの経過時間[microsec]
+    Elapsed(u64),
+    // 現在のフレームからNフレーム移動する
+    Frames(i64),
+    // 現在のフレームからNミリ秒移動する
+    Millis(i64),
+}
+
+// 移動した結果
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct SeekResult {
+    pub current_frame: usize,
+    pub current_stamp: u64,
+    // 先頭フレームからの経過時間[microsec]
+    pub elapsed: u64,
+}
+
+impl SeekResult {
+    pub fn new<S: FrameSource + ?Sized>(frames: &S, idx: usize) -> Self {
+        let current_stamp = frames.timestamp(idx);
+        Self {
+            current_frame: idx,
+            current_stamp,
+            elapsed: current_stamp.saturating_sub(frames.timestamp(0)),
+        }
+    }
+}
+
+// predがtrueになる先頭からのフレーム数
+// slice::partition_pointと同じだが、タイムスタンプだけを読む。
+fn partition_point<S: FrameSource + ?Sized>(frames: &S, pred: impl Fn(u64) -> bool) -> usize {
+    let mut low = 0;
+    let mut high = frames.len();
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(frames.timestamp(mid)) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+// timestamp以前で最も新しいフレーム
+// 先頭より前を指定した場合は先頭を返す。
+pub fn index_at_or_before<S: FrameSource + ?Sized>(frames: &S, timestamp: u64) -> usize {
+    let n = partition_point(frames, |t| t <= timestamp);
+    n.saturating_sub(1)
+}
+
+// timestamp以降で最も古いフレーム
+// 末尾より後を指定した場合は末尾を返す。
+pub fn index_at_or_after<S: FrameSource + ?Sized>(frames: &S, timestamp: u64) -> usize {
+    let n = partition_point(frames, |t| t < timestamp);
+    n.min(frames.len() - 1)
+}
+
+// currentから見たtargetのフレーム番号を返す。framesは空でないこと。
+// 時間で進める場合は指定時刻以降の最初のフレーム、
+// 時間で戻る場合は指定時刻以前の最後のフレームに移動する。
+pub fn seek_index<S: FrameSource + ?Sized>(
+    frames: &S,
+    current: usize,
+    target: SeekTarget,
+) -> usize {
+    let last = frames.len() - 1;
+    let current = current.min(last);
+    match target {
+        SeekTarget::Timestamp(timestamp) => index_at_or_before(frames, timestamp),
+        SeekTarget::Elapsed(elapsed) => {
+            index_at_or_before(frames, frames.timestamp(0).saturating_add(elapsed))
+        }
+        SeekTarget::Frames(n) => {
+            if n >= 0 {
+                current.saturating_add(n as usize).min(last)
+            } else {
+                current.saturating_sub(n.unsigned_abs() as usize)
+            }
+        }
+        SeekTarget::Millis(ms) => {
+            let t = frames.timestamp(current);
+            let delta = ms.unsigned_abs().saturating_mul(1000);
+            if ms >= 0 {
+                index_at_or_after(frames, t.saturating_add(delta))
+            } else {
+                index_at_or_before(frames, t.saturating_sub(delta))
+            }
+        }
+    }
+}
+
+// オフラインでの再生位置。再生、ステップ実行、シークで共有する。
+// nextは最後のフレームの次で0に戻るので、シークの起点には表示したフレームを別に持つ。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlaybackPosition {
+    // 次に再生するフレーム
+    pub next: usize,
+    // 最後に表示したフレーム。ファイルを開いた直後はNone
+    pub current: Option<usize>,
+}
+
+impl PlaybackPosition {
+    // idxのフレームを表示した。lenはフレーム数
+    pub fn show(&mut self, idx: usize, len: usize) {
+        self.current = Some(idx);
+        self.next = (idx + 1) % len.max(1);
+    }
+
+    // シークの起点。まだ表示していない場合は、フロントエンドに通知している先頭のフレーム
+    pub fn seek_origin(&self) -> usize {
+        self.current.unwrap_or(0)
+    }
+
+    // 次(forwardがfalseなら前)のフレームに移動して、そのフレーム番号を返す。framesは空でないこと。
+    pub fn step(&mut self, len: usize, forward: bool) -> usize {
+        let next = self.next % len;
+        let idx = if forward {
+            next
+        } else {
+            (next + 2 * len - 2) % len
+        };
+        self.show(idx, len);
+        idx
+    }
+
+    // targetに移動して、そのフレーム番号を返す。framesは空でないこと。
+    pub fn seek<S: FrameSource + ?Sized>(&mut self, frames: &S, target: SeekTarget) -> usize {
+        let idx = seek_index(frames, self.seek_origin(), target);
+        self.show(idx, frames.len());
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frames::TrackingFrame;
+    use crate::holistic::HolisticFrame;
+
+    fn frames(stamps: &[u64]) -> Vec<TrackingFrame> {
+        stamps
+            .iter()
+            .map(|stamp| TrackingFrame {
+                json_str: String::new(),
+                frame: HolisticFrame::default(),
+                timestamp: *stamp,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seek_by_timestamp() {
+        let frames = frames(&[1000, 2000, 3000, 4000]);
+        assert_eq!(seek_index(&frames, 0, SeekTarget::Timestamp(2500)), 1);
+        assert_eq!(seek_index(&frames, 0, SeekTarget::Timestamp(0)), 0);
+        assert_eq!(seek_index(&frames, 0, SeekTarget::Timestamp(9000)), 3);
+        assert_eq!(seek_index(&frames, 0, SeekTarget::Elapsed(2000)), 2);
+    }
+
+    #[test]
+    fn seek_relative() {
+        let frames = frames(&[1000, 2000, 3000, 4000]);
+        assert_eq!(seek_index(&frames, 1, SeekTarget::Frames(1)), 2);
+        assert_eq!(seek_index(&frames, 1, SeekTarget::Frames(10)), 3);
+        assert_eq!(seek_index(&frames, 1, SeekTarget::Frames(-10)), 0);
+        assert_eq!(seek_index(&frames, 3, SeekTarget::Frames(-1)), 2);
+        assert_eq!(seek_index(&frames, 1, SeekTarget::Millis(1)), 2);
+        assert_eq!(seek_index(&frames, 2, SeekTarget::Millis(-1)), 1);
+        assert_eq!(seek_index(&frames, 2, SeekTarget::Millis(-10)), 0);
+    }
+
+    #[test]
+    fn seek_from_fresh_position() {
+        let frames = frames(&[1000, 2000, 3000, 4000]);
+        let mut position = PlaybackPosition::default();
+        assert_eq!(position.seek(&frames, SeekTarget::Frames(1)), 1);
+        let mut position = PlaybackPosition::default();
+        assert_eq!(position.seek(&frames, SeekTarget::Millis(1)), 1);
+        assert_eq!(position.next, 2);
+    }
+
+    #[test]
+    fn seek_after_wrap() {
+        let frames = frames(&[1000, 2000, 3000, 4000]);
+        let mut position = PlaybackPosition::default();
+        position.show(3, frames.len());
+        assert_eq!(position.next, 0);
+        assert_eq!(position.seek(&frames, SeekTarget::Frames(-1)), 2);
+        position.show(3, frames.len());
+        assert_eq!(position.seek(&frames, SeekTarget::Millis(1)), 3);
+    }
+
+    #[test]
+    fn step_both_ways() {
+        let mut position = PlaybackPosition::default();
+        assert_eq!(position.step(4, true), 0);
+        assert_eq!(position.step(4, false), 3);
+        assert_eq!(position.step(4, true), 0);
+        assert_eq!(position.step(4, true), 1);
+        assert_eq!(position.step(4, false), 0);
+        // 再生を最後まで進めてnextがフレーム数になった場合
+        let mut position = PlaybackPosition {
+            next: 4,
+            current: Some(3),
+        };
+        assert_eq!(position.step(4, false), 2);
+    }
+}
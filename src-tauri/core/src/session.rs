@@ -0,0 +1,218 @@
+// 分割して記録したファイルを一つのセッションとしてまとめる。
+// record.datに対して、record_0001.dat, record_0002.dat, ...と番号を付けたファイルに記録し、
+// その一覧をインデックスrecord.dat.session.jsonに保存する。
+// インデックスを開くと、すべてのファイルを一続きのフレームとして読み込む。
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::frames::{LineError, LoadError, LoadPolicy, LoadSummary, TrackingFrame};
+use crate::metadata::SessionMetadata;
+use crate::store::{FrameSource, FrameStore};
+
+pub const SESSION_SUFFIX: &str = ".session.json";
+
+// ファイル形式のバージョン
+const SESSION_VERSION: u32 = 1;
+
+// 記録ファイルに対応するインデックスのパス
+pub fn session_index_path(record_path: &Path) -> PathBuf {
+    let mut name = record_path.as_os_str().to_os_string();
+    name.push(SESSION_SUFFIX);
+    PathBuf::from(name)
+}
+
+pub fn is_session_index(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(SESSION_SUFFIX)
+}
+
+// 分割したファイルのパス。番号は1から始める。
+// record.datの場合はrecord_0001.dat
+pub fn part_path(record_path: &Path, number: usize) -> PathBuf {
+    let stem = record_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match record_path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, number, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, number),
+    };
+    record_path.with_file_name(file_name)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionIndex {
+    pub version: u32,
+    // 分割したファイルを記録した順に並べる。インデックスからの相対パス
+    pub parts: Vec<String>,
+}
+
+impl SessionIndex {
+    pub fn load(index_path: &Path) -> io::Result<Self> {
+        let s = fs::read_to_string(index_path)?;
+        serde_json::from_str(&s).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+    }
+
+    pub fn save_for(&self, record_path: &Path) -> io::Result<()> {
+        let index = Self {
+            version: SESSION_VERSION,
+            ..self.clone()
+        };
+        let s = serde_json::to_string_pretty(&index)?;
+        fs::write(session_index_path(record_path), s)
+    }
+
+    // 分割したファイルはインデックスと同じディレクトリに作るので、ファイル名だけを保存する。
+    pub fn push_part(&mut self, part: &Path) {
+        let name = part
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.parts.push(name);
+    }
+
+    pub fn part_paths(&self, index_path: &Path) -> Vec<PathBuf> {
+        let dir = index_path.parent().unwrap_or(Path::new(""));
+        self.parts.iter().map(|part| dir.join(part)).collect()
+    }
+}
+
+// 分割したファイルを一続きにしたFrameSource
+pub struct SessionFrames {
+    parts: Vec<FrameStore>,
+    // 各ファイルの最初のフレームの番号
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl SessionFrames {
+    // インデックスにあるファイルを順に開く。
+    // 空のファイルや開けないファイル(記録中に落ちた場合の最後のファイルなど)は飛ばす。
+    pub fn open(index_path: &Path, policy: LoadPolicy) -> Result<(Self, LoadSummary), LoadError> {
+        let index = SessionIndex::load(index_path)?;
+        let mut frames = Self {
+            parts: Vec::new(),
+            starts: Vec::new(),
+            len: 0,
+        };
+        let mut summaries = Vec::new();
+        let mut errors = Vec::new();
+        for (name, path) in index.parts.iter().zip(index.part_paths(index_path)) {
+            match FrameStore::open(&path, policy) {
+                Ok((store, summary)) => {
+                    frames.starts.push(frames.len);
+                    frames.len += store.len();
+                    frames.parts.push(store);
+                    summaries.push((name, summary));
+                }
+                Err(LoadError::Malformed { line, reason }) => {
+                    return Err(LoadError::Malformed {
+                        line,
+                        reason: format!("{}: {}", name, reason),
+                    });
+                }
+                Err(why) => {
+                    println!("session: {}: {}", name, why);
+                    errors.push(LineError {
+                        line: 0,
+                        reason: format!("{}: {}", name, why),
+                    });
+                }
+            }
+        }
+        if frames.parts.is_empty() {
+            return Err(LoadError::Empty { errors });
+        }
+        let mut summary = merge_summaries(summaries);
+        summary.path = index_path.to_string_lossy().to_string();
+        summary.errors.extend(errors);
+        Ok((frames, summary))
+    }
+
+    pub fn parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    // フレームの番号から、ファイルの番号とその中での番号を求める。
+    fn locate(&self, idx: usize) -> (usize, usize) {
+        let part = self.starts.partition_point(|start| *start <= idx) - 1;
+        (part, idx - self.starts[part])
+    }
+}
+
+// 各ファイルのLoadSummaryを一つにまとめる。壊れた行にはファイル名を付ける。
+fn merge_summaries(summaries: Vec<(&String, LoadSummary)>) -> LoadSummary {
+    let mut merged = LoadSummary::default();
+    let mut metadata: Option<SessionMetadata> = None;
+    for (i, (name, summary)) in summaries.into_iter().enumerate() {
+        if i == 0 {
+            merged.begin_timestamp = summary.begin_timestamp;
+        }
+        merged.total_lines += summary.total_lines;
+        merged.loaded_frames += summary.loaded_frames;
+        merged.skipped_lines += summary.skipped_lines;
+        merged.fallback_frames += summary.fallback_frames;
+        merged.end_timestamp = summary.end_timestamp;
+        merged.streaming |= summary.streaming;
+        merged
+            .errors
+            .extend(summary.errors.into_iter().map(|error| LineError {
+                line: error.line,
+                reason: format!("{}: {}", name, error.reason),
+            }));
+        metadata = match (metadata, summary.metadata) {
+            (None, part) => part,
+            (Some(merged), None) => Some(merged),
+            (Some(merged), Some(part)) => Some(merge_metadata(merged, part)),
+        };
+    }
+    merged.metadata = metadata;
+    merged
+}
+
+// 最初のファイルの情報に、後のファイルのフレーム数や停止時刻を足す。
+fn merge_metadata(mut merged: SessionMetadata, part: SessionMetadata) -> SessionMetadata {
+    for source in part.sources {
+        if !merged.sources.contains(&source) {
+            merged.sources.push(source);
+        }
+    }
+    if merged.camera_params.is_none() {
+        merged.camera_params = part.camera_params;
+    }
+    merged.frames += part.frames;
+    merged.first_timestamp = merged.first_timestamp.or(part.first_timestamp);
+    if part.last_timestamp.is_some() {
+        merged.last_timestamp = part.last_timestamp;
+    }
+    merged.stopped_at = part.stopped_at;
+    merged.duration_sec = match (merged.first_timestamp, merged.last_timestamp) {
+        (Some(first), Some(last)) if last >= first => Some((last - first) as f64 / 1e6),
+        _ => None,
+    };
+    merged
+}
+
+impl FrameSource for SessionFrames {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        let (part, idx) = self.locate(idx);
+        self.parts[part].timestamp(idx)
+    }
+
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>> {
+        if idx >= self.len {
+            return None;
+        }
+        let (part, idx) = self.locate(idx);
+        self.parts[part].frame(idx)
+    }
+}
@@ -0,0 +1,118 @@
+// 受信・再生したフレームを受け取るためのtrait。
+// Tauriのウィンドウ、ファイルへの記録、UDP送信などはそれぞれFrameSinkとして実装する。
+
+use std::borrow::Cow;
+use std::net::SocketAddr;
+
+use crate::devices::device_id;
+use crate::holistic::HolisticFrame;
+
+// フレームがどこから来たか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameOrigin {
+    // UDPで受信した
+    Live,
+    // 記録したファイルを再生した
+    Playback,
+}
+
+pub struct FrameEvent<'a> {
+    pub origin: FrameOrigin,
+    // 受信・記録したjson文字列そのもの
+    pub json_str: &'a str,
+    // 受信時の送信元。再生時はNone
+    pub source: Option<SocketAddr>,
+    // 再生時のフレーム番号。受信時は0
+    pub frame_index: usize,
+    // 再生時のタイムスタンプ。受信時は0
+    pub timestamp: u64,
+    // 再生時は読み込み済みの内容を渡す。
+    pub frame: Option<&'a HolisticFrame>,
+}
+
+impl<'a> FrameEvent<'a> {
+    pub fn live(json_str: &'a str, source: SocketAddr) -> Self {
+        Self {
+            origin: FrameOrigin::Live,
+            json_str,
+            source: Some(source),
+            frame_index: 0,
+            timestamp: 0,
+            frame: None,
+        }
+    }
+
+    pub fn playback(
+        json_str: &'a str,
+        frame: &'a HolisticFrame,
+        frame_index: usize,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            origin: FrameOrigin::Playback,
+            json_str,
+            source: None,
+            frame_index,
+            timestamp,
+            frame: Some(frame),
+        }
+    }
+
+    pub fn device_id(&self) -> Option<String> {
+        self.source.as_ref().map(device_id)
+    }
+
+    // 型付きの内容を返す。
+    // 受信時はここでjsonを読み込むので、必要なFrameSinkだけが呼び出す。
+    pub fn holistic(&self) -> Option<Cow<'a, HolisticFrame>> {
+        match self.frame {
+            Some(frame) => Some(Cow::Borrowed(frame)),
+            None => HolisticFrame::from_json_str(self.json_str)
+                .ok()
+                .map(Cow::Owned),
+        }
+    }
+}
+
+pub trait FrameSink: Send {
+    fn on_frame(&mut self, event: &FrameEvent);
+
+    // 受信・再生の途中で取り外すときに呼ばれる。
+    // ファイルへの書き込みなど、後始末が必要な場合に実装する。
+    fn close(&mut self) {}
+}
+
+impl<S: FrameSink + ?Sized> FrameSink for Box<S> {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        (**self).on_frame(event);
+    }
+
+    fn close(&mut self) {
+        (**self).close();
+    }
+}
+
+impl<S: FrameSink + ?Sized> FrameSink for &mut S {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        (**self).on_frame(event);
+    }
+
+    fn close(&mut self) {
+        (**self).close();
+    }
+}
+
+// 複数のFrameSinkに順番に渡す。
+impl FrameSink for Vec<Box<dyn FrameSink + '_>> {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        for sink in self.iter_mut() {
+            sink.on_frame(event);
+        }
+    }
+
+    fn close(&mut self) {
+        for sink in self.iter_mut() {
+            sink.close();
+        }
+    }
+}
@@ -0,0 +1,722 @@
+// pose_world_landmarksから人型の骨格を作り、関節ごとの回転を求める。
+// BVHやglTFへの書き出しで共通に使う。
+//
+// 座標系はY軸が上、人物の左が+X、正面が+Zの右手系とし、単位はメートルとする。
+// mediapipeのworld座標(Y軸が下、Z軸がカメラの奥向き)はY, Zを反転して使う。
+// 基準姿勢は両腕を横に伸ばしたTポーズで、各関節のローカル軸はこの姿勢でワールド軸と一致する。
+// 関節の名前はUnityのHumanBodyBonesに合わせている。
+
+use crate::holistic::HolisticFrame;
+use crate::math::{Quat, Vec3};
+use crate::position::root_translation;
+
+// pose_landmarksの番号
+mod pose {
+    pub const NOSE: usize = 0;
+    pub const LEFT_EAR: usize = 7;
+    pub const RIGHT_EAR: usize = 8;
+    pub const LEFT_SHOULDER: usize = 11;
+    pub const RIGHT_SHOULDER: usize = 12;
+    pub const LEFT_ELBOW: usize = 13;
+    pub const RIGHT_ELBOW: usize = 14;
+    pub const LEFT_WRIST: usize = 15;
+    pub const RIGHT_WRIST: usize = 16;
+    pub const LEFT_PINKY: usize = 17;
+    pub const RIGHT_PINKY: usize = 18;
+    pub const LEFT_INDEX: usize = 19;
+    pub const RIGHT_INDEX: usize = 20;
+    pub const LEFT_HIP: usize = 23;
+    pub const RIGHT_HIP: usize = 24;
+    pub const LEFT_KNEE: usize = 25;
+    pub const RIGHT_KNEE: usize = 26;
+    pub const LEFT_ANKLE: usize = 27;
+    pub const RIGHT_ANKLE: usize = 28;
+    pub const LEFT_FOOT_INDEX: usize = 31;
+    pub const RIGHT_FOOT_INDEX: usize = 32;
+    pub const COUNT: usize = 33;
+}
+
+// hand_landmarksの番号
+mod hand {
+    pub const WRIST: usize = 0;
+    pub const INDEX_MCP: usize = 5;
+    pub const PINKY_MCP: usize = 17;
+    pub const COUNT: usize = 21;
+    // 各指の付け根から指先までの番号(親指, 人差し指, 中指, 薬指, 小指)
+    pub const FINGERS: [[usize; 4]; 5] = [
+        [1, 2, 3, 4],
+        [5, 6, 7, 8],
+        [9, 10, 11, 12],
+        [13, 14, 15, 16],
+        [17, 18, 19, 20],
+    ];
+}
+
+// 関節の番号
+pub const HIPS: usize = 0;
+pub const SPINE: usize = 1;
+pub const NECK: usize = 2;
+pub const HEAD: usize = 3;
+pub const LEFT_UPPER_ARM: usize = 4;
+pub const LEFT_LOWER_ARM: usize = 5;
+pub const LEFT_HAND: usize = 6;
+pub const RIGHT_UPPER_ARM: usize = 7;
+pub const RIGHT_LOWER_ARM: usize = 8;
+pub const RIGHT_HAND: usize = 9;
+pub const LEFT_UPPER_LEG: usize = 10;
+pub const LEFT_LOWER_LEG: usize = 11;
+pub const LEFT_FOOT: usize = 12;
+pub const RIGHT_UPPER_LEG: usize = 13;
+pub const RIGHT_LOWER_LEG: usize = 14;
+pub const RIGHT_FOOT: usize = 15;
+// 指の関節はこの後ろに左手、右手の順で並ぶ。
+const BODY_JOINTS: usize = 16;
+
+const BODY_JOINT_NAMES: [&str; BODY_JOINTS] = [
+    "Hips",
+    "Spine",
+    "Neck",
+    "Head",
+    "LeftUpperArm",
+    "LeftLowerArm",
+    "LeftHand",
+    "RightUpperArm",
+    "RightLowerArm",
+    "RightHand",
+    "LeftUpperLeg",
+    "LeftLowerLeg",
+    "LeftFoot",
+    "RightUpperLeg",
+    "RightLowerLeg",
+    "RightFoot",
+];
+
+const FINGER_NAMES: [&str; 5] = ["Thumb", "Index", "Middle", "Ring", "Little"];
+const FINGER_SEGMENTS: [&str; 3] = ["Proximal", "Intermediate", "Distal"];
+
+// 肘や膝の曲がりから腕や脚のねじれを求めるのに必要な曲がり具合(sin)
+const MIN_BEND: f64 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    // 基準姿勢で腕が伸びる方向(X軸)の符号
+    fn sign(self) -> f64 {
+        match self {
+            Side::Left => 1.0,
+            Side::Right => -1.0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Side::Left => "Left",
+            Side::Right => "Right",
+        }
+    }
+}
+
+// 骨格の計算に使う1フレーム分の点
+#[derive(Clone, Debug)]
+pub struct BodySample {
+    // TrackingFrame::timestamp(通常はpose_landmarks_stamp)[microsec]
+    pub timestamp: u64,
+    // pose_world_landmarks[m]
+    pub pose: Vec<Vec3>,
+    // hand_landmarks[pixel]。画像の縦横比を戻すためにカメラの解像度を掛けている。
+    pub left_hand: Option<Vec<Vec3>>,
+    pub right_hand: Option<Vec<Vec3>>,
+    // camera_paramsから求めた腰の中心の位置(position.rs)。カメラを原点とし、座標系はposeと同じ[m]
+    pub root: Option<Vec3>,
+}
+
+impl BodySample {
+    // pose_world_landmarksがないフレームはNoneを返す。
+    pub fn from_frame(frame: &HolisticFrame, timestamp: u64) -> Option<Self> {
+        let world = frame.pose_world_landmarks.as_ref()?;
+        if world.len() < pose::COUNT {
+            return None;
+        }
+        let (w, h) = match &frame.camera_params {
+            Some(camera) => (camera.frame_width as f64, camera.frame_height as f64),
+            None => (1.0, 1.0),
+        };
+        let hand = |landmarks: &Option<Vec<crate::holistic::Landmark>>| {
+            landmarks
+                .as_ref()
+                .filter(|landmarks| landmarks.len() >= hand::COUNT)
+                .map(|landmarks| {
+                    landmarks
+                        .iter()
+                        .map(|l| Vec3::new(l.x * w, -l.y * h, -l.z * w))
+                        .collect()
+                })
+        };
+        Some(Self {
+            timestamp,
+            pose: world.iter().map(|l| Vec3::new(l.x, -l.y, -l.z)).collect(),
+            left_hand: hand(&frame.left_hand_landmarks),
+            right_hand: hand(&frame.right_hand_landmarks),
+            root: root_translation(frame).map(|t| Vec3::new(t[0], -t[1], -t[2])),
+        })
+    }
+
+    pub fn hand(&self, side: Side) -> Option<&Vec<Vec3>> {
+        match side {
+            Side::Left => self.left_hand.as_ref(),
+            Side::Right => self.right_hand.as_ref(),
+        }
+    }
+
+    // selfとotherの間をtで補間する。
+    // 手はどちらかにしかない場合、近い方のフレームのものを使う。
+    pub fn lerp(&self, other: &BodySample, t: f64) -> BodySample {
+        let points = |a: &Vec<Vec3>, b: &Vec<Vec3>| -> Vec<Vec3> {
+            a.iter().zip(b.iter()).map(|(a, b)| a.lerp(*b, t)).collect()
+        };
+        let hand = |a: &Option<Vec<Vec3>>, b: &Option<Vec<Vec3>>| match (a, b) {
+            (Some(a), Some(b)) => Some(points(a, b)),
+            _ if t < 0.5 => a.clone(),
+            _ => b.clone(),
+        };
+        BodySample {
+            timestamp: self.timestamp
+                + ((other.timestamp.saturating_sub(self.timestamp)) as f64 * t) as u64,
+            pose: points(&self.pose, &other.pose),
+            left_hand: hand(&self.left_hand, &other.left_hand),
+            right_hand: hand(&self.right_hand, &other.right_hand),
+            root: match (self.root, other.root) {
+                (Some(a), Some(b)) => Some(a.lerp(b, t)),
+                _ if t < 0.5 => self.root,
+                _ => other.root,
+            },
+        }
+    }
+
+    fn mid_hip(&self) -> Vec3 {
+        self.pose[pose::LEFT_HIP].midpoint(self.pose[pose::RIGHT_HIP])
+    }
+
+    fn mid_shoulder(&self) -> Vec3 {
+        self.pose[pose::LEFT_SHOULDER].midpoint(self.pose[pose::RIGHT_SHOULDER])
+    }
+
+    fn head_center(&self) -> Vec3 {
+        self.pose[pose::LEFT_EAR].midpoint(self.pose[pose::RIGHT_EAR])
+    }
+
+    fn limb(&self, side: Side) -> Limb {
+        let p = &self.pose;
+        match side {
+            Side::Left => Limb {
+                shoulder: p[pose::LEFT_SHOULDER],
+                elbow: p[pose::LEFT_ELBOW],
+                wrist: p[pose::LEFT_WRIST],
+                pinky: p[pose::LEFT_PINKY],
+                index: p[pose::LEFT_INDEX],
+                hip: p[pose::LEFT_HIP],
+                knee: p[pose::LEFT_KNEE],
+                ankle: p[pose::LEFT_ANKLE],
+                foot_index: p[pose::LEFT_FOOT_INDEX],
+            },
+            Side::Right => Limb {
+                shoulder: p[pose::RIGHT_SHOULDER],
+                elbow: p[pose::RIGHT_ELBOW],
+                wrist: p[pose::RIGHT_WRIST],
+                pinky: p[pose::RIGHT_PINKY],
+                index: p[pose::RIGHT_INDEX],
+                hip: p[pose::RIGHT_HIP],
+                knee: p[pose::RIGHT_KNEE],
+                ankle: p[pose::RIGHT_ANKLE],
+                foot_index: p[pose::RIGHT_FOOT_INDEX],
+            },
+        }
+    }
+}
+
+// 片側の手足の点
+struct Limb {
+    shoulder: Vec3,
+    elbow: Vec3,
+    wrist: Vec3,
+    pinky: Vec3,
+    index: Vec3,
+    hip: Vec3,
+    knee: Vec3,
+    ankle: Vec3,
+    foot_index: Vec3,
+}
+
+impl Limb {
+    fn palm(&self) -> Vec3 {
+        self.pinky.midpoint(self.index)
+    }
+}
+
+// 手のひらの向き(手首から中指の付け根、小指から人差し指)を基準姿勢と比べた回転
+// handはhand_landmarks、戻り値はhand_landmarksの座標系での回転
+fn hand_frame(side: Side, hand: &[Vec3]) -> Option<Quat> {
+    let wrist = hand[hand::WRIST];
+    let palm = hand[hand::INDEX_MCP].midpoint(hand[hand::PINKY_MCP]);
+    Quat::from_basis(
+        Vec3::X * side.sign(),
+        Vec3::Z,
+        palm - wrist,
+        hand[hand::INDEX_MCP] - hand[hand::PINKY_MCP],
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    // 基準姿勢での親の関節からの位置[m]
+    pub offset: Vec3,
+    // 子の関節がない場合の先端の位置
+    pub end_site: Option<Vec3>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    // 基準姿勢での腰の高さ[m]
+    pub hip_height: f64,
+    pub fingers: bool,
+}
+
+// 平均を求めるための入れ物
+#[derive(Default)]
+struct Average {
+    sum: f64,
+    count: usize,
+}
+
+impl Average {
+    fn add(&mut self, v: f64) {
+        if v.is_finite() {
+            self.sum += v;
+            self.count += 1;
+        }
+    }
+
+    fn get(&self, default: f64) -> f64 {
+        if self.count == 0 {
+            default
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+}
+
+#[derive(Default)]
+struct AverageVec {
+    sum: Vec3,
+    count: usize,
+}
+
+impl AverageVec {
+    fn add(&mut self, v: Vec3) {
+        self.sum = self.sum + v;
+        self.count += 1;
+    }
+
+    fn get(&self) -> Option<Vec3> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum * (1.0 / self.count as f64))
+        }
+    }
+}
+
+// 手のデータがない場合の指の基準姿勢(左手、手のひらの長さを1とする)
+// 付け根の位置と、各節の長さ
+const DEFAULT_FINGERS: [(Vec3, [f64; 3]); 5] = [
+    (Vec3::new(0.25, 0.0, 0.25), [0.35, 0.3, 0.25]),
+    (Vec3::new(0.95, 0.0, 0.25), [0.45, 0.28, 0.22]),
+    (Vec3::new(1.0, 0.0, 0.05), [0.48, 0.3, 0.22]),
+    (Vec3::new(0.95, 0.0, -0.12), [0.45, 0.28, 0.22]),
+    (Vec3::new(0.85, 0.0, -0.28), [0.35, 0.22, 0.2]),
+];
+
+impl Skeleton {
+    // samplesの骨の長さの平均から基準姿勢を作る。左右は同じ長さとする。
+    // fingersがtrueの場合は手の下に指の関節を追加する。
+    pub fn new(samples: &[BodySample], fingers: bool) -> Self {
+        let mut hip_width = Average::default();
+        let mut shoulder_width = Average::default();
+        let mut torso = Average::default();
+        let mut neck = Average::default();
+        let mut upper_arm = Average::default();
+        let mut lower_arm = Average::default();
+        let mut palm = Average::default();
+        let mut upper_leg = Average::default();
+        let mut lower_leg = Average::default();
+        let mut foot = Average::default();
+        let mut nose = AverageVec::default();
+
+        for sample in samples.iter() {
+            let p = &sample.pose;
+            hip_width.add((p[pose::LEFT_HIP] - p[pose::RIGHT_HIP]).length());
+            shoulder_width.add((p[pose::LEFT_SHOULDER] - p[pose::RIGHT_SHOULDER]).length());
+            torso.add((sample.mid_shoulder() - sample.mid_hip()).length());
+            neck.add((sample.head_center() - sample.mid_shoulder()).length());
+            for side in [Side::Left, Side::Right] {
+                let limb = sample.limb(side);
+                upper_arm.add((limb.elbow - limb.shoulder).length());
+                lower_arm.add((limb.wrist - limb.elbow).length());
+                palm.add((limb.palm() - limb.wrist).length());
+                upper_leg.add((limb.knee - limb.hip).length());
+                lower_leg.add((limb.ankle - limb.knee).length());
+                foot.add((limb.foot_index - limb.ankle).length());
+            }
+            // 鼻の位置は頭の向きを決めるのに使うので、頭のローカル座標で平均をとる。
+            let head = sample.head_center();
+            if let Some(q) = Quat::from_basis(
+                Vec3::X,
+                Vec3::Z,
+                p[pose::LEFT_EAR] - p[pose::RIGHT_EAR],
+                p[pose::NOSE] - head,
+            ) {
+                nose.add(q.conjugate().rotate(p[pose::NOSE] - head));
+            }
+        }
+
+        // データがない場合は平均的な体格とする。
+        let hip_half = hip_width.get(0.2) * 0.5;
+        let shoulder_half = shoulder_width.get(0.35) * 0.5;
+        let spine = torso.get(0.5) * 0.5;
+        let palm = palm.get(0.08);
+        let upper_leg = upper_leg.get(0.42);
+        let lower_leg = lower_leg.get(0.4);
+        let nose = nose.get().unwrap_or(Vec3::new(0.0, 0.0, 0.1));
+
+        let mut joints = Vec::new();
+        let mut push = |name: &str, parent: Option<usize>, offset: Vec3, end_site: Option<Vec3>| {
+            joints.push(Joint {
+                name: name.to_string(),
+                parent,
+                offset,
+                end_site,
+            });
+        };
+        let names = BODY_JOINT_NAMES;
+        push(names[HIPS], None, Vec3::ZERO, None);
+        push(names[SPINE], Some(HIPS), Vec3::new(0.0, spine, 0.0), None);
+        push(names[NECK], Some(SPINE), Vec3::new(0.0, spine, 0.0), None);
+        push(
+            names[HEAD],
+            Some(NECK),
+            Vec3::new(0.0, neck.get(0.15), 0.0),
+            Some(nose),
+        );
+        for side in [Side::Left, Side::Right] {
+            let s = side.sign();
+            let upper = match side {
+                Side::Left => LEFT_UPPER_ARM,
+                Side::Right => RIGHT_UPPER_ARM,
+            };
+            push(
+                names[upper],
+                Some(SPINE),
+                Vec3::new(s * shoulder_half, spine, 0.0),
+                None,
+            );
+            push(
+                names[upper + 1],
+                Some(upper),
+                Vec3::X * (s * upper_arm.get(0.28)),
+                None,
+            );
+            let end_site = if fingers {
+                None
+            } else {
+                Some(Vec3::X * (s * palm))
+            };
+            push(
+                names[upper + 2],
+                Some(upper + 1),
+                Vec3::X * (s * lower_arm.get(0.25)),
+                end_site,
+            );
+        }
+        for side in [Side::Left, Side::Right] {
+            let s = side.sign();
+            let upper = match side {
+                Side::Left => LEFT_UPPER_LEG,
+                Side::Right => RIGHT_UPPER_LEG,
+            };
+            push(
+                names[upper],
+                Some(HIPS),
+                Vec3::new(s * hip_half, 0.0, 0.0),
+                None,
+            );
+            push(names[upper + 1], Some(upper), Vec3::Y * -upper_leg, None);
+            push(
+                names[upper + 2],
+                Some(upper + 1),
+                Vec3::Y * -lower_leg,
+                Some(Vec3::Z * foot.get(0.2)),
+            );
+        }
+        if fingers {
+            let rest = finger_rest(samples, palm);
+            for side in [Side::Left, Side::Right] {
+                let hand_joint = match side {
+                    Side::Left => LEFT_HAND,
+                    Side::Right => RIGHT_HAND,
+                };
+                for (f, (base, segments)) in rest.iter().enumerate() {
+                    // 右手はX軸を反転する。
+                    let mirror = |v: Vec3| Vec3::new(v.x * side.sign(), v.y, v.z);
+                    let first = joints.len();
+                    let mut push_finger = |segment: usize, offset: Vec3, end_site: Option<Vec3>| {
+                        joints.push(Joint {
+                            name: format!(
+                                "{}{}{}",
+                                side.name(),
+                                FINGER_NAMES[f],
+                                FINGER_SEGMENTS[segment]
+                            ),
+                            parent: Some(if segment == 0 {
+                                hand_joint
+                            } else {
+                                first + segment - 1
+                            }),
+                            offset: mirror(offset),
+                            end_site: end_site.map(mirror),
+                        });
+                    };
+                    push_finger(0, *base, None);
+                    push_finger(1, segments[0], None);
+                    push_finger(2, segments[1], Some(segments[2]));
+                }
+            }
+        }
+
+        Self {
+            joints,
+            hip_height: upper_leg + lower_leg,
+            fingers,
+        }
+    }
+
+    // 基準姿勢で関節から子の関節(なければ先端)に向かう方向
+    fn rest_direction(&self, joint: usize) -> Vec3 {
+        match self.joints.iter().position(|j| j.parent == Some(joint)) {
+            Some(child) => self.joints[child].offset,
+            None => self.joints[joint].end_site.unwrap_or(Vec3::Y),
+        }
+    }
+
+    // 関節ごとのローカルな回転を求める。
+    // 決められなかった関節(手のデータがないフレームの指など)はNoneとする。
+    pub fn solve(&self, sample: &BodySample) -> Vec<Option<Quat>> {
+        let mut world: Vec<Option<Quat>> = vec![None; self.joints.len()];
+        let p = &sample.pose;
+
+        let up = sample.mid_shoulder() - sample.mid_hip();
+        let hips = Quat::from_basis(Vec3::X, Vec3::Y, p[pose::LEFT_HIP] - p[pose::RIGHT_HIP], up)
+            .unwrap_or_default();
+        world[HIPS] = Some(hips);
+        let spine = Quat::from_basis(
+            Vec3::X,
+            Vec3::Y,
+            p[pose::LEFT_SHOULDER] - p[pose::RIGHT_SHOULDER],
+            up,
+        )
+        .unwrap_or(hips);
+        world[SPINE] = Some(spine);
+        let neck = swing(spine, Vec3::Y, sample.head_center() - sample.mid_shoulder());
+        world[NECK] = Some(neck);
+        let nose_rest = self.joints[HEAD].end_site.unwrap_or(Vec3::Z);
+        world[HEAD] = Some(
+            Quat::from_basis(
+                Vec3::X,
+                nose_rest,
+                p[pose::LEFT_EAR] - p[pose::RIGHT_EAR],
+                p[pose::NOSE] - sample.head_center(),
+            )
+            .unwrap_or(neck),
+        );
+
+        for side in [Side::Left, Side::Right] {
+            let s = side.sign();
+            let limb = sample.limb(side);
+            let (upper_arm, upper_leg) = match side {
+                Side::Left => (LEFT_UPPER_ARM, LEFT_UPPER_LEG),
+                Side::Right => (RIGHT_UPPER_ARM, RIGHT_UPPER_LEG),
+            };
+
+            // 肘が曲がっている場合は、前腕の向きから上腕のねじれを決める。
+            let upper = bent_limb(
+                spine,
+                Vec3::X * s,
+                Vec3::Z,
+                limb.elbow - limb.shoulder,
+                limb.wrist - limb.elbow,
+            );
+            let lower = swing(upper, Vec3::X * s, limb.wrist - limb.elbow);
+            let hand = Quat::from_basis(
+                Vec3::X * s,
+                Vec3::Z,
+                limb.palm() - limb.wrist,
+                limb.index - limb.pinky,
+            )
+            .unwrap_or_else(|| swing(lower, Vec3::X * s, limb.palm() - limb.wrist));
+            world[upper_arm] = Some(upper);
+            world[upper_arm + 1] = Some(lower);
+            world[upper_arm + 2] = Some(hand);
+
+            // 膝は後ろに曲がる。
+            let thigh = bent_limb(
+                hips,
+                -Vec3::Y,
+                -Vec3::Z,
+                limb.knee - limb.hip,
+                limb.ankle - limb.knee,
+            );
+            let shin = swing(thigh, -Vec3::Y, limb.ankle - limb.knee);
+            let foot = swing(shin, Vec3::Z, limb.foot_index - limb.ankle);
+            world[upper_leg] = Some(thigh);
+            world[upper_leg + 1] = Some(shin);
+            world[upper_leg + 2] = Some(foot);
+        }
+
+        let mut local: Vec<Option<Quat>> = self
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| match (joint.parent, world[i]) {
+                (None, w) => w,
+                (Some(parent), Some(w)) => world[parent].map(|parent| parent.conjugate() * w),
+                _ => None,
+            })
+            .collect();
+
+        if self.fingers {
+            for side in [Side::Left, Side::Right] {
+                self.solve_fingers(sample, side, &mut local);
+            }
+        }
+        local
+    }
+
+    // 指の回転はhand_landmarksの中で手のひらに対する回転として求める。
+    fn solve_fingers(&self, sample: &BodySample, side: Side, local: &mut [Option<Quat>]) {
+        let hand = match sample.hand(side) {
+            Some(hand) => hand,
+            None => return,
+        };
+        let palm = match hand_frame(side, hand) {
+            Some(palm) => palm.conjugate(),
+            None => return,
+        };
+        let first = BODY_JOINTS
+            + match side {
+                Side::Left => 0,
+                Side::Right => FINGER_NAMES.len() * FINGER_SEGMENTS.len(),
+            };
+        for (f, ids) in hand::FINGERS.iter().enumerate() {
+            let mut parent = Quat::IDENTITY;
+            for segment in 0..FINGER_SEGMENTS.len() {
+                let joint = first + f * FINGER_SEGMENTS.len() + segment;
+                let direction = palm.rotate(hand[ids[segment + 1]] - hand[ids[segment]]);
+                let rotation = swing(parent, self.rest_direction(joint), direction);
+                local[joint] = Some(parent.conjugate() * rotation);
+                parent = rotation;
+            }
+        }
+    }
+}
+
+// 親の回転parentのままrestの向きをdirectionに向ける回転(ねじれは親と同じ)
+fn swing(parent: Quat, rest: Vec3, direction: Vec3) -> Quat {
+    match Quat::from_two_vectors(parent.rotate(rest), direction) {
+        Some(q) => q * parent,
+        None => parent,
+    }
+}
+
+// 腕や脚の付け根の回転。
+// 関節が十分に曲がっている場合は、先の骨(child)がrest_bendの方向に曲がるようにねじれを決める。
+fn bent_limb(parent: Quat, rest: Vec3, rest_bend: Vec3, bone: Vec3, child: Vec3) -> Quat {
+    if let (Some(b), Some(c)) = (bone.normalize(), child.normalize()) {
+        if b.cross(c).length() > MIN_BEND {
+            if let Some(q) = Quat::from_basis(rest, rest_bend, bone, child) {
+                return q;
+            }
+        }
+    }
+    swing(parent, rest, bone)
+}
+
+// 指の基準姿勢を手のデータの平均から求める。
+// 戻り値は左手での付け根の位置と各節の位置(手のひらに対するローカル座標)
+fn finger_rest(samples: &[BodySample], palm_length: f64) -> Vec<(Vec3, [Vec3; 3])> {
+    let mut base = Vec::new();
+    let mut direction = Vec::new();
+    let mut lengths = Vec::new();
+    for _ in 0..hand::FINGERS.len() {
+        base.push(AverageVec::default());
+        direction.push(AverageVec::default());
+        lengths.push([Average::default(), Average::default(), Average::default()]);
+    }
+    let mut palm_pixels = Average::default();
+
+    for sample in samples.iter() {
+        for side in [Side::Left, Side::Right] {
+            let hand = match sample.hand(side) {
+                Some(hand) => hand,
+                None => continue,
+            };
+            let frame = match hand_frame(side, hand) {
+                Some(frame) => frame.conjugate(),
+                None => continue,
+            };
+            // 右手の値は左手に揃える。
+            let mirror = |v: Vec3| Vec3::new(v.x * side.sign(), v.y, v.z);
+            let wrist = hand[hand::WRIST];
+            let palm = hand[hand::INDEX_MCP].midpoint(hand[hand::PINKY_MCP]);
+            palm_pixels.add((palm - wrist).length());
+            for (f, ids) in hand::FINGERS.iter().enumerate() {
+                base[f].add(mirror(frame.rotate(hand[ids[0]] - wrist)));
+                direction[f].add(mirror(frame.rotate(hand[ids[3]] - hand[ids[0]])));
+                for segment in 0..3 {
+                    lengths[f][segment].add((hand[ids[segment + 1]] - hand[ids[segment]]).length());
+                }
+            }
+        }
+    }
+
+    // 画像上の長さを手のひらの長さで割って、pose_world_landmarksの長さに合わせる。
+    let scale = palm_length / palm_pixels.get(1.0);
+    (0..hand::FINGERS.len())
+        .map(|f| {
+            let (default_base, default_lengths) = DEFAULT_FINGERS[f];
+            let base = match base[f].get() {
+                Some(base) => base * scale,
+                None => default_base * palm_length,
+            };
+            let direction = direction[f]
+                .get()
+                .and_then(|d| d.normalize())
+                .or_else(|| default_base.normalize())
+                .unwrap_or(Vec3::X);
+            let segment = |i: usize| {
+                let length = if lengths[f][i].count == 0 {
+                    default_lengths[i] * palm_length
+                } else {
+                    lengths[f][i].get(0.0) * scale
+                };
+                direction * length
+            };
+            (base, [segment(0), segment(1), segment(2)])
+        })
+        .collect()
+}
@@ -0,0 +1,230 @@
+// 送信元ごとの受信の統計。
+// 端末がWi-Fiでフレームを落としていないかを確認するために使う。
+// pose_landmarks_stamp(なければ他の*_stamp)の間隔と受信時刻から、
+// ジッタ、欠落したフレーム数、順序の入れ替わり、他のストリームの遅れを推定する。
+// 受信fpsはDeviceSessionのpacket_rateを使う。
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::holistic::LandmarkStream;
+
+// フレーム間隔の推定に使う直近の間隔の数
+const INTERVAL_HISTORY: usize = 30;
+// 推定したフレーム間隔のこの倍以上空いた場合に欠落とみなす
+const DROP_THRESHOLD: f64 = 1.5;
+// pose_landmarks_stampからこのフレーム数以上遅れたストリームを古いとみなす
+const STALE_FRAMES: f64 = 3.0;
+
+// タイムスタンプだけを読むための構造体
+#[derive(Default, serde::Deserialize)]
+struct Stamps {
+    pose_landmarks_stamp: Option<u64>,
+    pose_world_landmarks_stamp: Option<u64>,
+    face_landmarks_stamp: Option<u64>,
+    left_hand_landmarks_stamp: Option<u64>,
+    right_hand_landmarks_stamp: Option<u64>,
+}
+
+impl Stamps {
+    fn stamp(&self, stream: LandmarkStream) -> Option<u64> {
+        match stream {
+            LandmarkStream::Pose => self.pose_landmarks_stamp,
+            LandmarkStream::PoseWorld => self.pose_world_landmarks_stamp,
+            LandmarkStream::Face => self.face_landmarks_stamp,
+            LandmarkStream::LeftHand => self.left_hand_landmarks_stamp,
+            LandmarkStream::RightHand => self.right_hand_landmarks_stamp,
+        }
+    }
+
+    // フレームのタイムスタンプ。gravity_stampは単位が違うので使わない。
+    fn frame_stamp(&self) -> Option<u64> {
+        self.pose_landmarks_stamp
+            .or(self.pose_world_landmarks_stamp)
+            .or(self.face_landmarks_stamp)
+            .or(self.right_hand_landmarks_stamp)
+            .or(self.left_hand_landmarks_stamp)
+    }
+}
+
+// pose_landmarks_stampに対するストリームの遅れ
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct StreamLag {
+    pub stream: LandmarkStream,
+    // 最後に受信したフレームでの遅れ[msec]
+    pub lag_ms: f64,
+    // フレーム間隔のSTALE_FRAMES倍以上遅れている
+    pub stale: bool,
+}
+
+// get_udp_statsやudp_statsでフロントエンドに返す情報
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct PacketStats {
+    pub device_id: String,
+    pub packets: u64,
+    // 直近1秒に受信したフレーム数
+    pub fps: f64,
+    // タイムスタンプの間隔から推定した送信側のフレーム間隔[msec]
+    pub frame_interval_ms: Option<f64>,
+    // 受信間隔とタイムスタンプの間隔の差のばらつき(RFC 3550と同じ計算)[msec]
+    pub jitter_ms: f64,
+    // タイムスタンプの飛びから推定した欠落フレーム数
+    pub dropped: u64,
+    // dropped / (受信したフレーム数 + dropped)
+    pub loss_rate: f64,
+    // 前のフレームより古いタイムスタンプで届いたフレーム数
+    pub out_of_order: u64,
+    // 前のフレームと同じタイムスタンプで届いたフレーム数
+    pub duplicates: u64,
+    // タイムスタンプが読めなかったパケット数
+    pub invalid: u64,
+    pub streams: Vec<StreamLag>,
+}
+
+// 送信元一つ分の統計
+#[derive(Default)]
+pub struct SourceStats {
+    // 最後に順番通りに届いたフレームのタイムスタンプと受信時刻
+    last: Option<(u64, Instant)>,
+    // 直近のタイムスタンプの間隔[usec]
+    intervals: VecDeque<u64>,
+    jitter_us: f64,
+    frames: u64,
+    dropped: u64,
+    out_of_order: u64,
+    duplicates: u64,
+    invalid: u64,
+    // 最後に受信したフレームでの各ストリームの遅れ[usec]
+    lags: Vec<(LandmarkStream, u64)>,
+}
+
+impl SourceStats {
+    pub fn on_packet(&mut self, json_str: &str, now: Instant) {
+        let stamps: Stamps = serde_json::from_str(json_str).unwrap_or_default();
+        let stamp = match stamps.frame_stamp() {
+            Some(stamp) => stamp,
+            None => {
+                self.invalid += 1;
+                return;
+            }
+        };
+        if let Some((last_stamp, last_arrival)) = self.last {
+            if stamp < last_stamp {
+                self.out_of_order += 1;
+                return;
+            }
+            if stamp == last_stamp {
+                self.duplicates += 1;
+                return;
+            }
+            let delta = stamp - last_stamp;
+            if let Some(interval) = self.frame_interval() {
+                if delta as f64 >= interval * DROP_THRESHOLD {
+                    self.dropped += (delta as f64 / interval).round() as u64 - 1;
+                }
+            }
+            let arrival = now.duration_since(last_arrival).as_micros() as f64;
+            let d = (arrival - delta as f64).abs();
+            self.jitter_us += (d - self.jitter_us) / 16.0;
+            self.intervals.push_back(delta);
+            if self.intervals.len() > INTERVAL_HISTORY {
+                self.intervals.pop_front();
+            }
+        }
+        self.last = Some((stamp, now));
+        self.frames += 1;
+
+        self.lags.clear();
+        if let Some(pose_stamp) = stamps.pose_landmarks_stamp {
+            for stream in LandmarkStream::ALL {
+                if let Some(stream_stamp) = stamps.stamp(stream) {
+                    if stream != LandmarkStream::Pose {
+                        self.lags
+                            .push((stream, pose_stamp.saturating_sub(stream_stamp)));
+                    }
+                }
+            }
+        }
+    }
+
+    // 直近の間隔の中央値[usec]。落ちたフレームによる大きな間隔の影響を受けにくい。
+    fn frame_interval(&self) -> Option<f64> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<u64> = self.intervals.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2] as f64)
+    }
+
+    pub fn report(&self, device_id: String, packets: u64, fps: f64) -> PacketStats {
+        let interval = self.frame_interval();
+        let total = self.frames + self.dropped;
+        PacketStats {
+            device_id,
+            packets,
+            fps,
+            frame_interval_ms: interval.map(|interval| interval / 1000.0),
+            jitter_ms: self.jitter_us / 1000.0,
+            dropped: self.dropped,
+            loss_rate: if total > 0 {
+                self.dropped as f64 / total as f64
+            } else {
+                0.0
+            },
+            out_of_order: self.out_of_order,
+            duplicates: self.duplicates,
+            invalid: self.invalid,
+            streams: self
+                .lags
+                .iter()
+                .map(|(stream, lag)| StreamLag {
+                    stream: *stream,
+                    lag_ms: *lag as f64 / 1000.0,
+                    stale: interval.is_some_and(|interval| *lag as f64 >= interval * STALE_FRAMES),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn count_drops_duplicates_and_reordering() {
+        let start = Instant::now();
+        let mut stats = SourceStats::default();
+        let packets = [
+            (0, "{\"pose_landmarks_stamp\":0}"),
+            (33, "{\"pose_landmarks_stamp\":33333}"),
+            (66, "{\"pose_landmarks_stamp\":66666}"),
+            // 2フレーム落ちた。
+            (
+                166,
+                "{\"pose_landmarks_stamp\":166665,\"face_landmarks_stamp\":66666}",
+            ),
+            (170, "{\"pose_landmarks_stamp\":166665}"),
+            (175, "{\"pose_landmarks_stamp\":100000}"),
+            (180, "{broken"),
+        ];
+        for (ms, json_str) in packets {
+            stats.on_packet(json_str, start + Duration::from_millis(ms));
+        }
+        let report = stats.report("a".to_string(), packets.len() as u64, 30.0);
+        assert_eq!(report.dropped, 2);
+        assert_eq!(report.duplicates, 1);
+        assert_eq!(report.out_of_order, 1);
+        assert_eq!(report.invalid, 1);
+        assert!((report.loss_rate - 2.0 / 6.0).abs() < 1e-9);
+        assert!((report.frame_interval_ms.unwrap() - 33.333).abs() < 1e-3);
+        assert!(report.jitter_ms < 1.0);
+        // 最後に順番通りに届いたフレームでの顔の遅れ
+        assert_eq!(report.streams.len(), 1);
+        assert_eq!(report.streams[0].stream, LandmarkStream::Face);
+        assert!((report.streams[0].lag_ms - 99.999).abs() < 1e-9);
+        assert!(report.streams[0].stale);
+    }
+}
@@ -0,0 +1,436 @@
+// 読み込んだフレームの入れ物。
+// 小さいファイルは全体をメモリに読み込む。
+// 大きいファイルや圧縮形式のファイルは開いたままにして、
+// 各フレームの位置とタイムスタンプだけを保持し、必要になったときにファイルから読む。
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::compressed::{self, BlockEntry};
+use crate::frames::{load_file, LineError, LoadError, LoadPolicy, LoadSummary, TrackingFrame};
+use crate::metadata::SessionMetadata;
+use crate::session::{is_session_index, SessionFrames};
+
+// これ以上の大きさのファイルはメモリに読み込まずに開いたままにする。
+pub const STREAMING_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+// 再生やシークで使うフレームの読み出し方法
+// timestampはすぐに返せるようにしておき、フレームの中身はframeで読む。
+pub trait FrameSource {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn timestamp(&self, idx: usize) -> u64;
+
+    // 読めなかった場合はNoneを返す。
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>>;
+}
+
+impl FrameSource for [TrackingFrame] {
+    fn len(&self) -> usize {
+        <[TrackingFrame]>::len(self)
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        self[idx].timestamp
+    }
+
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>> {
+        self.get(idx).map(Cow::Borrowed)
+    }
+}
+
+impl FrameSource for Vec<TrackingFrame> {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        self[idx].timestamp
+    }
+
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>> {
+        self.as_slice().frame(idx)
+    }
+}
+
+// ファイル内のフレームの位置
+#[derive(Clone, Copy)]
+enum Location {
+    // 改行区切りのjsonの場合はその行の位置と長さ(改行を含まない)
+    Line { offset: u64, len: u32 },
+    // 圧縮形式の場合はブロック番号とブロック内の番号
+    Block { block: u32, line: u32 },
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    timestamp: u64,
+    location: Location,
+}
+
+struct FileReader {
+    file: File,
+    // 最後に展開したブロックと、その中の各行の範囲
+    block: Option<(u32, Vec<u8>, Vec<Range<usize>>)>,
+}
+
+impl FileReader {
+    fn read_line(&mut self, location: Location, blocks: &[BlockEntry]) -> io::Result<String> {
+        match location {
+            Location::Line { offset, len } => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                let mut buf = vec![0u8; len as usize];
+                self.file.read_exact(&mut buf)?;
+                String::from_utf8(buf)
+                    .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+            }
+            Location::Block { block, line } => {
+                let cached = matches!(&self.block, Some((b, _, _)) if *b == block);
+                if !cached {
+                    let entry = blocks.get(block as usize).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "block not found")
+                    })?;
+                    let data = compressed::read_block(&mut self.file, entry)?;
+                    let mut lines = Vec::new();
+                    let mut begin = 0;
+                    for (i, c) in data.iter().enumerate() {
+                        if *c == b'\n' {
+                            lines.push(begin..i);
+                            begin = i + 1;
+                        }
+                    }
+                    self.block = Some((block, data, lines));
+                }
+                let (_, data, lines) = self.block.as_ref().unwrap();
+                let range = lines
+                    .get(line as usize)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "line not found"))?;
+                String::from_utf8(data[range.clone()].to_vec())
+                    .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+            }
+        }
+    }
+}
+
+// ファイルを開いたままにして、必要なフレームだけを読むFrameSource
+pub struct FileFrames {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    blocks: Vec<BlockEntry>,
+    reader: Mutex<FileReader>,
+}
+
+impl FileFrames {
+    // ファイル全体を一度だけ走査して各行の位置とタイムスタンプを記録する。
+    // 圧縮形式の場合はインデックスからブロックの位置を読み、ブロックを順に展開して走査する。
+    // メモリに読み込む場合(load_file)と同じ結果になるように、各行をTrackingFrameとして読めるか確かめる。
+    pub fn open(path: &Path, policy: LoadPolicy) -> Result<(Self, LoadSummary), LoadError> {
+        let mut file = File::open(path)?;
+        let mut summary = LoadSummary::default();
+        let mut entries = Vec::new();
+        let mut blocks = Vec::new();
+
+        if compressed::is_compressed(path)? {
+            let index = compressed::read_index(&mut file)?;
+            let mut line_no = 0;
+            for (block_no, block) in index.blocks.iter().enumerate() {
+                let data = compressed::read_block(&mut file, block)?;
+                let lines = data.strip_suffix(b"\n").unwrap_or(&data);
+                for (line, text) in lines.split(|c| *c == b'\n').enumerate() {
+                    line_no += 1;
+                    let location = Location::Block {
+                        block: block_no as u32,
+                        line: line as u32,
+                    };
+                    scan_line(text, line_no, location, policy, &mut entries, &mut summary)?;
+                }
+            }
+            blocks = index.blocks;
+            summary.total_lines = line_no;
+        } else {
+            let mut reader = BufReader::with_capacity(1024 * 1024, File::open(path)?);
+            let mut buf = Vec::new();
+            let mut offset = 0u64;
+            let mut line_no = 0;
+            loop {
+                buf.clear();
+                let n = reader.read_until(b'\n', &mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                line_no += 1;
+                let line_offset = offset;
+                offset += n as u64;
+                while buf.last() == Some(&b'\n') || buf.last() == Some(&b'\r') {
+                    buf.pop();
+                }
+                let location = Location::Line {
+                    offset: line_offset,
+                    len: buf.len() as u32,
+                };
+                scan_line(&buf, line_no, location, policy, &mut entries, &mut summary)?;
+            }
+            summary.total_lines = line_no;
+        }
+
+        if entries.is_empty() {
+            return Err(LoadError::Empty {
+                errors: summary.errors,
+            });
+        }
+        summary.path = path.to_string_lossy().to_string();
+        summary.loaded_frames = entries.len();
+        summary.begin_timestamp = entries[0].timestamp;
+        summary.end_timestamp = entries[entries.len() - 1].timestamp;
+        summary.streaming = true;
+
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                entries,
+                blocks,
+                reader: Mutex::new(FileReader { file, block: None }),
+            },
+            summary,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// 一行分のタイムスタンプを読んでentriesに追加する。
+// 空行と読めない行はpolicyに従って読み飛ばすか、LoadError::Malformedを返す。
+fn scan_line(
+    line: &[u8],
+    line_no: usize,
+    location: Location,
+    policy: LoadPolicy,
+    entries: &mut Vec<Entry>,
+    summary: &mut LoadSummary,
+) -> Result<(), LoadError> {
+    if line.iter().all(|c| c.is_ascii_whitespace()) {
+        summary.skipped_lines += 1;
+        return Ok(());
+    }
+    match scan_timestamp(line, policy) {
+        Ok((timestamp, fell_back)) => {
+            if fell_back {
+                summary.fallback_frames += 1;
+            }
+            entries.push(Entry {
+                timestamp,
+                location,
+            });
+        }
+        Err(reason) => {
+            println!("file_frames: line {}: {}", line_no, reason);
+            if policy == LoadPolicy::Abort {
+                return Err(LoadError::Malformed {
+                    line: line_no,
+                    reason,
+                });
+            }
+            summary.errors.push(LineError {
+                line: line_no,
+                reason,
+            });
+            summary.skipped_lines += 1;
+        }
+    }
+    Ok(())
+}
+
+// 一行分のタイムスタンプを読む。戻り値の2番目はfallbackしたかどうか。
+// 読んだフレームは捨てて、必要になったときに読み直す。
+fn scan_timestamp(line: &[u8], policy: LoadPolicy) -> Result<(u64, bool), String> {
+    let s = std::str::from_utf8(line).map_err(|why| why.to_string())?;
+    let (tf, fell_back) = TrackingFrame::parse(s.to_string(), policy == LoadPolicy::Fallback)?;
+    Ok((tf.timestamp, fell_back))
+}
+
+impl FrameSource for FileFrames {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        self.entries[idx].timestamp
+    }
+
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>> {
+        let entry = self.entries.get(idx)?;
+        let line = match self
+            .reader
+            .lock()
+            .unwrap()
+            .read_line(entry.location, &self.blocks)
+        {
+            Ok(line) => line,
+            Err(why) => {
+                println!("file_frames: frame {}: {}", idx, why);
+                return None;
+            }
+        };
+        match TrackingFrame::parse(line, true) {
+            Ok((mut tf, _)) => {
+                // 開いたときに決めたタイムスタンプに揃える。
+                tf.timestamp = entry.timestamp;
+                Some(Cow::Owned(tf))
+            }
+            Err(why) => {
+                println!("file_frames: frame {}: {}", idx, why);
+                None
+            }
+        }
+    }
+}
+
+// open_fileで読み込んだフレーム
+pub enum FrameStore {
+    Memory(Vec<TrackingFrame>),
+    File(FileFrames),
+    // 分割して記録したファイルをインデックスから開いたもの
+    Session(SessionFrames),
+}
+
+impl Default for FrameStore {
+    fn default() -> Self {
+        FrameStore::Memory(Vec::new())
+    }
+}
+
+impl FrameStore {
+    // 圧縮形式かSTREAMING_THRESHOLD以上のファイルは開いたままにし、
+    // それ以外はメモリに読み込む。
+    // サイドカーがあればセッションの情報もLoadSummaryに入れる。
+    // 分割して記録したセッションのインデックスの場合は、すべてのファイルをまとめて開く。
+    pub fn open(path: &Path, policy: LoadPolicy) -> Result<(Self, LoadSummary), LoadError> {
+        if is_session_index(path) {
+            let (frames, summary) = SessionFrames::open(path, policy)?;
+            return Ok((FrameStore::Session(frames), summary));
+        }
+        let size = std::fs::metadata(path)?.len();
+        let (store, mut summary) =
+            if size >= STREAMING_THRESHOLD || compressed::is_compressed(path)? {
+                let (frames, summary) = FileFrames::open(path, policy)?;
+                (FrameStore::File(frames), summary)
+            } else {
+                let (frames, summary) = load_file(path, policy)?;
+                (FrameStore::Memory(frames), summary)
+            };
+        summary.metadata = match SessionMetadata::load_for(path) {
+            Ok(metadata) => metadata,
+            Err(why) => {
+                println!("metadata: {}", why);
+                None
+            }
+        };
+        Ok((store, summary))
+    }
+}
+
+impl FrameSource for FrameStore {
+    fn len(&self) -> usize {
+        match self {
+            FrameStore::Memory(frames) => frames.as_slice().len(),
+            FrameStore::File(frames) => frames.len(),
+            FrameStore::Session(frames) => frames.len(),
+        }
+    }
+
+    fn timestamp(&self, idx: usize) -> u64 {
+        match self {
+            FrameStore::Memory(frames) => frames.timestamp(idx),
+            FrameStore::File(frames) => frames.timestamp(idx),
+            FrameStore::Session(frames) => frames.timestamp(idx),
+        }
+    }
+
+    fn frame(&self, idx: usize) -> Option<Cow<'_, TrackingFrame>> {
+        match self {
+            FrameStore::Memory(frames) => frames.frame(idx),
+            FrameStore::File(frames) => frames.frame(idx),
+            FrameStore::Session(frames) => frames.frame(idx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::CompressedWriter;
+
+    const LINES: [&str; 5] = [
+        "{\"pose_landmarks_stamp\":1000}",
+        "{\"face_landmarks_stamp\":1500}",
+        "{broken",
+        // タイムスタンプは読めるが、HolisticFrameとしては読めない行
+        "{\"pose_landmarks_stamp\":1800,\"pose_landmarks\":[{\"x\":\"a\"}]}",
+        "{\"pose_landmarks_stamp\":2000}",
+    ];
+
+    // 同じ内容の改行区切りのjsonと圧縮形式のファイルを作る。
+    fn write_files(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("mru_store_{}_{}.jsonl", std::process::id(), name));
+        let packed = dir.join(format!("mru_store_{}_{}.dat", std::process::id(), name));
+        std::fs::write(&plain, LINES.join("\n") + "\n").unwrap();
+        let mut writer = CompressedWriter::create(&packed).unwrap();
+        for line in LINES {
+            writer.write_line(line).unwrap();
+        }
+        writer.finish().unwrap();
+        (plain, packed)
+    }
+
+    fn open(path: &Path, policy: LoadPolicy) -> Result<(Vec<u64>, LoadSummary), LoadError> {
+        let (frames, summary) = FileFrames::open(path, policy)?;
+        let stamps = (0..frames.len()).map(|idx| frames.timestamp(idx)).collect();
+        assert!((0..frames.len()).all(|idx| frames.frame(idx).is_some()));
+        Ok((stamps, summary))
+    }
+
+    #[test]
+    fn compressed_file_follows_policy() {
+        let (plain, packed) = write_files("policy");
+        for path in [&plain, &packed] {
+            let (stamps, summary) = open(path, LoadPolicy::Skip).unwrap();
+            assert_eq!(stamps, vec![1000, 2000]);
+            assert_eq!(summary.total_lines, 5);
+            assert_eq!(summary.skipped_lines, 3);
+            let lines: Vec<usize> = summary.errors.iter().map(|e| e.line).collect();
+            assert_eq!(lines, vec![2, 3, 4]);
+
+            let (stamps, summary) = open(path, LoadPolicy::Fallback).unwrap();
+            assert_eq!(stamps, vec![1000, 1500, 2000]);
+            assert_eq!(summary.fallback_frames, 1);
+            assert_eq!(summary.skipped_lines, 2);
+
+            // メモリに読み込んだ場合と同じ結果になる。
+            for policy in [LoadPolicy::Skip, LoadPolicy::Fallback] {
+                let (_, streamed) = FileFrames::open(path, policy).unwrap();
+                let (frames, loaded) = load_file(path, policy).unwrap();
+                assert_eq!(streamed.loaded_frames, frames.len());
+                assert_eq!(streamed.skipped_lines, loaded.skipped_lines);
+                assert_eq!(streamed.total_lines, loaded.total_lines);
+            }
+
+            match open(path, LoadPolicy::Abort) {
+                Err(LoadError::Malformed { line, .. }) => assert_eq!(line, 2),
+                _ => panic!("expected LoadError::Malformed"),
+            }
+        }
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&packed).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+// VMC(Virtual Motion Capture)プロトコルでの送信。
+// 受信・再生したフレームからskeletonで関節の回転を求め、
+// /VMC/Ext/Bone/Posと/VMC/Ext/Blend/ValをOSCでUDP送信する。
+// https://protocol.vmc.info/
+//
+// VMCの座標はUnityの左手系(Y軸が上、人物の右が+X、正面が+Z)なので、
+// skeletonの右手系(人物の左が+X)からX軸を反転して送る。
+// 関節の回転はTポーズを基準としたローカルな回転で、VRMの正規化されたボーンにそのまま適用できる。
+
+use std::io;
+use std::net::UdpSocket;
+use std::time::Instant;
+
+use crate::holistic::HolisticFrame;
+use crate::math::{Quat, Vec3};
+use crate::network::socket_addr_string;
+use crate::osc::{encode_bundle, OscArg, OscMessage, IMMEDIATELY};
+use crate::sink::{FrameEvent, FrameSink};
+use crate::skeleton::{BodySample, Skeleton};
+
+// VMCの受信側(Performer)のデフォルトのポート番号
+pub const DEFAULT_VMC_PORT: u16 = 39539;
+
+// face_landmarksの番号
+// 目と口の開き具合から瞬きと口の形を決める。
+mod face {
+    // 人物の右目(外側, 内側, 上, 下)
+    pub const RIGHT_EYE: [usize; 4] = [33, 133, 159, 145];
+    // 人物の左目(外側, 内側, 上, 下)
+    pub const LEFT_EYE: [usize; 4] = [263, 362, 386, 374];
+    // 口(右端, 左端, 上唇, 下唇)
+    pub const MOUTH: [usize; 4] = [61, 291, 13, 14];
+    pub const COUNT: usize = 468;
+}
+
+// 目と口の開き具合(縦/横)を0から1に変換する範囲
+const EYE_CLOSED: f64 = 0.04;
+const EYE_OPEN: f64 = 0.12;
+const MOUTH_CLOSED: f64 = 0.05;
+const MOUTH_OPEN: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct VmcOptions {
+    // 送信先
+    pub host: String,
+    pub port: u16,
+    // hand_landmarksから指の関節も送る。
+    pub fingers: bool,
+    // face_landmarksから瞬きと口の開きを送る。
+    pub blend_shapes: bool,
+    // 受信時に送る端末。Noneの場合はすべての端末のフレームを送る。
+    pub device_id: Option<String>,
+}
+
+impl Default for VmcOptions {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: DEFAULT_VMC_PORT,
+            fingers: false,
+            blend_shapes: true,
+            device_id: None,
+        }
+    }
+}
+
+// skeletonの座標をUnityの座標に変換する。
+fn unity_position(v: Vec3) -> [f32; 3] {
+    [-v.x as f32, v.y as f32, v.z as f32]
+}
+
+// X軸の反転で回転の向きも変わるので、Y, Z成分の符号を反転する。
+fn unity_rotation(q: Quat) -> [f32; 4] {
+    [q.x as f32, -q.y as f32, -q.z as f32, q.w as f32]
+}
+
+fn pos_message(address: &str, name: &str, position: Vec3, rotation: Quat) -> OscMessage {
+    let p = unity_position(position);
+    let q = unity_rotation(rotation);
+    OscMessage::new(
+        address,
+        vec![
+            OscArg::String(name.to_string()),
+            OscArg::Float(p[0]),
+            OscArg::Float(p[1]),
+            OscArg::Float(p[2]),
+            OscArg::Float(q[0]),
+            OscArg::Float(q[1]),
+            OscArg::Float(q[2]),
+            OscArg::Float(q[3]),
+        ],
+    )
+}
+
+// 縦/横の比をclosedからopenの範囲で0から1にする。
+fn openness(points: &[Vec3], ids: [usize; 4], closed: f64, open: f64) -> f64 {
+    let width = (points[ids[0]] - points[ids[1]]).length();
+    let height = (points[ids[2]] - points[ids[3]]).length();
+    if width < 1e-9 {
+        return 0.0;
+    }
+    ((height / width - closed) / (open - closed)).clamp(0.0, 1.0)
+}
+
+// VRMのプリセットの表情名と値
+fn blend_shapes(frame: &HolisticFrame) -> Option<Vec<(&'static str, f64)>> {
+    let landmarks = frame.face_landmarks.as_ref()?;
+    if landmarks.len() < face::COUNT {
+        return None;
+    }
+    let (w, h) = match &frame.camera_params {
+        Some(camera) => (camera.frame_width as f64, camera.frame_height as f64),
+        None => (1.0, 1.0),
+    };
+    let points: Vec<Vec3> = landmarks
+        .iter()
+        .map(|l| Vec3::new(l.x * w, l.y * h, 0.0))
+        .collect();
+    let blink_l = 1.0 - openness(&points, face::LEFT_EYE, EYE_CLOSED, EYE_OPEN);
+    let blink_r = 1.0 - openness(&points, face::RIGHT_EYE, EYE_CLOSED, EYE_OPEN);
+    let mouth = openness(&points, face::MOUTH, MOUTH_CLOSED, MOUTH_OPEN);
+    Some(vec![
+        ("Blink_L", blink_l),
+        ("Blink_R", blink_r),
+        ("A", mouth),
+    ])
+}
+
+// フレームをVMCプロトコルで送信するFrameSink
+pub struct VmcSink {
+    sock: UdpSocket,
+    options: VmcOptions,
+    // 最初にpose_world_landmarksを受け取ったときに作る。
+    skeleton: Option<Skeleton>,
+    // 決められなかった関節は前のフレームの回転のままにする。
+    rotations: Vec<Quat>,
+    started: Instant,
+}
+
+impl VmcSink {
+    pub fn connect(options: VmcOptions) -> io::Result<Self> {
+        let sock = UdpSocket::bind("0.0.0.0:0")?;
+        sock.connect(socket_addr_string(&options.host, options.port))?;
+        Ok(Self {
+            sock,
+            options,
+            skeleton: None,
+            rotations: Vec::new(),
+            started: Instant::now(),
+        })
+    }
+
+    // 1フレーム分のメッセージを作る。送るものがない場合は空になる。
+    fn messages(&mut self, frame: &HolisticFrame, timestamp: u64) -> Vec<OscMessage> {
+        let mut messages = Vec::new();
+
+        if let Some(sample) = BodySample::from_frame(frame, timestamp) {
+            let fingers = self.options.fingers;
+            let skeleton = self
+                .skeleton
+                .get_or_insert_with(|| Skeleton::new(std::slice::from_ref(&sample), fingers));
+            self.rotations.resize(skeleton.joints.len(), Quat::IDENTITY);
+            messages.push(pos_message(
+                "/VMC/Ext/Root/Pos",
+                "root",
+                Vec3::ZERO,
+                Quat::IDENTITY,
+            ));
+            let root_position = Vec3::new(0.0, skeleton.hip_height, 0.0);
+            for (j, solved) in skeleton.solve(&sample).into_iter().enumerate() {
+                if let Some(q) = solved {
+                    self.rotations[j] = q;
+                }
+                let joint = &skeleton.joints[j];
+                let position = match joint.parent {
+                    Some(_) => joint.offset,
+                    None => root_position,
+                };
+                messages.push(pos_message(
+                    "/VMC/Ext/Bone/Pos",
+                    &joint.name,
+                    position,
+                    self.rotations[j],
+                ));
+            }
+        }
+
+        if self.options.blend_shapes {
+            if let Some(values) = blend_shapes(frame) {
+                for (name, value) in values {
+                    messages.push(OscMessage::new(
+                        "/VMC/Ext/Blend/Val",
+                        vec![
+                            OscArg::String(name.to_string()),
+                            OscArg::Float(value as f32),
+                        ],
+                    ));
+                }
+                messages.push(OscMessage::new("/VMC/Ext/Blend/Apply", Vec::new()));
+            }
+        }
+
+        if !messages.is_empty() {
+            messages.insert(0, OscMessage::new("/VMC/Ext/OK", vec![OscArg::Int(1)]));
+            messages.insert(
+                1,
+                OscMessage::new(
+                    "/VMC/Ext/T",
+                    vec![OscArg::Float(self.started.elapsed().as_secs_f32())],
+                ),
+            );
+        }
+        messages
+    }
+}
+
+impl FrameSink for VmcSink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        let frame = match event.holistic() {
+            Some(frame) => frame,
+            None => return,
+        };
+        if let Some(device_id) = &self.options.device_id {
+            let source = event.device_id().or_else(|| frame.device_id.clone());
+            if source.as_ref() != Some(device_id) {
+                return;
+            }
+        }
+        let messages = self.messages(&frame, event.timestamp);
+        if messages.is_empty() {
+            return;
+        }
+        if let Err(why) = self.sock.send(&encode_bundle(IMMEDIATELY, &messages)) {
+            println!("vmc sink: {}", why);
+        }
+    }
+}
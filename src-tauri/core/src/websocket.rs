@@ -0,0 +1,305 @@
+// 受信・再生したフレームをWebSocketで配信するサーバ。
+// ブラウザのダッシュボードやLAN内の他のツールから、
+// 38013番ポートでUDPを待ち受けずに受信中・再生中のデータを見られるようにする。
+// デフォルトではローカルホストだけで待ち受ける。
+//
+// 各フレームは受信したjsonと同じ形式のテキストメッセージとして送り、
+// 受信時は送信元をdevice_idとして付加する。
+// クライアントは{"subscribe": ["pose", "left_hand"]}を送ると指定したストリームだけを受け取る。
+// 接続時にws://host:port/?streams=pose,left_handのように指定することもできる。
+// 指定しない場合({"subscribe": null})はすべてのストリームを受け取る。
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+use tokio_util::sync::CancellationToken;
+
+use crate::devices::tag_device;
+use crate::holistic::{is_stream_name, HolisticFrame};
+use crate::network::{bind_tcp, socket_addr_string, NetworkError};
+use crate::sink::{FrameEvent, FrameSink};
+
+// 0x947E
+pub const DEFAULT_WS_PORT: u16 = 38014;
+
+// クライアントが受け取りきれなかったフレームは古いものから捨てる。
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct WsRelayOptions {
+    // LANに公開する場合は0.0.0.0を指定する。
+    pub bind_addr: String,
+    pub port: u16,
+}
+
+impl Default for WsRelayOptions {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1".to_string(),
+            port: DEFAULT_WS_PORT,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct WsRelayStatus {
+    pub addr: String,
+    pub clients: usize,
+}
+
+// 配信する1フレーム
+struct RelayFrame {
+    text: Utf8Bytes,
+    // ストリームを選択しているクライアントがいる場合だけ読み込む。
+    frame: OnceLock<Option<HolisticFrame>>,
+}
+
+impl RelayFrame {
+    fn frame(&self) -> Option<&HolisticFrame> {
+        self.frame
+            .get_or_init(|| HolisticFrame::from_json_str(self.text.as_str()).ok())
+            .as_ref()
+    }
+}
+
+// クライアントが受け取るストリーム。Noneの場合はすべて受け取る。
+#[derive(Clone, Debug, Default)]
+struct Subscription(Option<Vec<String>>);
+
+#[derive(serde::Deserialize)]
+struct SubscribeRequest {
+    subscribe: Option<Vec<String>>,
+}
+
+impl Subscription {
+    fn new(names: Option<Vec<String>>) -> Result<Self, String> {
+        if let Some(names) = &names {
+            for name in names.iter() {
+                if !is_stream_name(name) {
+                    return Err(format!("unknown stream: {}", name));
+                }
+            }
+        }
+        Ok(Self(names))
+    }
+
+    // 接続時のクエリ文字列(streams=pose,left_hand)から作る。
+    fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let streams = query.and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("streams="))
+        });
+        Subscription::new(streams.map(|streams| {
+            streams
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        }))
+    }
+
+    // 送信するテキスト。選択したストリームが一つも含まれていない場合はNone
+    fn render(&self, relay_frame: &RelayFrame) -> Option<Utf8Bytes> {
+        let names = match &self.0 {
+            Some(names) => names,
+            None => return Some(relay_frame.text.clone()),
+        };
+        let selected = relay_frame.frame()?.select_streams(names)?;
+        selected.to_json_string().ok().map(Utf8Bytes::from)
+    }
+}
+
+// WebSocketのサーバ。dropするかstopを呼ぶと、待ち受けとすべての接続を終了する。
+pub struct WsRelay {
+    addr: SocketAddr,
+    sender: broadcast::Sender<Arc<RelayFrame>>,
+    clients: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+}
+
+impl WsRelay {
+    // 待ち受けを開始する。tokioのランタイムの中で呼び出す。
+    pub async fn start(options: &WsRelayOptions) -> Result<Self, NetworkError> {
+        let addr = socket_addr_string(&options.bind_addr, options.port);
+        let listener = bind_tcp(&addr).await?;
+        let local_addr = listener.local_addr().map_err(|why| NetworkError::Io {
+            addr: addr.clone(),
+            reason: why.to_string(),
+        })?;
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let clients = Arc::new(AtomicUsize::new(0));
+        let cancel = CancellationToken::new();
+        println!("ws relay: start on {}", local_addr);
+
+        let accept_sender = sender.clone();
+        let accept_clients = clients.clone();
+        let accept_cancel = cancel.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = accept_cancel.cancelled() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(why) => {
+                            println!("ws relay: {}", why);
+                            continue;
+                        }
+                    },
+                };
+                tokio::spawn(serve_client(
+                    stream,
+                    peer,
+                    accept_sender.subscribe(),
+                    accept_clients.clone(),
+                    accept_cancel.clone(),
+                ));
+            }
+            println!("ws relay: stop");
+        });
+
+        Ok(Self {
+            addr: local_addr,
+            sender,
+            clients,
+            cancel,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn status(&self) -> WsRelayStatus {
+        WsRelayStatus {
+            addr: format!("ws://{}", self.addr),
+            clients: self.clients.load(Ordering::Relaxed),
+        }
+    }
+
+    // 受信・再生のFrameSinkに加えるためのsink
+    pub fn sink(&self) -> WsRelaySink {
+        WsRelaySink {
+            sender: self.sender.clone(),
+        }
+    }
+
+    pub fn stop(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Drop for WsRelay {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut frames: broadcast::Receiver<Arc<RelayFrame>>,
+    clients: Arc<AtomicUsize>,
+    cancel: CancellationToken,
+) {
+    let mut query = None;
+    // 戻り値の型はtungsteniteで決められている。
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        query = request.uri().query().map(|query| query.to_string());
+        Ok(response)
+    };
+    let ws = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(ws) => ws,
+        Err(why) => {
+            println!("ws relay: {}: {}", peer, why);
+            return;
+        }
+    };
+    let (mut write, mut read) = ws.split();
+
+    let mut subscription = match Subscription::from_query(query.as_deref()) {
+        Ok(subscription) => subscription,
+        Err(why) => {
+            let _ = write.send(Message::text(error_json(&why))).await;
+            Subscription::default()
+        }
+    };
+    clients.fetch_add(1, Ordering::Relaxed);
+    println!("ws relay: {} connected", peer);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            frame = frames.recv() => match frame {
+                Ok(frame) => {
+                    if let Some(text) = subscription.render(&frame) {
+                        if write.send(Message::Text(text)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    println!("ws relay: {} skipped {} frames", peer, n);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<SubscribeRequest>(text.as_str())
+                        .map_err(|why| why.to_string())
+                        .and_then(|request| Subscription::new(request.subscribe))
+                    {
+                        Ok(new) => {
+                            subscription = new;
+                            serde_json::json!({ "subscribed": subscription.0 }).to_string()
+                        }
+                        Err(why) => error_json(&why),
+                    };
+                    if write.send(Message::text(reply)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = write.close().await;
+    clients.fetch_sub(1, Ordering::Relaxed);
+    println!("ws relay: {} disconnected", peer);
+}
+
+fn error_json(reason: &str) -> String {
+    serde_json::json!({ "error": reason }).to_string()
+}
+
+// WsRelayにフレームを渡すFrameSink
+// 接続しているクライアントがいない場合は何もしない。
+pub struct WsRelaySink {
+    sender: broadcast::Sender<Arc<RelayFrame>>,
+}
+
+impl FrameSink for WsRelaySink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let text = match event.device_id() {
+            Some(device_id) => tag_device(event.json_str, &device_id),
+            None => event.json_str.to_string(),
+        };
+        let _ = self.sender.send(Arc::new(RelayFrame {
+            text: Utf8Bytes::from(text),
+            frame: OnceLock::new(),
+        }));
+    }
+}
@@ -36,39 +36,134 @@ use tauri::menu::{
 use tauri_plugin_dialog::DialogExt;
 use tauri_plugin_fs::FilePath;
 
-// 複数行にわたるJSONを格納するための構造体
-struct TrackingFrame {
-    json_str: String,
-    timestamp: u64,
+use mru_core::devices::{DeviceInfo, DeviceRecordMode, DeviceTable};
+use mru_core::export::bvh::{self, BvhOptions};
+use mru_core::export::gltf::{self, GltfOptions};
+use mru_core::export::csv::{self, CsvOptions};
+use mru_core::export::{ExportError, MotionSummary};
+use mru_core::filter::{write_filtered, FilterControl, FilterOptions};
+use mru_core::gapfill::{write_filled, GapFillOptions, GapFillSummary};
+use mru_core::frames::{LoadError, LoadPolicy, LoadSummary};
+use mru_core::metadata::SessionInfo;
+use mru_core::network::{socket_addr_string, NetworkConfig, NetworkError, UdpSink};
+use mru_core::osc::{OscOptions, OscSink};
+use mru_core::position::{json_with_root_translation, PositionSink};
+use mru_core::playback::{PlaybackControl, PlaybackOptions, Player};
+use mru_core::receiver::{Receiver, SinkSet};
+use mru_core::recorder::{RecordFormat, RecordOptions, Recorder, RotationOptions, SyncPolicy};
+use mru_core::seek::{PlaybackPosition, SeekResult, SeekTarget};
+use mru_core::sink::{FrameEvent, FrameOrigin, FrameSink};
+use mru_core::stats::PacketStats;
+use mru_core::store::{FrameSource, FrameStore};
+use mru_core::relay::{RelayTarget, RelayTargets};
+use mru_core::resample::{write_resampled, ResampleOptions};
+use mru_core::vmc::{VmcOptions, VmcSink};
+use mru_core::websocket::{WsRelay, WsRelayOptions, WsRelayStatus};
+
+// 読み込んだフレーム(FrameStore)を格納するための構造体
+#[derive(Default)]
+struct TrackingFrames(Mutex<FrameStore>);
+
+// 再生位置(次に再生するフレームと、表示しているフレーム)
+#[derive(Default)]
+struct Counter(Arc<Mutex<PlaybackPosition>>);
+
+// 現在スレッドが実行されているかどうか
+#[derive(Default)]
+struct RunningStatus(Arc<Mutex<bool>>);
+
+// 再生速度やループの設定
+// 再生中にも変更できるように、start_jsonとは別に保持する。
+#[derive(Default)]
+struct PlaybackSettings(PlaybackControl);
+
+// 受信中の端末の一覧
+// 受信のタスクと共有するのでArcで保持する。
+#[derive(Default)]
+struct Devices(Arc<Mutex<DeviceTable>>);
+
+// UDPでの受信
+// ソケットは一つだけ開き、表示・記録・転送はsinksに名前を付けて追加・削除する。
+// 平滑化のフィルタと腰の位置(root_translation)の推定は表示・転送にだけかけて、記録には元のフレームを保存するので、
+// 記録以外はoutputsに追加し、outputsはフィルタと位置の推定を通してsinksに追加しておく。
+struct LiveReceiver {
+    receiver: Mutex<Option<Receiver>>,
+    sinks: SinkSet,
+    outputs: SinkSet,
 }
 
-impl TrackingFrame {
-    fn new(json_str: String) -> Self {
+impl LiveReceiver {
+    fn new(filter: &FilterControl) -> Self {
+        let sinks = SinkSet::default();
+        let outputs = SinkSet::default();
+        sinks.insert(
+            LIVE_OUTPUTS,
+            Box::new(filter.sink(PositionSink::new(outputs.clone()))),
+        );
         Self {
-            json_str: json_str,
-            timestamp: 0,
+            receiver: Mutex::new(None),
+            sinks,
+            outputs,
         }
     }
-
-    // JSONからpose_landmarks_stampを取り出してtimestampに追加する
-    fn extract_timestamp(&mut self) {
-        let v: serde_json::Value = serde_json::from_str(&self.json_str).unwrap();
-        let timestamp = v["pose_landmarks_stamp"].as_u64().unwrap();
-        self.timestamp = timestamp;
-    }
 }
 
-// TrackingFrameのVecを格納するための構造体
+// 平滑化のフィルタの設定。受信と再生で共有する。
+struct Filter(FilterControl);
+
+// LiveReceiver::sinks, outputsでの名前
+const LIVE_OUTPUTS: &str = "outputs";
+const LIVE_RECORDER: &str = "recorder";
+const LIVE_VIEW: &str = "view";
+const LIVE_VMC: &str = "vmc";
+const LIVE_OSC: &str = "osc";
+const LIVE_UDP_RELAY: &str = "udp_relay";
+const LIVE_WS_RELAY: &str = "ws_relay";
+
+// 待ち受けアドレスなどの設定
 #[derive(Default)]
-struct TrackingFrames(Mutex<Vec<TrackingFrame>>);
+struct NetworkSettings(Mutex<NetworkConfig>);
 
-// カウンタ
+// WebSocketでの配信
+// 受信・再生とは独立して開始・停止し、動いている間は受信・再生したフレームを配信する。
 #[derive(Default)]
-struct Counter(Arc<Mutex<usize>>);
+struct WsRelayState(Mutex<Option<WsRelay>>);
 
-// 現在スレッドが実行されているかどうか
+// UDPでの転送先
+// 受信・再生の途中でも追加・削除できるように、sinkとは別に保持する。
 #[derive(Default)]
-struct RunningStatus(Arc<Mutex<bool>>);
+struct UdpRelay(RelayTargets);
+
+// 再生時に、WebSocketで配信中であればそのFrameSinkを追加する。
+// UDPでの転送は送信先がなくても追加しておき、途中で追加された送信先にも送る。
+// 受信時はensure_receiverでLiveReceiverに追加する。
+async fn push_relay_sinks(app_handle: &tauri::AppHandle, sinks: &mut Vec<Box<dyn FrameSink>>) {
+    sinks.push(Box::new(app_handle.state::<UdpRelay>().0.sink()));
+    if let Some(relay) = &*app_handle.state::<WsRelayState>().0.lock().await {
+        sinks.push(Box::new(relay.sink()));
+    }
+}
+
+// 設定ファイルの保存先
+fn network_config_path(app_handle: &tauri::AppHandle) -> Option<PathBuf> {
+    match app_handle.path().app_config_dir() {
+        Ok(dir) => Some(dir.join("network.json")),
+        Err(_) => None,
+    }
+}
+
+// 引数で指定されなかった項目は保存されている設定を使う。
+async fn listen_addr(
+    bind_addr: Option<String>,
+    port: Option<u16>,
+    network_settings: &State<'_, NetworkSettings>,
+) -> String {
+    let config = network_settings.0.lock().await;
+    socket_addr_string(
+        &bind_addr.unwrap_or(config.bind_addr.clone()),
+        port.unwrap_or(config.port),
+    )
+}
 
 // event用のPayload
 #[derive(Clone, serde::Serialize)]
@@ -78,6 +173,16 @@ struct Payload {
     current_stamp: u64,
 }
 
+// udp_receive用のPayload
+// どの端末から受信したかをdevice_idで区別する。
+#[derive(Clone, serde::Serialize)]
+struct ReceivePayload {
+    filetext: String,
+    current_frame: usize,
+    current_stamp: u64,
+    device_id: String,
+}
+
 // 読み込んだファイルの概要を通知するためのPayload
 #[derive(Clone, serde::Serialize)]
 struct FrameNotifyPayload {
@@ -87,220 +192,419 @@ struct FrameNotifyPayload {
     end_timestamp: u64,
 }
 
-// UDPソケットでの待ち受け、明示的にinvokeで開始。
-// 明示的にeventで終了。
-// 以下の投稿を参考にしている。
-// https://github.com/tokio-rs/tokio/discussions/4533
-async fn receive_udp(
-    app_handle: &tauri::AppHandle,
-    window: &tauri::Window,
-    framed: UdpFramed<LinesCodec>,
-) {
-    framed
-        .for_each(|msg| async {
-            // println!("receiver: received.");
-            let (msg_str, _addr) = msg.unwrap();
-            window.emit(
-                "udp_receive",
-                Payload {
-                    filetext: msg_str,
-                    current_frame: 0,
-                    current_stamp: 0,
-                },
-            );
-        })
-        .await;
+// フロントエンドにeventで送信するFrameSink
+// 受信したフレームはudp_receive、再生したフレームはjson_sendとして送る。
+struct WindowSink {
+    window: tauri::Window,
 }
 
-#[tauri::command]
-async fn start_receive(app_handle: tauri::AppHandle, window: tauri::Window) {
-    println!("receiver: called");
-    match UdpSocket::bind("0.0.0.0:38013").await {
-        Ok(sock) => {
-            println!("receiver: start");
-            let framed = UdpFramed::new(sock, LinesCodec::new());
-            // let (_tx, rx) = framed.split();  // cannot infer type for type parameter `T`
+impl FrameSink for WindowSink {
+    fn on_frame(&mut self, event: &FrameEvent) {
+        match event.origin {
+            FrameOrigin::Live => {
+                self.window.emit(
+                    "udp_receive",
+                    ReceivePayload {
+                        filetext: event.json_str.to_string(),
+                        current_frame: 0,
+                        current_stamp: 0,
+                        device_id: event.device_id().unwrap_or_default(),
+                    },
+                );
+            }
+            FrameOrigin::Playback => {
+                self.window.emit(
+                    "json_send",
+                    Payload {
+                        filetext: event.json_str.to_string(),
+                        current_frame: event.frame_index,
+                        current_stamp: event.timestamp,
+                    },
+                );
+            }
+        }
+    }
+}
 
-            let (send, mut recv) = unbounded_channel();
+// VMCプロトコルでの送信先を開く。
+fn vmc_sink(options: VmcOptions) -> Result<VmcSink, NetworkError> {
+    let addr = socket_addr_string(&options.host, options.port);
+    match VmcSink::connect(options) {
+        Ok(sink) => {
+            println!("vmc: sending to {}", addr);
+            Ok(sink)
+        }
+        Err(why) => Err(NetworkError::Io {
+            addr,
+            reason: why.to_string(),
+        }),
+    }
+}
 
-            let stop_id = app_handle.listen_any("udp_stop", move |event| {
-                println!("receiver: stop");
-                send.send(());
-            });
+// ランドマークをOSCで送信する送信先を開く。
+fn osc_sink(options: OscOptions) -> Result<OscSink, NetworkError> {
+    let addr = socket_addr_string(&options.host, options.port);
+    match OscSink::connect(options) {
+        Ok(sink) => {
+            println!("osc: sending to {}", addr);
+            Ok(sink)
+        }
+        Err(why) => Err(NetworkError::Io {
+            addr,
+            reason: why.to_string(),
+        }),
+    }
+}
 
-            tokio::select! {
-              _ = receive_udp(&app_handle, &window, framed) => {},
-              _ = recv.recv() => {},
+// UDPでの受信を開始していなければ開始する。
+// 別のアドレスが指定された場合は、表示・記録を続けたまま開き直す。
+// 新しいアドレスで開けなかった場合は前のアドレスで開き直してエラーを返し、
+// それも失敗した場合は記録と送信を止めて、受信していない状態にする。
+async fn ensure_receiver(addr: &str, app_handle: &tauri::AppHandle) -> Result<(), NetworkError> {
+    let live = app_handle.state::<LiveReceiver>();
+    let devices = app_handle.state::<Devices>().0.clone();
+    let mut receiver = live.receiver.lock().await;
+    let running = match receiver.take() {
+        Some(running) if running.addr() == addr => {
+            *receiver = Some(running);
+            return Ok(());
+        }
+        Some(running) => running,
+        None => {
+            devices.lock().await.clear();
+            live.outputs.insert(
+                LIVE_UDP_RELAY,
+                Box::new(app_handle.state::<UdpRelay>().0.sink()),
+            );
+            if let Some(relay) = &*app_handle.state::<WsRelayState>().0.lock().await {
+                live.outputs.insert(LIVE_WS_RELAY, Box::new(relay.sink()));
             }
+            match Receiver::start(addr, devices, live.sinks.clone()).await {
+                Ok(started) => *receiver = Some(started),
+                Err(why) => {
+                    live.outputs.remove(LIVE_UDP_RELAY);
+                    live.outputs.remove(LIVE_WS_RELAY);
+                    return Err(why);
+                }
+            }
+            return Ok(());
+        }
+    };
 
-            app_handle.unlisten(stop_id); // recv.recv()が終わってからunlisten
-
-            // 以下のコードはstack overflowでうごかない
-            // let mut buf = [0; 65536];
-            // loop {
-            //   println!("receiver: waiting packet");
-            //   match sock.recv_from(&mut buf).await{
-            //     Ok((len, addr)) => {
-            //       println!("{:?} bytes received from {:?}", len, addr);
-            //       let sbuf = &buf[0..len];
-            //       let json_str = String::from_utf8(sbuf.to_vec()).unwrap();
-            //     },
-            //     Err(err) => {}
-            //   }
-            // }
+    // 前のソケットを開いたまま新しいアドレスで開く。
+    // ポートが同じ場合などは前のソケットと重なって失敗するので、閉じてから開き直す。
+    if let Ok(started) = Receiver::start(addr, devices.clone(), live.sinks.clone()).await {
+        running.stop().await;
+        *receiver = Some(started);
+        return Ok(());
+    }
+    let prev_addr = running.addr().to_string();
+    running.stop().await;
+    let why = match Receiver::start(addr, devices.clone(), live.sinks.clone()).await {
+        Ok(started) => {
+            *receiver = Some(started);
+            return Ok(());
         }
-        Err(err) => {
-            println!("receiver: already running?");
+        Err(why) => why,
+    };
+    println!("receiver: {}, reopening {}", why, prev_addr);
+    match Receiver::start(&prev_addr, devices, live.sinks.clone()).await {
+        Ok(started) => *receiver = Some(started),
+        Err(again) => {
+            // 届かないフレームを待ち続けないように、記録と送信を止める。
+            println!("receiver: {}, recording stopped", again);
+            live.sinks.remove(LIVE_RECORDER);
+            live.outputs.clear();
         }
     }
+    Err(why)
 }
 
-// 受け取ったUDPパケットをファイルに保存しながら送信する。
-async fn record_udp(
-    app_handle: &tauri::AppHandle,
-    window: &tauri::Window,
-    mut framed: UdpFramed<LinesCodec>,
-    path: &PathBuf,
-) {
-    match AsyncFile::create(&path).await {
-        Err(why) => panic!("{}", why),
-        Ok(mut file) => {
-            // NOTE: for_eachを使うとfileを渡せなくなるのでwhileにしている
-            while let Some(msg) = framed.next().await {
-                let (msg_str, _addr) = msg.unwrap();
-                window.emit(
-                    "udp_receive",
-                    Payload {
-                        filetext: msg_str.clone(),
-                        current_frame: 0,
-                        current_stamp: 0,
-                    },
-                );
-                // msg_strの最後に改行を追加して書き込む
-                let msg_str_ln = format!("{}\n", msg_str);
-                file.write_all(msg_str_ln.as_bytes()).await.unwrap();
+// VMC, OSCでの送信先
+// アドレスの誤りで受信だけが始まった状態にならないように、受信を開始する前に開く。
+struct LiveOutputs {
+    vmc: Option<VmcSink>,
+    osc: Option<OscSink>,
+}
+
+impl LiveOutputs {
+    fn open(vmc: Option<VmcOptions>, osc: Option<OscOptions>) -> Result<Self, NetworkError> {
+        Ok(Self {
+            vmc: vmc.map(vmc_sink).transpose()?,
+            osc: osc.map(osc_sink).transpose()?,
+        })
+    }
+
+    // 送信の設定を反映する。Noneの場合は送信を止める。
+    fn apply(self, live: &LiveReceiver) {
+        match self.vmc {
+            Some(sink) => live.outputs.insert(LIVE_VMC, Box::new(sink)),
+            None => {
+                live.outputs.remove(LIVE_VMC);
+            }
+        }
+        match self.osc {
+            Some(sink) => live.outputs.insert(LIVE_OSC, Box::new(sink)),
+            None => {
+                live.outputs.remove(LIVE_OSC);
             }
         }
     }
 }
 
+// UDPでの受信を開始し、受信したフレームをウィンドウに表示する。
+// 記録中に呼んだ場合も、記録はそのまま続く。
 #[tauri::command]
-async fn start_record(app_handle: tauri::AppHandle, window: tauri::Window) {
+async fn start_receive(
+    bind_addr: Option<String>,
+    port: Option<u16>,
+    vmc: Option<VmcOptions>,
+    osc: Option<OscOptions>,
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    network_settings: State<'_, NetworkSettings>,
+    live: State<'_, LiveReceiver>,
+) -> Result<(), NetworkError> {
+    println!("receiver: called");
+    let outputs = LiveOutputs::open(vmc, osc)?;
+    let addr = listen_addr(bind_addr, port, &network_settings).await;
+    ensure_receiver(&addr, &app_handle).await?;
+    outputs.apply(&live);
+    live.outputs.insert(LIVE_VIEW, Box::new(WindowSink { window }));
+    Ok(())
+}
+
+// 受信したフレームのウィンドウへの表示を切り替える。受信と記録はそのまま続く。
+#[tauri::command]
+async fn set_live_view(
+    enabled: bool,
+    window: tauri::Window,
+    live: State<'_, LiveReceiver>,
+) -> Result<(), ()> {
+    if enabled {
+        live.outputs.insert(LIVE_VIEW, Box::new(WindowSink { window }));
+    } else {
+        live.outputs.remove(LIVE_VIEW);
+    }
+    Ok(())
+}
+
+// 受信中のフレームの記録を開始する。受信していなければ受信も開始する。
+// 表示や転送を止めずに記録だけを開始できる。
+// infoは端末やカメラなどの情報で、記録ファイルのサイドカーに保存する。
+// rotationを指定した場合は一定の時間・サイズごとにファイルを分割し、<file>.session.jsonに一覧を保存する。
+// ダイアログがキャンセルされた場合はfalseを返す。
+#[tauri::command]
+async fn start_record(
+    bind_addr: Option<String>,
+    port: Option<u16>,
+    device_mode: Option<DeviceRecordMode>,
+    format: Option<RecordFormat>,
+    info: Option<SessionInfo>,
+    rotation: Option<RotationOptions>,
+    sync: Option<SyncPolicy>,
+    vmc: Option<VmcOptions>,
+    osc: Option<OscOptions>,
+    app_handle: tauri::AppHandle,
+    window: tauri::Window,
+    network_settings: State<'_, NetworkSettings>,
+    live: State<'_, LiveReceiver>,
+) -> Result<bool, NetworkError> {
     println!("recorder: called");
     // まずダイアログを開いてファイルを指定する。
-    let mut file_path = app_handle.dialog().file().blocking_save_file();
-    match file_path {
-        Some(path) => {
-            // UDP待ち受け開始
-            match UdpSocket::bind("0.0.0.0:38013").await {
-                Ok(sock) => {
-                    println!("recorder: start");
-                    let framed = UdpFramed::new(sock, LinesCodec::new());
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => {
+            println!("recorder: invalid file path?");
+            return Ok(false);
+        }
+    };
+
+    let outputs = LiveOutputs::open(vmc, osc)?;
+    let addr = listen_addr(bind_addr, port, &network_settings).await;
+    ensure_receiver(&addr, &app_handle).await?;
+    outputs.apply(&live);
+    if !live.outputs.contains(LIVE_VIEW) {
+        live.outputs.insert(LIVE_VIEW, Box::new(WindowSink { window }));
+    }
+
+    // 受信と送信の準備ができてからファイルを作るので、失敗しても空のファイルは残らない。
+    // ファイルを作れなかった場合はキャンセルと区別できるようにエラーを返す。
+    let options = RecordOptions {
+        mode: device_mode.unwrap_or_default(),
+        format: format.unwrap_or_default(),
+        info: info.unwrap_or_default(),
+        rotation: rotation.unwrap_or_default(),
+        sync: sync.unwrap_or_default(),
+    };
+    let recorder = Recorder::create(&pathbuf, options).map_err(|why| {
+        println!("recorder: {}", why);
+        NetworkError::Io {
+            addr: pathbuf.to_string_lossy().to_string(),
+            reason: why.to_string(),
+        }
+    })?;
+    // 記録中の場合は前のファイルを閉じてから切り替える。
+    live.sinks.insert(LIVE_RECORDER, Box::new(recorder));
+    println!("recorder: writing to {:?}", pathbuf);
+    Ok(true)
+}
 
-                    let (send, mut recv) = unbounded_channel();
+// 記録だけを停止する。受信と表示は続く。
+#[tauri::command]
+async fn stop_record(live: State<'_, LiveReceiver>) -> Result<(), ()> {
+    live.sinks.remove(LIVE_RECORDER);
+    Ok(())
+}
 
-                    let stop_id = app_handle.listen_any("udp_stop", move |event| {
-                        println!("recorder: stop");
-                        send.send(());
-                    });
+// 受信を停止する。記録中の場合は記録も停止する。
+#[tauri::command]
+async fn stop_receive(live: State<'_, LiveReceiver>) -> Result<(), ()> {
+    if let Some(receiver) = live.receiver.lock().await.take() {
+        receiver.stop().await;
+    }
+    live.sinks.remove(LIVE_RECORDER);
+    live.outputs.clear();
+    Ok(())
+}
 
-                    let pathbuf = path.into_path().unwrap().to_path_buf();
+// 受信中のアドレスと、表示・記録しているかどうか
+#[derive(Clone, serde::Serialize)]
+struct ReceiveStatus {
+    addr: Option<String>,
+    view: bool,
+    recording: bool,
+}
 
-                    tokio::select! {
-                    _ = record_udp(&app_handle, &window, framed, &pathbuf) => {},
-                    _ = recv.recv() => {},
-                    }
+#[tauri::command]
+async fn get_receive_status(live: State<'_, LiveReceiver>) -> Result<ReceiveStatus, ()> {
+    Ok(ReceiveStatus {
+        addr: live
+            .receiver
+            .lock()
+            .await
+            .as_ref()
+            .map(|receiver| receiver.addr().to_string()),
+        view: live.outputs.contains(LIVE_VIEW),
+        recording: live.sinks.contains(LIVE_RECORDER),
+    })
+}
 
-                    app_handle.unlisten(stop_id); // recv.recv()が終わってからunlisten
-                }
-                Err(err) => {
-                    println!("recorder: already running?");
-                }
-            }
-        }
-        _ => {
-            println!("recorder: invalid file path?");
-        }
+// WebSocketでの配信を開始する。配信中の場合は一度停止してから開始する。
+#[tauri::command]
+async fn start_ws_relay(
+    options: Option<WsRelayOptions>,
+    ws_relay: State<'_, WsRelayState>,
+    live: State<'_, LiveReceiver>,
+) -> Result<WsRelayStatus, NetworkError> {
+    // ensure_receiverと逆の順番でロックしないように、先に受信中かどうかを確認する。
+    let receiving = live.receiver.lock().await.is_some();
+    let mut relay = ws_relay.0.lock().await;
+    // 同じポートで開き直せるように、先に停止する。
+    *relay = None;
+    let started = WsRelay::start(&options.unwrap_or_default()).await?;
+    let status = started.status();
+    // 受信中であれば、受信したフレームもすぐに配信する。
+    if receiving {
+        live.outputs.insert(LIVE_WS_RELAY, Box::new(started.sink()));
     }
+    *relay = Some(started);
+    Ok(status)
 }
 
-// 中身が空なのは、eventがバックエンド内部では送受信できない。
-// なので、フロントエンドからeventを送信して、
-// udp_stopを呼び出す。
 #[tauri::command]
-async fn end_receive() {}
+async fn stop_ws_relay(
+    ws_relay: State<'_, WsRelayState>,
+    live: State<'_, LiveReceiver>,
+) -> Result<(), ()> {
+    live.outputs.remove(LIVE_WS_RELAY);
+    *ws_relay.0.lock().await = None;
+    Ok(())
+}
 
-// UDPと同様にjson文字列をemitする無限ループを作成する。
-async fn send_json(
-    app_handle: &tauri::AppHandle,
-    window: &tauri::Window,
-    sock: UdpSocket,
-    tracking_frames: &State<'_, TrackingFrames>,
-    counter: &State<'_, Counter>,
-) {
-    let tf_buf = tracking_frames.0.lock().await;
-    let idx = *counter.0.lock().await;
-    if idx >= tf_buf.len() {
-        println!("  send_json: idx is out of range.");
-    } else {
-        // 長時間のデータの場合、誤差が累積しないように
-        // 送信開始時のタイムスタンプと現在のタイムスタンプの差分が
-        // 送信開始時の時刻と現在の時刻の差分と同じになるようにする。
-        let timeline_origin: u64 = tf_buf[idx].timestamp;
-        let stream_origin = Instant::now();
-        // 前回送信直後の時刻を保持する。
-        let mut timestamp_prev = stream_origin;
-
-        let mut t0: u64 = tf_buf[idx].timestamp;
-        // for tf in tf_buf.iter() {
-        for i in idx..tf_buf.len() {
-            let tf = &tf_buf[i];
-            let t1: u64 = tf.timestamp;
-            if t1 < t0 {
-                // この場合タイムラインが壊れているので送信しない。
-                println!("  send_json: time diff is negative.");
-                t0 = t1;
-            } else {
-                t0 = t1;
-                // タイムスタンプにおける現在フレームと始点との差分(1)
-                let td_from_origin = t1 - timeline_origin;
-                // 前回送信時刻と最初に送信した時刻の差分(2)
-                let duration_from_origin = timestamp_prev - stream_origin;
-                // 待機時間は(1)-(2)
-                let td_from_prev = td_from_origin - (duration_from_origin.as_micros() as u64);
-                tokio::time::sleep(Duration::from_micros(td_from_prev)).await;
-                println!(
-                    "  send_json: in the loop, waited {} microsec.",
-                    td_from_prev
-                );
-                // 送信前のタイムスタンプを保持する
-                let duration0 = Instant::now();
-                // フロントエンドに送信
-                window.emit(
-                    "json_send",
-                    Payload {
-                        filetext: tf.json_str.clone(),
-                        current_frame: i,
-                        current_stamp: tf.timestamp,
-                    },
-                );
-                // UDPで送信
-                sock.send(tf.json_str.clone().as_bytes()).await;
-                // 送信にかかった時間を計算
-                let duration1 = Instant::now();
-                let duration = duration1 - duration0;
-                println!(
-                    "  send_json: emit duration: {} microsec.",
-                    duration.as_micros()
-                );
-                // 送信にかかった時間は加算せず、今回送信を開始した時刻を保存しておく。
-                timestamp_prev = duration0;
-            }
-            // *counter.0.lock().await += 1;
-            *counter.0.lock().await = i + 1; // 他のスレッドから書き換えられる可能性を考えるとi+1
-            println!("  send_json: counter: {}", *counter.0.lock().await);
+// 配信中のアドレスと接続しているクライアントの数を返す。停止中はNone
+#[tauri::command]
+async fn get_ws_relay_status(
+    ws_relay: State<'_, WsRelayState>,
+) -> Result<Option<WsRelayStatus>, ()> {
+    Ok(ws_relay.0.lock().await.as_ref().map(|relay| relay.status()))
+}
+
+// UDPでの転送先を追加する。同じアドレスの場合はストリームの指定を置き換える。
+#[tauri::command]
+async fn add_relay_target(
+    target: RelayTarget,
+    udp_relay: State<'_, UdpRelay>,
+) -> Result<Vec<RelayTarget>, NetworkError> {
+    udp_relay.0.add(target)?;
+    Ok(udp_relay.0.list())
+}
+
+#[tauri::command]
+async fn remove_relay_target(
+    host: String,
+    port: u16,
+    udp_relay: State<'_, UdpRelay>,
+) -> Result<Vec<RelayTarget>, ()> {
+    udp_relay.0.remove(&host, port);
+    Ok(udp_relay.0.list())
+}
+
+#[tauri::command]
+async fn list_relay_targets(udp_relay: State<'_, UdpRelay>) -> Result<Vec<RelayTarget>, ()> {
+    Ok(udp_relay.0.list())
+}
+
+// 現在受信中の端末の一覧を返す。
+#[tauri::command]
+async fn list_devices(devices: State<'_, Devices>) -> Result<Vec<DeviceInfo>, ()> {
+    Ok(devices.0.lock().await.active(Instant::now()))
+}
+
+// 送信元ごとの受信の統計(受信fps、ジッタ、欠落フレーム数など)を返す。
+#[tauri::command]
+async fn get_udp_stats(devices: State<'_, Devices>) -> Result<Vec<PacketStats>, ()> {
+    Ok(devices.0.lock().await.stats(Instant::now()))
+}
+
+// 受信中は送信元ごとの受信の統計を1秒ごとにudp_statsで通知する。
+async fn emit_udp_stats(app_handle: tauri::AppHandle) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        if app_handle.state::<LiveReceiver>().receiver.lock().await.is_none() {
+            continue;
         }
-        println!("  send_json: loop end.");
+        let stats = app_handle
+            .state::<Devices>()
+            .0
+            .lock()
+            .await
+            .stats(Instant::now());
+        app_handle.emit("udp_stats", stats);
+    }
+}
+
+// 保存されている設定を返す。
+#[tauri::command]
+async fn get_network_config(
+    network_settings: State<'_, NetworkSettings>,
+) -> Result<NetworkConfig, ()> {
+    Ok(network_settings.0.lock().await.clone())
+}
+
+// 設定を更新してファイルに保存する。
+#[tauri::command]
+async fn set_network_config(
+    config: NetworkConfig,
+    app_handle: tauri::AppHandle,
+    network_settings: State<'_, NetworkSettings>,
+) -> Result<(), String> {
+    *network_settings.0.lock().await = config.clone();
+    match network_config_path(&app_handle) {
+        Some(path) => config.save(&path).map_err(|why| why.to_string()),
+        None => Err("config directory not found".to_string()),
     }
 }
 
@@ -309,11 +613,17 @@ async fn send_json(
 async fn start_json(
     counter_reset: bool,
     ipaddr: String,
+    dest_port: Option<u16>,
+    vmc: Option<VmcOptions>,
+    osc: Option<OscOptions>,
     app_handle: tauri::AppHandle,
     window: tauri::Window,
     tracking_frames: State<'_, TrackingFrames>,
     counter: State<'_, Counter>,
     running: State<'_, RunningStatus>,
+    network_settings: State<'_, NetworkSettings>,
+    playback_settings: State<'_, PlaybackSettings>,
+    filter: State<'_, Filter>,
 ) -> Result<(), ()> {
     println!("start_json: called");
     if *running.0.lock().await {
@@ -324,35 +634,63 @@ async fn start_json(
 
     // counterを初期化
     if counter_reset {
-        *counter.0.lock().await = 0;
+        *counter.0.lock().await = PlaybackPosition::default();
     }
 
-    // bindでは0.0.0.0を指定しておく。
-    match UdpSocket::bind("0.0.0.0:0").await {
-        Ok(sock) => {
-            // 送信だけが必要なのでconnectで送信先を指定する。
-            sock.connect(format!("{}:38013", ipaddr)).await;
+    // UDPと同様にjson文字列をemitするループを作成する。
+    // フロントエンドへのemitとUDP送信はそれぞれFrameSinkとする。
+    let port = dest_port.unwrap_or(network_settings.0.lock().await.dest_port);
+    match UdpSink::connect(&socket_addr_string(&ipaddr, port)) {
+        Ok(udp_sink) => {
+            let mut sinks: Vec<Box<dyn FrameSink>> = vec![Box::new(WindowSink {
+                window: window.clone(),
+            })];
+            if let Some(options) = vmc {
+                match vmc_sink(options) {
+                    Ok(sink) => sinks.push(Box::new(sink)),
+                    Err(why) => println!("start_json: {}", why),
+                }
+            }
+            if let Some(options) = osc {
+                match osc_sink(options) {
+                    Ok(sink) => sinks.push(Box::new(sink)),
+                    Err(why) => println!("start_json: {}", why),
+                }
+            }
+            push_relay_sinks(&app_handle, &mut sinks).await;
+            // HIROMEIROへは記録された行をそのまま送り、root_translationは他の送信先だけに付ける。
+            let sinks: Vec<Box<dyn FrameSink>> =
+                vec![Box::new(udp_sink), Box::new(PositionSink::new(sinks))];
+            let mut sinks = filter.0.sink(sinks);
 
             // UDPと同様に、unbounded_channelを使って送信スレッドを作成。
             let (send, mut recv) = unbounded_channel();
 
-            let stop_id = app_handle.listen_any("json_stop", move |event| {
-                println!("receiver: stop");
-                send.send(());
+            let stop_id = app_handle.listen_any("json_stop", move |_event| {
+                println!("start_json: stop");
+                let _ = send.send(());
             });
 
+            let tf_buf = tracking_frames.0.lock().await;
+            let mut player = Player::new(counter.0.lock().await.next);
             tokio::select! {
-              _ = send_json(
-                &app_handle, &window, sock, &tracking_frames, &counter) => {},
+              _ = player.play(&*tf_buf, &mut sinks, &playback_settings.0) => {},
               _ = recv.recv() => {},
             }
-            println!("open_file: end");
-            println!("open_file: counter: {}", *counter.0.lock().await);
+            // 途中で止めた場合も次のフレームから再開できるようにする。
+            {
+                let mut position = counter.0.lock().await;
+                position.next = player.next;
+                if player.last.is_some() {
+                    position.current = player.last;
+                }
+                println!("start_json: counter: {}", position.next);
+            }
 
             app_handle.unlisten(stop_id); // recv.recv()が終わってからunlisten
         }
-        Err(err) => {
-            println!("start_json: binding failed.");
+        Err(why) => {
+            println!("start_json: binding failed: {}", why);
         }
     }
 
@@ -362,12 +700,41 @@ async fn start_json(
     Ok(())
 }
 
+// 再生速度やループの設定を変更する。
+// 再生中に呼び出した場合も、再生を止めずに次のフレームから反映される。
+#[tauri::command]
+async fn set_playback_options(
+    options: PlaybackOptions,
+    playback_settings: State<'_, PlaybackSettings>,
+) -> Result<PlaybackOptions, ()> {
+    playback_settings.0.set(options);
+    Ok(playback_settings.0.get())
+}
+
+// 平滑化のフィルタの設定を変更する。
+// 受信・再生中に呼び出した場合も、次のフレームから反映される。
+#[tauri::command]
+async fn set_filter(options: FilterOptions, filter: State<'_, Filter>) -> Result<FilterOptions, ()> {
+    filter.0.set(options);
+    Ok(filter.0.get())
+}
+
+#[tauri::command]
+async fn get_filter(filter: State<'_, Filter>) -> Result<FilterOptions, ()> {
+    Ok(filter.0.get())
+}
+
+#[tauri::command]
+async fn get_playback_options(
+    playback_settings: State<'_, PlaybackSettings>,
+) -> Result<PlaybackOptions, ()> {
+    Ok(playback_settings.0.get())
+}
+
 // ステップ実行を行う。
 #[tauri::command]
 async fn step_json(
-    counter_reset: bool,
     increment: bool,
-    app_handle: tauri::AppHandle,
     window: tauri::Window,
     tracking_frames: State<'_, TrackingFrames>,
     counter: State<'_, Counter>,
@@ -384,27 +751,18 @@ async fn step_json(
     let buf_length = tf_buf.len();
 
     if buf_length > 1 {
-        let cnt = *counter.0.lock().await;
-        // cntには次のフレームのインデックスが入っている。
-        // incrementがtrueなら、cntをそのまま使う。
-        // incrementがfalseなら、cntを2減らす。
-        let mut idx: usize;
-        if increment {
-            idx = cnt % buf_length;
-            *counter.0.lock().await = (cnt + 1) % buf_length;
-        } else {
-            idx = (buf_length + cnt - 2) % buf_length;
-            *counter.0.lock().await = (buf_length + cnt - 1) % buf_length;
+        // incrementがtrueなら次のフレーム、falseなら前のフレームに移動する。
+        let idx = counter.0.lock().await.step(buf_length, increment);
+        if let Some(tf) = tf_buf.frame(idx) {
+            window.emit(
+                "json_send",
+                Payload {
+                    filetext: json_with_root_translation(&tf.json_str, &tf.frame),
+                    current_frame: idx,
+                    current_stamp: tf.timestamp,
+                },
+            );
         }
-        let tf = &tf_buf[idx];
-        window.emit(
-            "json_send",
-            Payload {
-                filetext: tf.json_str.clone(),
-                current_frame: idx,
-                current_stamp: tf.timestamp,
-            },
-        );
     }
 
     *running.0.lock().await = false;
@@ -434,17 +792,17 @@ async fn set_counter(
     let buf_length = tf_buf.len();
 
     if idx < buf_length {
-        // cntには次のフレームのインデックスが入る。
-        *counter.0.lock().await = (idx + 1) % buf_length;
-        let tf = &tf_buf[idx];
-        window.emit(
-            "json_send",
-            Payload {
-                filetext: tf.json_str.clone(),
-                current_frame: idx,
-                current_stamp: tf.timestamp,
-            },
-        );
+        counter.0.lock().await.show(idx, buf_length);
+        if let Some(tf) = tf_buf.frame(idx) {
+            window.emit(
+                "json_send",
+                Payload {
+                    filetext: json_with_root_translation(&tf.json_str, &tf.frame),
+                    current_frame: idx,
+                    current_stamp: tf.timestamp,
+                },
+            );
+        }
     }
 
     *running.0.lock().await = false;
@@ -453,67 +811,247 @@ async fn set_counter(
     Ok(())
 }
 
+// 時刻またはフレーム数を指定して移動する。
+// 移動先のフレームをフロントエンドに送信し、移動先を返す。
+#[tauri::command]
+async fn seek(
+    target: SeekTarget,
+    window: tauri::Window,
+    tracking_frames: State<'_, TrackingFrames>,
+    counter: State<'_, Counter>,
+    running: State<'_, RunningStatus>,
+) -> Result<SeekResult, ()> {
+    println!("seek: called");
+    if *running.0.lock().await {
+        println!("seek: already running.");
+        return Err(());
+    }
+    *running.0.lock().await = true;
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let buf_length = tf_buf.len();
+
+    let mut result = Err(());
+    if buf_length > 0 {
+        // 表示しているフレームから移動する。
+        let idx = counter.0.lock().await.seek(&*tf_buf, target);
+        if let Some(tf) = tf_buf.frame(idx) {
+            window.emit(
+                "json_send",
+                Payload {
+                    filetext: json_with_root_translation(&tf.json_str, &tf.frame),
+                    current_frame: idx,
+                    current_stamp: tf.timestamp,
+                },
+            );
+        }
+        result = Ok(SeekResult::new(&*tf_buf, idx));
+    }
+
+    *running.0.lock().await = false;
+    println!("seek: end");
+
+    result
+}
+
 // メニューからファイルダイアログを開き、
 // 指定されたファイルをテキストで開いて、一行ずつ読み込む。
 // 読み込んだ内容をフロントエンドにeventで送る。
-// ファイルの開き方？
 // 小さいファイルであれば一度メモリにためてしまう。
-// 大きいファイルであれば、
-// 開きっぱなしにして任意の行を送信できるようにする。
+// 大きいファイルや圧縮形式のファイルであれば、
+// 開きっぱなしにして任意の行を送信できるようにする(FrameStore)。
 #[tauri::command]
 async fn open_file(
+    policy: Option<LoadPolicy>,
     app_handle: tauri::AppHandle,
     window: tauri::Window,
     tracking_frames: State<'_, TrackingFrames>,
     counter: State<'_, Counter>,
     running: State<'_, RunningStatus>,
-) -> Result<(), ()> {
+) -> Result<LoadSummary, LoadError> {
     println!("open_file invoked");
-    let mut file_path = app_handle.dialog().file().blocking_pick_file();
+    let file_path = app_handle.dialog().file().blocking_pick_file();
 
-    match file_path {
-        Some(path) => {
-            let pathbuf = path.into_path().unwrap().to_path_buf();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(LoadError::Cancelled),
+    };
 
-            let mut file = match File::open(&pathbuf) {
-                Err(why) => panic!("{}", why),
-                Ok(file) => file,
-            };
-
-            // ファイルの中身を一行ずつ読み込んでTrackingFrameを作成し、
-            // TrackingFramesに格納する。
-            for line in BufReader::new(file).lines() {
-                match line {
-                    Ok(s) => {
-                        let mut tf = TrackingFrame::new(s);
-                        tf.extract_timestamp();
-                        println!("stamp: {}", tf.timestamp);
-                        tracking_frames.0.lock().await.push(tf);
-                    }
-                    _ => {}
-                }
-            }
+    // ファイルの中身を一行ずつ読み込んでTrackingFrameを作成する。
+    // 読み込みに成功した場合のみTrackingFramesを置き換える。
+    let (frames, summary) = FrameStore::open(&pathbuf, policy.unwrap_or_default())?;
+    println!(
+        "open_file: loaded {} frames, skipped {} lines, streaming {}.",
+        summary.loaded_frames, summary.skipped_lines, summary.streaming
+    );
 
-            // フロントエンドに読み込んだファイルの行数を送信する。
-            let tf_buf = tracking_frames.0.lock().await;
-            let total_frames = tf_buf.len();
-            window.emit(
-                "total_frames",
-                FrameNotifyPayload {
-                    current_frame: 0,
-                    total_frames: total_frames,
-                    begin_timestamp: tf_buf[0].timestamp,
-                    end_timestamp: tf_buf[total_frames - 1].timestamp,
-                },
-            );
-        }
-        _ => {}
-    }
+    let mut tf_buf = tracking_frames.0.lock().await;
+    *tf_buf = frames;
+
+    // フロントエンドに読み込んだファイルの行数を送信する。
+    window.emit(
+        "total_frames",
+        FrameNotifyPayload {
+            current_frame: 0,
+            total_frames: summary.loaded_frames,
+            begin_timestamp: summary.begin_timestamp,
+            end_timestamp: summary.end_timestamp,
+        },
+    );
 
     // counterを初期化
-    *counter.0.lock().await = 0;
+    *counter.0.lock().await = PlaybackPosition::default();
 
-    Ok(())
+    Ok(summary)
+}
+
+// 読み込んだフレームをストリームごとのCSVファイルに書き出す。
+// 指定したファイル名にストリーム名を付けたファイルが作成される。
+#[tauri::command]
+async fn export_csv(
+    options: Option<CsvOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+) -> Result<Vec<String>, ExportError> {
+    println!("export_csv: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let paths = csv::export_csv(&*tf_buf, &pathbuf, &options.unwrap_or_default())?;
+    println!("export_csv: {} files written.", paths.len());
+    Ok(paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
+}
+
+// 読み込んだフレームのpose_world_landmarksから骨格のアニメーションを作り、BVHに書き出す。
+#[tauri::command]
+async fn export_bvh(
+    options: Option<BvhOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+) -> Result<MotionSummary, ExportError> {
+    println!("export_bvh: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let summary = bvh::export_bvh(&*tf_buf, &pathbuf, &options.unwrap_or_default())?;
+    println!(
+        "export_bvh: {} frames, {} fps.",
+        summary.frames, summary.frame_rate
+    );
+    Ok(summary)
+}
+
+// 読み込んだフレームのpose_world_landmarksから骨格のアニメーションを作り、glTF(GLB)に書き出す。
+#[tauri::command]
+async fn export_gltf(
+    options: Option<GltfOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+) -> Result<MotionSummary, ExportError> {
+    println!("export_gltf: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let summary = gltf::export_gltf(&*tf_buf, &pathbuf, &options.unwrap_or_default())?;
+    println!(
+        "export_gltf: {} frames, {} fps.",
+        summary.frames, summary.frame_rate
+    );
+    Ok(summary)
+}
+
+// 読み込んだフレームにフィルタをかけたコピーを1行1フレームのjsonとして書き出す。
+// optionsを省略した場合は、受信・再生と同じ設定を使う。
+#[tauri::command]
+async fn export_filtered(
+    options: Option<FilterOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+    filter: State<'_, Filter>,
+) -> Result<FilteredSummary, ExportError> {
+    println!("export_filtered: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let options = options.unwrap_or_else(|| filter.0.get());
+    let tf_buf = tracking_frames.0.lock().await;
+    let frames = write_filtered(&*tf_buf, &pathbuf, &options)?;
+    println!("export_filtered: {} frames written.", frames);
+    Ok(FilteredSummary {
+        path: pathbuf.to_string_lossy().to_string(),
+        frames,
+    })
+}
+
+// 読み込んだフレームを一定のフレームレートに補間したコピーを1行1フレームのjsonとして書き出す。
+#[tauri::command]
+async fn export_resampled(
+    options: Option<ResampleOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+) -> Result<MotionSummary, ExportError> {
+    println!("export_resampled: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let summary = write_resampled(&*tf_buf, &pathbuf, &options.unwrap_or_default())?;
+    println!(
+        "export_resampled: {} frames, {} fps.",
+        summary.frames, summary.frame_rate
+    );
+    Ok(summary)
+}
+
+// 読み込んだフレームの手や顔の欠落、visibilityが低いランドマークを補ったコピーを
+// 1行1フレームのjsonとして書き出す。補った値にはfilledが付く。
+#[tauri::command]
+async fn export_filled(
+    options: Option<GapFillOptions>,
+    app_handle: tauri::AppHandle,
+    tracking_frames: State<'_, TrackingFrames>,
+) -> Result<GapFillSummary, ExportError> {
+    println!("export_filled: called");
+    let file_path = app_handle.dialog().file().blocking_save_file();
+    let pathbuf = match file_path.and_then(|path| path.into_path().ok()) {
+        Some(pathbuf) => pathbuf,
+        None => return Err(ExportError::Cancelled),
+    };
+
+    let tf_buf = tracking_frames.0.lock().await;
+    let summary = write_filled(&*tf_buf, &pathbuf, &options.unwrap_or_default())?;
+    println!(
+        "export_filled: {} frames, {} streams and {} landmarks filled.",
+        summary.frames, summary.filled_streams, summary.filled_landmarks
+    );
+    Ok(summary)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct FilteredSummary {
+    path: String,
+    frames: usize,
 }
 
 // ファイルを保存する場合は、
@@ -547,6 +1085,7 @@ async fn save_file(app_handle: tauri::AppHandle, window: tauri::Window) {
 
 pub fn run() {
     let context = tauri::generate_context!();
+    let filter_control = FilterControl::new();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_http::init())
@@ -561,21 +1100,100 @@ pub fn run() {
         .manage(TrackingFrames(Default::default()))
         .manage(Counter(Default::default()))
         .manage(RunningStatus(Default::default()))
+        .manage(NetworkSettings(Default::default()))
+        .manage(Devices(Default::default()))
+        .manage(PlaybackSettings(Default::default()))
+        .manage(WsRelayState(Default::default()))
+        .manage(UdpRelay(Default::default()))
+        .manage(Filter(filter_control.clone()))
+        .manage(LiveReceiver::new(&filter_control))
         .invoke_handler(tauri::generate_handler![
             start_receive,
+            stop_receive,
+            set_live_view,
+            get_receive_status,
             start_record,
-            end_receive,
+            stop_record,
             open_file,
             save_file,
             start_json,
             step_json,
-            set_counter
+            set_counter,
+            get_network_config,
+            set_network_config,
+            list_devices,
+            get_udp_stats,
+            set_playback_options,
+            get_playback_options,
+            seek,
+            export_csv,
+            export_bvh,
+            export_gltf,
+            export_filtered,
+            export_resampled,
+            export_filled,
+            set_filter,
+            get_filter,
+            start_ws_relay,
+            stop_ws_relay,
+            get_ws_relay_status,
+            add_relay_target,
+            remove_relay_target,
+            list_relay_targets
         ])
         .setup(|app| {
+            // 保存されている設定を読み込む
+            if let Some(path) = network_config_path(app.handle()) {
+                let config = NetworkConfig::load(&path);
+                *app.state::<NetworkSettings>().0.blocking_lock() = config;
+            }
+            tauri::async_runtime::spawn(emit_udp_stats(app.handle().clone()));
+
             let m_open = MenuItemBuilder::with_id("open", "Open").build(app)?;
             let m_save = MenuItemBuilder::with_id("save", "Save").build(app)?;
             let submenu = SubmenuBuilder::new(app, "File").items(&[&m_open, &m_save]).build()?;
-            let menu = MenuBuilder::new(app).item(&submenu).build()?;
+            let m_csv_wide = MenuItemBuilder::with_id("export_csv_wide", "CSV (wide)").build(app)?;
+            let m_csv_long = MenuItemBuilder::with_id("export_csv_long", "CSV (long)").build(app)?;
+            let m_bvh = MenuItemBuilder::with_id("export_bvh", "BVH").build(app)?;
+            let m_bvh_fingers =
+                MenuItemBuilder::with_id("export_bvh_fingers", "BVH (with fingers)").build(app)?;
+            let m_gltf = MenuItemBuilder::with_id("export_gltf", "glTF (GLB)").build(app)?;
+            let m_gltf_fingers =
+                MenuItemBuilder::with_id("export_gltf_fingers", "glTF (GLB, with fingers)")
+                    .build(app)?;
+            let m_bvh_root_motion =
+                MenuItemBuilder::with_id("export_bvh_root_motion", "BVH (with root motion)")
+                    .build(app)?;
+            let m_gltf_root_motion =
+                MenuItemBuilder::with_id("export_gltf_root_motion", "glTF (GLB, with root motion)")
+                    .build(app)?;
+            let m_filtered =
+                MenuItemBuilder::with_id("export_filtered", "Filtered copy (JSON)").build(app)?;
+            let m_resampled_30 =
+                MenuItemBuilder::with_id("export_resampled_30", "Resampled copy (JSON, 30 fps)")
+                    .build(app)?;
+            let m_resampled_60 =
+                MenuItemBuilder::with_id("export_resampled_60", "Resampled copy (JSON, 60 fps)")
+                    .build(app)?;
+            let m_filled =
+                MenuItemBuilder::with_id("export_filled", "Gap-filled copy (JSON)").build(app)?;
+            let export_menu = SubmenuBuilder::new(app, "Export")
+                .items(&[
+                    &m_csv_wide,
+                    &m_csv_long,
+                    &m_bvh,
+                    &m_bvh_fingers,
+                    &m_gltf,
+                    &m_gltf_fingers,
+                    &m_bvh_root_motion,
+                    &m_gltf_root_motion,
+                    &m_filtered,
+                    &m_resampled_30,
+                    &m_resampled_60,
+                    &m_filled,
+                ])
+                .build()?;
+            let menu = MenuBuilder::new(app).item(&submenu).item(&export_menu).build()?;
             app.set_menu(menu);
             app.on_menu_event(|app, event| match event.id().as_ref() {
                 "open" => {
@@ -600,6 +1218,18 @@ pub fn run() {
                         },
                     );
                 }
+                // 書き出し形式はfiletextで渡す。
+                id if id.starts_with("export_") => {
+                    println!("export menu called");
+                    app.emit(
+                        "export_menu",
+                        Payload {
+                            filetext: id.to_string(),
+                            current_frame: 0,
+                            current_stamp: 0,
+                        },
+                    );
+                }
                 _ => {}
             });
 
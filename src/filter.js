@@ -0,0 +1,67 @@
+import {invoke} from "@tauri-apps/api/core";
+
+// 平滑化のフィルタの設定。受信・再生の途中で変更してもすぐに反映される。
+// 記録するフレームにはかからず、表示・送信するフレームにだけかかる。
+export function setup_filter() {
+    const filter_kind_select = document.getElementById("filter_kind");
+    const filter_streams_input = document.getElementById("filter_streams");
+    // フィルタの種類ごとのパラメータ。選択した種類のものだけを表示する。
+    const params = {
+        one_euro: ["min_cutoff", "beta"],
+        ema: ["time_constant"],
+        kalman: ["measurement_std", "acceleration_std"],
+    };
+    const param_inputs = {};
+    Object.values(params).flat().forEach((name) => {
+        param_inputs[name] = document.getElementById("filter_" + name);
+    });
+
+    function show_params() {
+        Object.entries(params).forEach(([kind, names]) => {
+            names.forEach((name) => {
+                param_inputs[name].parentElement.style.display =
+                    filter_kind_select.value == kind ? "inline" : "none";
+            });
+        });
+    }
+
+    function filter_options() {
+        let kind = filter_kind_select.value;
+        let filter = null;
+        if (kind != "") {
+            filter = {kind: kind};
+            params[kind].forEach((name) => {
+                if (param_inputs[name].value != "") {
+                    filter[name] = param_inputs[name].valueAsNumber;
+                }
+            });
+        }
+        return {
+            filter: filter,
+            streams: filter_streams_input.value.split(",").map((s) => s.trim()).filter((s) => s != ""),
+        };
+    }
+
+    function apply() {
+        show_params();
+        invoke("set_filter", {options: filter_options()}).then().catch((err) => {
+            console.error(err);
+        });
+    }
+
+    // 別のウィンドウで変更した設定から始める。
+    invoke("get_filter").then((options) => {
+        if (options.filter !== null) {
+            filter_kind_select.value = options.filter.kind;
+            params[options.filter.kind].forEach((name) => {
+                param_inputs[name].value = options.filter[name];
+            });
+        }
+        filter_streams_input.value = options.streams.join(",");
+        show_params();
+    });
+
+    filter_kind_select.addEventListener("change", apply);
+    filter_streams_input.addEventListener("change", apply);
+    Object.values(param_inputs).forEach((input) => input.addEventListener("change", apply));
+}
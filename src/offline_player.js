@@ -7,6 +7,9 @@ import {listen, emit} from "@tauri-apps/api/event";
 import {getCurrentWebviewWindow} from "@tauri-apps/api/webviewWindow";
 
 import {MediapipeHolisticResult} from "./viewer";
+import {setup_ws_relay} from "./ws_relay";
+import {setup_udp_relay} from "./udp_relay";
+import {setup_filter} from "./filter";
 const appWindow = getCurrentWebviewWindow()
 
 const play_button = document.getElementById("play_anim");
@@ -15,8 +18,30 @@ const stop_button = document.getElementById("stop_anim");
 const next_button = document.getElementById("next_frame");
 const prev_button = document.getElementById("prev_frame");
 const message_div = document.getElementById("message_div");
+const export_div = document.getElementById("export_div");
 const frames_div = document.getElementById("frames_div");
+const metadata_div = document.getElementById("metadata_div");
 const frame_slider = document.getElementById("frame_slider");
+const dest_port_input = document.getElementById("dest_port");
+const speed_select = document.getElementById("speed");
+const looping_input = document.getElementById("looping");
+const set_a_button = document.getElementById("set_a");
+const set_b_button = document.getElementById("set_b");
+const clear_ab_button = document.getElementById("clear_ab");
+const ab_div = document.getElementById("ab_div");
+const back_1s_button = document.getElementById("back_1s");
+const forward_1s_button = document.getElementById("forward_1s");
+const seek_time_input = document.getElementById("seek_time");
+const seek_go_button = document.getElementById("seek_go");
+const vmc_enabled_input = document.getElementById("vmc_enabled");
+const vmc_host_input = document.getElementById("vmc_host");
+const vmc_port_input = document.getElementById("vmc_port");
+const vmc_fingers_input = document.getElementById("vmc_fingers");
+const osc_enabled_input = document.getElementById("osc_enabled");
+const osc_host_input = document.getElementById("osc_host");
+const osc_port_input = document.getElementById("osc_port");
+const osc_prefix_input = document.getElementById("osc_prefix");
+const osc_streams_input = document.getElementById("osc_streams");
 
 const scene = new THREE.Scene();
 const camera = new THREE.PerspectiveCamera(
@@ -49,12 +74,158 @@ var begin_timestamp = 0;
 var end_timestamp = 0;
 
 var holistic_result = new MediapipeHolisticResult(scene, message_div);
+setup_ws_relay();
+setup_udp_relay();
+setup_filter();
+
+// 保存されている送信先ポートを読み込み、変更されたら保存する。
+var network_config = null;
+invoke("get_network_config").then((config) => {
+    network_config = config;
+    dest_port_input.value = config.dest_port;
+});
+dest_port_input.addEventListener("change", (event) => {
+    if (network_config !== null) {
+        network_config.dest_port = dest_port_input.valueAsNumber;
+        invoke("set_network_config", {config: network_config}).then();
+    }
+});
+
+// 記録時に保存したセッションの情報を表示する。サイドカーがない場合は何も表示しない。
+function show_metadata(metadata) {
+    metadata_div.innerHTML = "";
+    if (metadata === null) {
+        return;
+    }
+    let items = [
+        ["started_at", metadata.started_at],
+        ["stopped_at", metadata.stopped_at],
+        ["sources", metadata.sources.join(", ")],
+        ["frames", metadata.frames],
+        ["duration [sec]", metadata.duration_sec === null ? null : metadata.duration_sec.toFixed(3)],
+        ["phone", metadata.device_name],
+        ["app version", metadata.app_version],
+        ["variant", metadata.mediapipe_variant],
+        ["camera", metadata.camera],
+        ["operator", metadata.operator],
+        ["tags", metadata.tags === undefined ? null : metadata.tags.join(", ")],
+        ["notes", metadata.notes],
+    ];
+    let list = document.createElement("ul");
+    items.forEach(([name, value]) => {
+        if (value === null || value === undefined || value === "") {
+            return;
+        }
+        let item = document.createElement("li");
+        item.innerText = name + ": " + value;
+        list.appendChild(item);
+    });
+    metadata_div.appendChild(list);
+}
 
 // メニューからファイルを開くと、eventがとんでくる。
 // eventを受け取ったらinvokeでファイルを開く。
+// 壊れた行は読み飛ばし、その数をメッセージに表示する。
 const unlisten_open_menu = listen("open_menu", event => {
     console.log("open_menu called.");
-    invoke("open_file").then();
+    invoke("open_file", {
+        policy: "skip"
+    }).then((summary) => {
+        show_metadata(summary.metadata);
+        if (summary.streaming) {
+            frames_div.innerHTML += " (streaming from file)";
+        }
+        if (summary.skipped_lines > 0) {
+            frames_div.innerHTML +=
+                ", skipped lines: " + summary.skipped_lines;
+            summary.errors.forEach((e) => {
+                console.warn("line " + e.line + ": " + e.reason);
+            });
+        }
+    }).catch((err) => {
+        if (err.kind != "cancelled") {
+            frames_div.innerHTML = "failed to open file: " + err.kind;
+            console.error(err);
+        }
+    });
+}).then();
+
+// メニューのExportから呼ばれる。
+// 書き出したファイルの一覧を表示する。
+const unlisten_export_menu = listen("export_menu", event => {
+    console.log("export_menu called: " + event.payload.filetext);
+    let command = null;
+    let args = {};
+    switch (event.payload.filetext) {
+        case "export_csv_wide":
+            command = "export_csv";
+            args = {options: {layout: "wide"}};
+            break;
+        case "export_csv_long":
+            command = "export_csv";
+            args = {options: {layout: "long"}};
+            break;
+        case "export_bvh":
+            command = "export_bvh";
+            args = {options: {fingers: false}};
+            break;
+        case "export_bvh_fingers":
+            command = "export_bvh";
+            args = {options: {fingers: true}};
+            break;
+        case "export_gltf":
+            command = "export_gltf";
+            args = {options: {fingers: false}};
+            break;
+        case "export_gltf_fingers":
+            command = "export_gltf";
+            args = {options: {fingers: true}};
+            break;
+        // camera_paramsから求めた腰の移動も書き出す。
+        case "export_bvh_root_motion":
+            command = "export_bvh";
+            args = {options: {fingers: false, root_motion: true}};
+            break;
+        case "export_gltf_root_motion":
+            command = "export_gltf";
+            args = {options: {fingers: false, root_motion: true}};
+            break;
+        // 受信・再生と同じフィルタの設定を使う。
+        case "export_filtered":
+            command = "export_filtered";
+            break;
+        case "export_resampled_30":
+            command = "export_resampled";
+            args = {options: {fps: 30}};
+            break;
+        case "export_resampled_60":
+            command = "export_resampled";
+            args = {options: {fps: 60}};
+            break;
+        case "export_filled":
+            command = "export_filled";
+            break;
+    }
+    if (command === null) {
+        return;
+    }
+    invoke(command, args).then((result) => {
+        if (Array.isArray(result)) {
+            export_div.innerHTML = "exported: " + result.join(", ");
+        } else if (result.frame_rate === undefined) {
+            export_div.innerHTML = "exported: " + result.path +
+                " (" + result.frames + " frames)";
+        } else {
+            export_div.innerHTML = "exported: " + result.path +
+                " (" + result.frames + " frames, " +
+                result.frame_rate.toFixed(2) + " fps)";
+        }
+    }).catch((err) => {
+        if (err.kind != "cancelled") {
+            export_div.innerHTML = "failed to export: " + err.kind;
+            console.error(err);
+        }
+    });
 }).then();
 
 // ファイルが正常に開けると、eventがとんでくる。
@@ -72,6 +243,44 @@ const unlisten_total_frames = listen("total_frames", event => {
         (end_timestamp - begin_timestamp) * 1e-6 + " sec";
 }).then();
 
+// 再生速度、ループ、A-B区間の設定。
+// 再生中に変更しても再生は止まらず、次のフレームから反映される。
+var range_a = null;
+var range_b = null;
+function update_playback_options() {
+    let options = {
+        speed: speed_select.value == "max" ? 1.0 : parseFloat(speed_select.value),
+        as_fast_as_possible: speed_select.value == "max",
+        looping: looping_input.checked,
+        range: null,
+    };
+    if (range_a !== null && range_b !== null) {
+        options.range = {
+            begin: Math.min(range_a, range_b),
+            end: Math.max(range_a, range_b),
+        };
+    }
+    ab_div.innerHTML =
+        "A: " + (range_a === null ? "-" : range_a) +
+        ", B: " + (range_b === null ? "-" : range_b);
+    invoke("set_playback_options", {options: options}).then();
+}
+speed_select.addEventListener("change", update_playback_options);
+looping_input.addEventListener("change", update_playback_options);
+set_a_button.addEventListener("click", (event) => {
+    range_a = frame_slider.valueAsNumber;
+    update_playback_options();
+});
+set_b_button.addEventListener("click", (event) => {
+    range_b = frame_slider.valueAsNumber;
+    update_playback_options();
+});
+clear_ab_button.addEventListener("click", (event) => {
+    range_a = null;
+    range_b = null;
+    update_playback_options();
+});
+
 // スライダーを動かしたら、フレームを移動する。
 frame_slider.addEventListener("input", (event) => {
     if (!playing) {
@@ -87,7 +296,6 @@ next_button.addEventListener("click", (event) => {
     if (!playing) {
         invoke("step_json", {
             increment: true,
-            counterReset: counter_reset
         }).then();
         needs_update = true;
     }
@@ -97,19 +305,71 @@ prev_button.addEventListener("click", (event) => {
     if (!playing) {
         invoke("step_json", {
             increment: false,
-            counterReset: counter_reset
         }).then();
         needs_update = true;
     }
 });
 
+// 時間を指定して移動する。
+// 移動先はjson_sendのeventで表示される。
+function seek(target) {
+    if (!playing) {
+        invoke("seek", {target: target}).then((result) => {
+            seek_time_input.value = (result.elapsed * 1e-6).toFixed(3);
+        });
+        needs_update = true;
+    }
+}
+
+back_1s_button.addEventListener("click", (event) => {
+    seek({millis: -1000});
+});
+
+forward_1s_button.addEventListener("click", (event) => {
+    seek({millis: 1000});
+});
+
+seek_go_button.addEventListener("click", (event) => {
+    seek({elapsed: Math.round(seek_time_input.valueAsNumber * 1e6)});
+});
+
+// VMCプロトコルでの送信設定。チェックされていない場合はnullを渡す。
+function vmc_options() {
+    if (!vmc_enabled_input.checked) {
+        return null;
+    }
+    return {
+        host: vmc_host_input.value,
+        port: vmc_port_input.valueAsNumber,
+        fingers: vmc_fingers_input.checked,
+        blend_shapes: true,
+    };
+}
+
+// ランドマークをそのままOSCで送信する設定。チェックされていない場合はnullを渡す。
+// Streamsはカンマ区切りで指定する。
+function osc_options() {
+    if (!osc_enabled_input.checked) {
+        return null;
+    }
+    return {
+        host: osc_host_input.value,
+        port: osc_port_input.valueAsNumber,
+        prefix: osc_prefix_input.value,
+        streams: osc_streams_input.value.split(",").map((s) => s.trim()).filter((s) => s != ""),
+    };
+}
+
 // invokeしてRust側で送信スレッドを開始する。
 play_button.addEventListener("click", (event) => {
     if (!playing) {
         playing = true;
         invoke("start_json", {
             counterReset: counter_reset,
-            ipaddr: document.getElementById("ipaddr").value
+            ipaddr: document.getElementById("ipaddr").value,
+            destPort: dest_port_input.valueAsNumber,
+            vmc: vmc_options(),
+            osc: osc_options()
         }).then();
     }
 });
@@ -7,12 +7,41 @@ import {listen, emit} from "@tauri-apps/api/event";
 import {getCurrentWebviewWindow} from "@tauri-apps/api/webviewWindow";
 
 import {MediapipeHolisticResult} from "./viewer";
+import {setup_ws_relay} from "./ws_relay";
+import {setup_udp_relay} from "./udp_relay";
+import {setup_filter} from "./filter";
 const appWindow = getCurrentWebviewWindow()
 
 const play_button = document.getElementById("play_anim");
 const record_button = document.getElementById("record_anim");
 const stop_button = document.getElementById("stop_anim");
 const message_div = document.getElementById("message_div");
+const bind_addr_input = document.getElementById("bind_addr");
+const port_input = document.getElementById("port");
+const per_device_input = document.getElementById("per_device");
+const compressed_input = document.getElementById("compressed");
+const device_select = document.getElementById("device_select");
+const devices_div = document.getElementById("devices_div");
+const vmc_enabled_input = document.getElementById("vmc_enabled");
+const vmc_host_input = document.getElementById("vmc_host");
+const vmc_port_input = document.getElementById("vmc_port");
+const vmc_fingers_input = document.getElementById("vmc_fingers");
+const osc_enabled_input = document.getElementById("osc_enabled");
+const osc_host_input = document.getElementById("osc_host");
+const osc_port_input = document.getElementById("osc_port");
+const osc_prefix_input = document.getElementById("osc_prefix");
+const osc_streams_input = document.getElementById("osc_streams");
+const live_view_input = document.getElementById("live_view");
+const session_device_name_input = document.getElementById("session_device_name");
+const session_app_version_input = document.getElementById("session_app_version");
+const session_variant_input = document.getElementById("session_variant");
+const session_camera_select = document.getElementById("session_camera");
+const session_operator_input = document.getElementById("session_operator");
+const session_tags_input = document.getElementById("session_tags");
+const session_notes_input = document.getElementById("session_notes");
+const split_minutes_input = document.getElementById("split_minutes");
+const split_megabytes_input = document.getElementById("split_megabytes");
+const sync_policy_select = document.getElementById("sync_policy");
 
 
 const scene = new THREE.Scene();
@@ -36,44 +65,225 @@ const controls = new OrbitControls(
 
 var json_string = null;
 var playing = false;
+var recording = false;
 var needs_update = false;
 
 var holistic_result = new MediapipeHolisticResult(scene, message_div);
+setup_ws_relay();
+setup_udp_relay();
+setup_filter();
 
 // TODO: UDPで送信されてくる
 // eventで送信されてきた文字列をjsonにする。
 // jsonをパースして表示する。
+// 端末が選択されている場合はその端末のデータだけを表示する。
 const unlisten_upd_receive = listen("udp_receive", event => {
+    if (device_select.value != "" &&
+        device_select.value != event.payload.device_id) {
+        return;
+    }
     json_string = event.payload.filetext;
     needs_update = true;
 }).then();
 
-// UDPの受付を開始
-play_button.addEventListener("click", (event) => {
+// 受信中の端末の一覧を定期的に取得する。
+setInterval(() => {
     if (!playing) {
-        playing = true;
-        needs_update = true;
-        invoke("start_receive").then(
-            () => {}
-        );
+        return;
     }
+    invoke("list_devices").then((devices) => {
+        let selected = device_select.value;
+        device_select.innerHTML = "";
+        let option_all = document.createElement("option");
+        option_all.value = "";
+        option_all.innerText = "(all)";
+        device_select.appendChild(option_all);
+        devices.forEach((device) => {
+            let option = document.createElement("option");
+            option.value = device.device_id;
+            option.innerText = device.device_id;
+            device_select.appendChild(option);
+        });
+        device_select.value = selected;
+    });
+}, 1000);
+
+// 受信中は送信元ごとの受信の統計が1秒ごとに送られてくる。
+// 欠落フレーム数はタイムスタンプの飛びから推定したもの。
+const unlisten_udp_stats = listen("udp_stats", event => {
+    devices_div.innerHTML = event.payload.map((stats) => {
+        let text = stats.device_id + ": " + stats.fps.toFixed(1) + " fps" +
+            ", jitter " + stats.jitter_ms.toFixed(1) + " ms" +
+            ", dropped " + stats.dropped + " (" + (stats.loss_rate * 100).toFixed(1) + "%)";
+        if (stats.out_of_order > 0) {
+            text += ", out of order " + stats.out_of_order;
+        }
+        let stale = stats.streams.filter((s) => s.stale).map((s) => s.stream);
+        if (stale.length > 0) {
+            text += ", stale: " + stale.join(", ");
+        }
+        return text;
+    }).join("<br />");
+}).then();
+
+// 保存されている待ち受けアドレスを読み込む
+var network_config = null;
+invoke("get_network_config").then((config) => {
+    network_config = config;
+    bind_addr_input.value = config.bind_addr;
+    port_input.value = config.port;
 });
 
-// UDPを閉じる
+// 待ち受けアドレスを変更したら次回のデフォルトとして保存する
+function save_network_config() {
+    if (network_config !== null) {
+        network_config.bind_addr = bind_addr_input.value;
+        network_config.port = port_input.valueAsNumber;
+        invoke("set_network_config", {config: network_config}).then();
+    }
+}
+bind_addr_input.addEventListener("change", save_network_config);
+port_input.addEventListener("change", save_network_config);
+
+// bindに失敗した場合はメッセージを表示して、バックエンドの状態に戻す
+function on_receive_error(err) {
+    refresh_status();
+    if (err.kind == "addr_in_use") {
+        message_div.innerHTML = err.addr + " is already in use.";
+    } else {
+        message_div.innerText = "failed to start: " + err.addr + " (" + (err.reason || err.kind) + ")";
+    }
+    console.error(err);
+}
+
+// VMCプロトコルでの送信設定。チェックされていない場合はnullを渡す。
+// 端末を選択している場合はその端末のフレームだけを送る。
+function vmc_options() {
+    if (!vmc_enabled_input.checked) {
+        return null;
+    }
+    return {
+        host: vmc_host_input.value,
+        port: vmc_port_input.valueAsNumber,
+        fingers: vmc_fingers_input.checked,
+        blend_shapes: true,
+        device_id: device_select.value == "" ? null : device_select.value,
+    };
+}
+
+// ランドマークをそのままOSCで送信する設定。チェックされていない場合はnullを渡す。
+// Streamsはカンマ区切りで指定する。
+function osc_options() {
+    if (!osc_enabled_input.checked) {
+        return null;
+    }
+    return {
+        host: osc_host_input.value,
+        port: osc_port_input.valueAsNumber,
+        prefix: osc_prefix_input.value,
+        streams: osc_streams_input.value.split(",").map((s) => s.trim()).filter((s) => s != ""),
+        device_id: device_select.value == "" ? null : device_select.value,
+    };
+}
+
+// 記録ファイルのサイドカーに保存するセッションの情報。空欄の項目はnullにする。
+function session_info() {
+    let value_or_null = (value) => value.trim() == "" ? null : value.trim();
+    return {
+        device_name: value_or_null(session_device_name_input.value),
+        app_version: value_or_null(session_app_version_input.value),
+        mediapipe_variant: value_or_null(session_variant_input.value),
+        camera: value_or_null(session_camera_select.value),
+        operator: value_or_null(session_operator_input.value),
+        tags: session_tags_input.value.split(",").map((s) => s.trim()).filter((s) => s != ""),
+        notes: value_or_null(session_notes_input.value),
+    };
+}
+
+// 記録ファイルを分割する条件。空欄の項目は分割しない。
+function rotation_options() {
+    let number_or_null = (input) => input.value == "" ? null : input.valueAsNumber;
+    return {
+        max_minutes: number_or_null(split_minutes_input),
+        max_megabytes: number_or_null(split_megabytes_input),
+    };
+}
+
+// 受信中・記録中かどうかをボタンに反映する
+function update_buttons() {
+    record_button.innerText = recording ? "Stop Recording" : "Record";
+}
+
+// バックエンドの受信・記録の状態を反映する
+function refresh_status() {
+    invoke("get_receive_status").then((status) => {
+        playing = status.addr !== null;
+        recording = status.recording;
+        live_view_input.checked = !playing || status.view;
+        update_buttons();
+    });
+}
+// 別のウィンドウで受信・記録を開始していた場合は、その状態から始める
+refresh_status();
+
+// UDPの受付を開始
+// 記録中でも、表示と送信の設定だけを切り替えられる。
+play_button.addEventListener("click", (event) => {
+    playing = true;
+    needs_update = true;
+    live_view_input.checked = true;
+    invoke("start_receive", {
+        bindAddr: bind_addr_input.value,
+        port: port_input.valueAsNumber,
+        vmc: vmc_options(),
+        osc: osc_options()
+    }).then(
+        () => {}
+    ).catch(on_receive_error);
+});
+
+// UDPを閉じる。記録中の場合は記録も停止する。
 stop_button.addEventListener("click", (event) => {
     playing = false;
-    emit("udp_stop", {}).then();
+    recording = false;
+    update_buttons();
+    invoke("stop_receive").then();
 });
 
-// 受け取ったパケットをファイルに書き込む
+// 受信を止めずに表示だけを切り替える
+live_view_input.addEventListener("change", (event) => {
+    if (playing) {
+        invoke("set_live_view", {enabled: live_view_input.checked}).then();
+    }
+});
+
+// 受け取ったパケットのファイルへの書き込みを開始・停止する。
+// 受信と表示はそのまま続くので、切り替えの前後でパケットを取りこぼさない。
 record_button.addEventListener("click", (event) => {
-    if (!playing) {
-        playing = true;
-        needs_update = true;
-        invoke("start_record").then(
-            () => {}
-        );
+    if (recording) {
+        recording = false;
+        update_buttons();
+        invoke("stop_record").then();
+        return;
     }
+    invoke("start_record", {
+        bindAddr: bind_addr_input.value,
+        port: port_input.valueAsNumber,
+        deviceMode: per_device_input.checked ? "per_device" : "merged",
+        format: compressed_input.checked ? "compressed" : "jsonl",
+        info: session_info(),
+        rotation: rotation_options(),
+        sync: sync_policy_select.value,
+        vmc: vmc_options(),
+        osc: osc_options()
+    }).then((started) => {
+        if (started) {
+            playing = true;
+            recording = true;
+            needs_update = true;
+            update_buttons();
+        }
+    }).catch(on_receive_error);
 });
 
 var prev_time = 0
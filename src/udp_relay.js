@@ -0,0 +1,52 @@
+import {invoke} from "@tauri-apps/api/core";
+
+// UDPでの転送先の追加・削除と一覧の表示。
+// 転送先は受信・再生の途中でも追加・削除でき、すぐに反映される。
+export function setup_udp_relay() {
+    const relay_host_input = document.getElementById("relay_host");
+    const relay_port_input = document.getElementById("relay_port");
+    const relay_streams_input = document.getElementById("relay_streams");
+    const relay_add_button = document.getElementById("relay_add");
+    const relay_targets_div = document.getElementById("relay_targets_div");
+
+    function show_targets(targets) {
+        relay_targets_div.innerHTML = "";
+        targets.forEach((target) => {
+            let item = document.createElement("div");
+            let streams = target.streams === null ? "all" : target.streams.join(",");
+            item.innerText = target.host + ":" + target.port + " (" + streams + ") ";
+            let remove_button = document.createElement("button");
+            remove_button.innerText = "Remove";
+            remove_button.addEventListener("click", (event) => {
+                invoke("remove_relay_target", {
+                    host: target.host,
+                    port: target.port
+                }).then(show_targets);
+            });
+            item.appendChild(remove_button);
+            relay_targets_div.appendChild(item);
+        });
+    }
+
+    // Streamsはカンマ区切りで指定する。空の場合はすべてのストリームを転送する。
+    relay_add_button.addEventListener("click", (event) => {
+        let streams = relay_streams_input.value.split(",").map((s) => s.trim()).filter((s) => s != "");
+        invoke("add_relay_target", {
+            target: {
+                host: relay_host_input.value,
+                port: relay_port_input.valueAsNumber,
+                streams: streams.length == 0 ? null : streams,
+            }
+        }).then(show_targets).catch((err) => {
+            if (err.kind == "unknown_stream") {
+                relay_targets_div.innerText = "unknown stream: " + err.stream;
+            } else {
+                relay_targets_div.innerText = "failed to add: " + err.addr + " (" + err.kind + ")";
+            }
+            console.error(err);
+        });
+    });
+
+    // 別のウィンドウで追加した転送先も表示する。
+    invoke("list_relay_targets").then(show_targets);
+}
@@ -194,6 +194,25 @@ export function MediapipeHolisticResult(scene, message_div=null) {
                     p_not_found.innerText = "not found";
                     this.message_div.appendChild(p_not_found);
                 }
+
+                // camera_paramsから推定したカメラ座標系での腰の位置[m]
+                let title_root = document.createElement("h2");
+                title_root.innerText = "root_translation";
+                this.message_div.appendChild(title_root);
+                if ("root_translation" in msg) {
+                    let msg_root = msg["root_translation"];
+                    let p_root = document.createElement("p");
+                    p_root.innerText =
+                    "[" +
+                        msg_root[0].toFixed(3) + ", " +
+                        msg_root[1].toFixed(3) + ", " +
+                        msg_root[2].toFixed(3) + "] m";
+                    this.message_div.appendChild(p_root);
+                } else {
+                    let p_not_found = document.createElement("p");
+                    p_not_found.innerText = "not found";
+                    this.message_div.appendChild(p_not_found);
+                }
             }
 
 
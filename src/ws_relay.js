@@ -0,0 +1,63 @@
+import {invoke} from "@tauri-apps/api/core";
+
+// WebSocketでの配信の開始・停止と状態の表示。
+// 受信・再生とは独立していて、チェックを入れている間は受信・再生したフレームを配信する。
+// 受信・再生の途中で開始した場合は、次に受信・再生を開始したときから配信される。
+export function setup_ws_relay() {
+    const ws_enabled_input = document.getElementById("ws_enabled");
+    const ws_bind_addr_input = document.getElementById("ws_bind_addr");
+    const ws_port_input = document.getElementById("ws_port");
+    const ws_status_span = document.getElementById("ws_status");
+
+    function show_status(status) {
+        if (status === null) {
+            ws_enabled_input.checked = false;
+            ws_status_span.innerText = "";
+        } else {
+            ws_enabled_input.checked = true;
+            ws_status_span.innerText = status.addr + " (" + status.clients + " clients)";
+        }
+    }
+
+    function start() {
+        invoke("start_ws_relay", {
+            options: {
+                bind_addr: ws_bind_addr_input.value,
+                port: ws_port_input.valueAsNumber,
+            }
+        }).then(show_status).catch((err) => {
+            show_status(null);
+            if (err.kind == "addr_in_use") {
+                ws_status_span.innerText = err.addr + " is already in use.";
+            } else {
+                ws_status_span.innerText = "failed to start: " + err.addr + " (" + err.kind + ")";
+            }
+            console.error(err);
+        });
+    }
+
+    ws_enabled_input.addEventListener("change", (event) => {
+        if (ws_enabled_input.checked) {
+            start();
+        } else {
+            invoke("stop_ws_relay").then(() => show_status(null));
+        }
+    });
+
+    // 配信中にアドレスを変更した場合は開き直す。
+    function restart() {
+        if (ws_enabled_input.checked) {
+            start();
+        }
+    }
+    ws_bind_addr_input.addEventListener("change", restart);
+    ws_port_input.addEventListener("change", restart);
+
+    // 別のウィンドウで開始した場合も含めて、状態を定期的に取得する。
+    invoke("get_ws_relay_status").then(show_status);
+    setInterval(() => {
+        if (ws_enabled_input.checked) {
+            invoke("get_ws_relay_status").then(show_status);
+        }
+    }, 1000);
+}